@@ -0,0 +1,65 @@
+//! Runs a flag-heavy arithmetic loop and reports the instruction throughput with
+//! lazy flags and with the flags written back after every instruction, as an
+//! eager implementation would.
+//!
+//!     cargo bench --bench flags
+
+use std::time::{Duration, Instant};
+
+use x86emu::function::*;
+use x86emu::*;
+
+const ORIGIN: u32 = 0x7c00;
+const ITERATIONS: u32 = 5_000_000;
+const RUNS: usize = 5;
+
+fn workload() -> Vec<u8> {
+    // push 0; mov ecx, ITERATIONS
+    let mut code = vec![0x6a, 0x00, 0xb9];
+    code.extend_from_slice(&ITERATIONS.to_le_bytes());
+    // loop: add eax, ebx; sub edx, eax; xor esi, eax; adc edi, 1; inc ebx; dec ecx;
+    // jnz loop; ret
+    code.extend_from_slice(&[
+        0x01, 0xd8, 0x29, 0xc2, 0x31, 0xc6, 0x83, 0xd7, 0x01, 0x43, 0x49, 0x75, 0xf3, 0xc3,
+    ]);
+    code
+}
+
+/// Runs `code` to completion and returns the time taken and instruction count.
+fn measure(code: &[u8], eager: bool) -> (Duration, u64) {
+    let mut emu = Emulator::new(0x10000, ORIGIN, 0x7c00);
+    emu.load(ORIGIN as usize, code).unwrap();
+
+    let start = Instant::now();
+    while emu.step().unwrap() {
+        if eager {
+            materialize_flags(&mut emu);
+        }
+    }
+    (start.elapsed(), emu.tsc)
+}
+
+fn main() {
+    let code = workload();
+
+    // Interleave the modes and report the best of several runs of each to keep
+    // scheduling noise out.
+    let mut best = [Duration::MAX; 2];
+    let mut instructions = 0;
+    for _ in 0..RUNS {
+        for (mode, &eager) in [true, false].iter().enumerate() {
+            let (elapsed, count) = measure(&code, eager);
+            best[mode] = best[mode].min(elapsed);
+            instructions = count;
+        }
+    }
+
+    let mips = |elapsed: Duration| instructions as f64 / elapsed.as_secs_f64() / 1e6;
+    println!("{} instructions per run", instructions);
+    println!("eager: {:.3?} ({:.1} MIPS)", best[0], mips(best[0]));
+    println!("lazy:  {:.3?} ({:.1} MIPS)", best[1], mips(best[1]));
+    println!(
+        "speedup: {:.2}x",
+        best[0].as_secs_f64() / best[1].as_secs_f64()
+    );
+}
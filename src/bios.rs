@@ -1,13 +1,34 @@
 use crate::emulator::*;
+use crate::error::*;
 use crate::function::*;
-use crate::io::*;
 use crate::*;
 
+/// Segment of the BIOS ROM holding the service stubs.
+pub const BIOS_SEGMENT: u16 = 0xf000;
+
+/// Interrupt vectors served by the BIOS, with their host implementations.
+const BIOS_SERVICES: [(u8, Hook); 1] = [(0x10, bios_video)];
+
+/// Default IRQ handlers in the BIOS ROM, as offsets into the segment with their
+/// code, for the master's vectors 0x08 to 0x0F and the slave's 0x70 to 0x77. They
+/// only acknowledge the interrupt.
+const IRQ_HANDLERS: [(u8, u16, &[u8]); 2] = [
+    // push ax; mov al, 0x20; out 0x20, al; pop ax; iret
+    (0x08, 0xe000, &[0x50, 0xb0, 0x20, 0xe6, 0x20, 0x58, 0xcf]),
+    // push ax; mov al, 0x20; out 0xa0, al; out 0x20, al; pop ax; iret
+    (
+        0x70,
+        0xe010,
+        &[0x50, 0xb0, 0x20, 0xe6, 0xa0, 0xe6, 0x20, 0x58, 0xcf],
+    ),
+];
+
 const BIOS_TO_TERMINAL: [usize; 8] = [30, 34, 32, 36, 31, 35, 33, 37];
 
-pub fn put_string(s: String) {
+/// Writes `s` to the device on COM1's data port.
+pub fn put_string(emu: &mut Emulator, s: String) {
     for c in s.chars() {
-        io_out8(0x03f8, c as u8);
+        emu.io.write(0x03f8, 8, c as u32);
     }
 }
 
@@ -17,16 +38,51 @@ pub fn bios_video_teletype(emu: &mut Emulator) {
 
     let term_color = BIOS_TO_TERMINAL[color as usize & 0x07];
     let bright = if (color & 0x08) != 0 { 1 } else { 0 };
-    put_string(format!(
-        "\x1b[{};{}m{}\x1b[0m",
-        bright, term_color, ch as char
-    ));
+    put_string(
+        emu,
+        format!("\x1b[{};{}m{}\x1b[0m", bright, term_color, ch as char),
+    );
 }
 
-pub fn bios_video(emu: &mut Emulator) {
+pub fn bios_video(emu: &mut Emulator) -> EmuResult {
     let func = get_register8(emu, AH);
     match func {
-        0x0e => bios_video_teletype(emu),
-        _ => println!("not implemented BIOS video function: {}", func),
+        0x0e => {
+            bios_video_teletype(emu);
+            Ok(())
+        }
+        _ => Err(EmuError::UnimplementedBiosFunction {
+            eip: emu.start_eip,
+            vector: 0x10,
+            function: func,
+        }),
+    }
+}
+
+/// Installs the BIOS services: the 64KB BIOS ROM is mapped over the BIOS segment,
+/// each vector in the IVT points at an IRET stub in it, and a host hook on the stub
+/// performs the service. The hardware interrupt vectors point at handlers that send
+/// the PIC an end of interrupt.
+pub fn install_bios(emu: &mut Emulator) -> EmuResult {
+    let base = (BIOS_SEGMENT as u32) << 4;
+    let mut rom = vec![0xff; 0x10000];
+    for &(vector, service) in BIOS_SERVICES.iter() {
+        let offset = vector as u32;
+        rom[offset as usize] = 0xcf;
+        set_memory32(emu, vector as u32 * 4, (BIOS_SEGMENT as u32) << 16 | offset)?;
+        emu.hooks.insert(base + offset, service);
+    }
+    for &(first, offset, code) in IRQ_HANDLERS.iter() {
+        let start = offset as usize;
+        rom[start..start + code.len()].copy_from_slice(code);
+        for vector in first..first + 8 {
+            set_memory32(
+                emu,
+                vector as u32 * 4,
+                (BIOS_SEGMENT as u32) << 16 | offset as u32,
+            )?;
+        }
     }
+    emu.memory.add_rom(base, rom);
+    Ok(())
 }
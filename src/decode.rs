@@ -0,0 +1,61 @@
+use crate::emulator::*;
+use crate::error::*;
+use crate::function::*;
+use crate::*;
+
+/// A repeat prefix. `Rep` (0xF3) doubles as REPE/REPZ for CMPS and SCAS.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RepPrefix {
+    Rep,
+    Repne,
+}
+
+/// Per-instruction state accumulated from the prefixes in front of the opcode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DecodeState {
+    pub operand_size: u32,
+    pub address_size: u32,
+    /// Segment register selected by a segment override prefix.
+    pub segment: Option<usize>,
+    pub lock: bool,
+    pub rep: Option<RepPrefix>,
+}
+
+impl DecodeState {
+    /// The state for an instruction without prefixes, given the D bit of CS.
+    pub fn new(big: bool) -> DecodeState {
+        let size = if big { 32 } else { 16 };
+        DecodeState {
+            operand_size: size,
+            address_size: size,
+            segment: None,
+            lock: false,
+            rep: None,
+        }
+    }
+}
+
+/// Consumes any prefix bytes at EIP, recording their effect in `emu.decode`. EIP is
+/// left pointing at the opcode.
+pub fn parse_prefixes(emu: &mut Emulator) -> EmuResult {
+    let big = emu.sregs[CS].big;
+    emu.decode = DecodeState::new(big);
+
+    loop {
+        match get_code8(emu, 0)? {
+            0x66 => emu.decode.operand_size = if big { 16 } else { 32 },
+            0x67 => emu.decode.address_size = if big { 16 } else { 32 },
+            0x26 => emu.decode.segment = Some(ES),
+            0x2E => emu.decode.segment = Some(CS),
+            0x36 => emu.decode.segment = Some(SS),
+            0x3E => emu.decode.segment = Some(DS),
+            0x64 => emu.decode.segment = Some(FS),
+            0x65 => emu.decode.segment = Some(GS),
+            0xF0 => emu.decode.lock = true,
+            0xF2 => emu.decode.rep = Some(RepPrefix::Repne),
+            0xF3 => emu.decode.rep = Some(RepPrefix::Rep),
+            _ => return Ok(()),
+        }
+        advance_eip(emu, 1);
+    }
+}
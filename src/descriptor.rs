@@ -0,0 +1,172 @@
+use crate::emulator::*;
+use crate::error::*;
+use crate::function::*;
+
+pub const ACCESS_ACCESSED: u8 = 1;
+pub const ACCESS_PRESENT: u8 = 1 << 7;
+/// Access byte of a present, writable, accessed data segment.
+pub const ACCESS_DATA: u8 = 0x93;
+
+// System descriptor and gate types.
+pub const TSS16_AVAILABLE: u8 = 0x01;
+pub const TSS16_BUSY: u8 = 0x03;
+pub const CALL_GATE16: u8 = 0x04;
+pub const INTERRUPT_GATE16: u8 = 0x06;
+pub const TRAP_GATE16: u8 = 0x07;
+pub const TSS32_AVAILABLE: u8 = 0x09;
+pub const TSS32_BUSY: u8 = 0x0b;
+pub const CALL_GATE32: u8 = 0x0c;
+pub const INTERRUPT_GATE32: u8 = 0x0e;
+pub const TRAP_GATE32: u8 = 0x0f;
+
+/// A segment descriptor from the GDT.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Descriptor {
+    pub base: u32,
+    /// The limit in bytes, already scaled by the granularity bit.
+    pub limit: u32,
+    /// P, DPL, S and type.
+    pub access: u8,
+    /// The D/B bit.
+    pub big: bool,
+}
+
+impl Descriptor {
+    pub fn from_raw(low: u32, high: u32) -> Descriptor {
+        let base = (low >> 16) | (high & 0xff) << 16 | (high & 0xff000000);
+        let limit = (low & 0xffff) | (high & 0x000f0000);
+        let limit = if high & (1 << 23) != 0 {
+            limit << 12 | 0xfff
+        } else {
+            limit
+        };
+        Descriptor {
+            base,
+            limit,
+            access: (high >> 8) as u8,
+            big: high & (1 << 22) != 0,
+        }
+    }
+
+    pub fn is_present(&self) -> bool {
+        self.access & ACCESS_PRESENT != 0
+    }
+
+    pub fn dpl(&self) -> u8 {
+        (self.access >> 5) & 0x03
+    }
+
+    /// Code or data segment, as opposed to a system descriptor.
+    pub fn is_segment(&self) -> bool {
+        self.access & 0x10 != 0
+    }
+
+    pub fn is_code(&self) -> bool {
+        self.is_segment() && self.access & 0x08 != 0
+    }
+
+    pub fn is_conforming(&self) -> bool {
+        self.is_code() && self.access & 0x04 != 0
+    }
+
+    /// The type of a system descriptor, such as a TSS or a gate.
+    pub fn system_type(&self) -> u8 {
+        self.access & 0x0f
+    }
+
+    pub fn is_writable(&self) -> bool {
+        self.is_segment() && !self.is_code() && self.access & 0x02 != 0
+    }
+
+    pub fn is_readable(&self) -> bool {
+        self.is_segment() && (!self.is_code() || self.access & 0x02 != 0)
+    }
+}
+
+/// A call, interrupt or trap gate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Gate {
+    pub selector: u16,
+    pub offset: u32,
+    /// P, DPL and type.
+    pub access: u8,
+    /// Number of stack parameters a call gate copies to the inner stack.
+    pub param_count: u8,
+}
+
+impl Gate {
+    pub fn from_raw(low: u32, high: u32) -> Gate {
+        Gate {
+            selector: (low >> 16) as u16,
+            offset: (high & 0xffff0000) | (low & 0xffff),
+            access: (high >> 8) as u8,
+            param_count: (high & 0x1f) as u8,
+        }
+    }
+
+    pub fn is_present(&self) -> bool {
+        self.access & ACCESS_PRESENT != 0
+    }
+
+    pub fn dpl(&self) -> u8 {
+        (self.access >> 5) & 0x03
+    }
+
+    pub fn gate_type(&self) -> u8 {
+        self.access & 0x1f
+    }
+
+    /// Operand size of the frame the gate pushes.
+    pub fn size(&self) -> u32 {
+        if self.access & 0x08 != 0 {
+            32
+        } else {
+            16
+        }
+    }
+}
+
+/// Reads the raw GDT entry for `selector`, raising #GP(selector) if it lies outside
+/// the table. Local descriptor tables are not supported.
+pub fn read_raw_descriptor(emu: &mut Emulator, selector: u16) -> EmuResult<(u32, u32)> {
+    let error_code = (selector & 0xfffc) as u32;
+    let offset = (selector & 0xfff8) as u32;
+    if selector & 0x04 != 0 || offset + 7 > emu.gdtr.limit as u32 {
+        return Err(exception_with_code(emu, GENERAL_PROTECTION, error_code));
+    }
+    let address = emu.gdtr.base.wrapping_add(offset);
+    let low = get_system_memory32(emu, address)?;
+    let high = get_system_memory32(emu, address.wrapping_add(4))?;
+    Ok((low, high))
+}
+
+pub fn read_descriptor(emu: &mut Emulator, selector: u16) -> EmuResult<Descriptor> {
+    let (low, high) = read_raw_descriptor(emu, selector)?;
+    Ok(Descriptor::from_raw(low, high))
+}
+
+/// Reads the code segment a gate points to, which must be present and at a
+/// privilege level no less privileged than the CPL.
+pub fn read_gate_target(emu: &mut Emulator, selector: u16) -> EmuResult<Descriptor> {
+    if selector & 0xfffc == 0 {
+        return Err(exception_with_code(emu, GENERAL_PROTECTION, 0));
+    }
+    let error_code = (selector & 0xfffc) as u32;
+    let descriptor = read_descriptor(emu, selector)?;
+    if !descriptor.is_code() || descriptor.dpl() > current_privilege_level(emu) {
+        return Err(exception_with_code(emu, GENERAL_PROTECTION, error_code));
+    }
+    if !descriptor.is_present() {
+        return Err(exception_with_code(emu, SEGMENT_NOT_PRESENT, error_code));
+    }
+    Ok(descriptor)
+}
+
+/// Sets the accessed bit of the GDT descriptor for `selector`.
+pub fn mark_accessed(emu: &mut Emulator, selector: u16, descriptor: &Descriptor) -> EmuResult {
+    if descriptor.access & ACCESS_ACCESSED != 0 {
+        return Ok(());
+    }
+    let address = emu.gdtr.base.wrapping_add((selector & 0xfff8) as u32 + 5);
+    set_system_memory8(emu, address, (descriptor.access | ACCESS_ACCESSED) as u32)
+}
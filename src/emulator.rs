@@ -1,8 +1,250 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::decode::*;
+use crate::descriptor::*;
+use crate::error::*;
+use crate::flags::*;
+use crate::function::*;
+use crate::instruction::*;
+use crate::interrupt::*;
+use crate::io::*;
+use crate::irq::*;
+use crate::memory::*;
+use crate::pic::*;
+use crate::pit::*;
 use crate::*;
 
+/// Number of instructions between device updates. Must be a power of two.
+pub const DEVICE_UPDATE_INTERVAL: u64 = 256;
+
+/// A segment register together with its cached descriptor.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SegmentRegister {
+    pub selector: u16,
+    pub base: u32,
+    pub limit: u32,
+    /// The D/B bit: 32-bit default operand and address size for CS, 32-bit stack
+    /// pointer for SS.
+    pub big: bool,
+    /// The access byte of the cached descriptor. A segment loaded with a null
+    /// selector in protected mode is not present.
+    pub access: u8,
+}
+
+impl SegmentRegister {
+    pub fn flat() -> SegmentRegister {
+        SegmentRegister {
+            selector: 0,
+            base: 0,
+            limit: 0xffffffff,
+            big: true,
+            access: ACCESS_DATA,
+        }
+    }
+
+    pub fn real_mode(selector: u16) -> SegmentRegister {
+        SegmentRegister {
+            selector,
+            base: (selector as u32) << 4,
+            limit: 0xffff,
+            big: false,
+            access: ACCESS_DATA,
+        }
+    }
+}
+
+/// A descriptor table register (GDTR or IDTR).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DescriptorTable {
+    pub base: u32,
+    pub limit: u16,
+}
+
+/// A host function run whenever execution reaches a given linear address, before
+/// the instruction there is executed.
+pub type Hook = fn(&mut Emulator) -> EmuResult;
+
 pub struct Emulator {
     pub registers: [u32; REGISTERS_COUNT],
+    pub sregs: [SegmentRegister; SEGMENT_REGISTERS_COUNT],
+    /// CF, PF, AF, ZF, SF and OF are stale while `lazy_flags` holds a pending
+    /// operation; use `get_eflags` or the `is_*` helpers to read them.
     pub eflags: u32,
-    pub memory: Vec<u8>,
-    pub eip: usize,
+    pub lazy_flags: LazyFlags,
+    /// Current privilege level, set whenever CS is loaded in protected mode.
+    pub cpl: u8,
+    /// Control registers CR0 to CR4.
+    pub cr: [u32; 5],
+    pub gdtr: DescriptorTable,
+    /// The interrupt descriptor table, or the IVT in real mode.
+    pub idtr: DescriptorTable,
+    /// The task register, loaded by LTR. Only the stack pointers for inner
+    /// privilege levels are read from the TSS.
+    pub tr: SegmentRegister,
+    /// Cached page translations, flushed when CR0, CR3 or CR4 is written.
+    pub tlb: Tlb,
+    pub memory: MemoryBus,
+    /// Devices attached to the I/O ports.
+    pub io: IoBus,
+    /// Interrupt request lines driven by the devices.
+    pub irq_lines: IrqLines,
+    /// The interrupt controllers, which sample `irq_lines`. They are also attached
+    /// to the I/O bus at 0x20 and 0xA0.
+    pub pic: Rc<RefCell<Pic>>,
+    /// The interval timer on IRQ0, attached to the I/O bus at 0x40 and 0x61.
+    pub pit: Rc<RefCell<Pit>>,
+    /// Set by STI, MOV SS and POP SS to hold off interrupts until after the next
+    /// instruction.
+    pub interrupt_shadow: bool,
+    pub eip: u32,
+    /// Address of the instruction currently being executed.
+    pub start_eip: u32,
+    /// Prefix state of the instruction currently being executed.
+    pub decode: DecodeState,
+    pub instructions: Insts,
+    /// Handlers for the two-byte opcode map, indexed by the byte after 0x0F.
+    pub instructions0f: Insts,
+    /// Time-stamp counter, incremented once per executed instruction.
+    pub tsc: u64,
+    /// Set by HLT and cleared when an interrupt is taken.
+    pub halted: bool,
+    /// Host hooks keyed by linear address, used for the BIOS services.
+    pub hooks: HashMap<u32, Hook>,
+}
+
+impl Emulator {
+    /// Creates an emulator with `size` bytes of zeroed memory, starting at `eip` with
+    /// the stack pointer set to `esp`. All segments are flat 32-bit segments.
+    pub fn new(size: usize, eip: u32, esp: u32) -> Emulator {
+        let mut registers = [0; REGISTERS_COUNT];
+        registers[ESP] = esp;
+
+        let mut instructions: Insts = [undefined; 256];
+        init_instructions(&mut instructions);
+        let mut instructions0f: Insts = [undefined; 256];
+        init_instructions0f(&mut instructions0f);
+
+        let irq_lines = IrqLines::new();
+        let pic = Rc::new(RefCell::new(Pic::new(irq_lines.clone())));
+        let mut io = IoBus::new();
+        io.register(0x20, 2, Box::new(pic.clone()));
+        io.register(0xa0, 2, Box::new(pic.clone()));
+        let pit = Rc::new(RefCell::new(Pit::new(irq_lines.line(0))));
+        io.register(0x40, 4, Box::new(pit.clone()));
+        io.register(0x61, 1, Box::new(pit.clone()));
+
+        Emulator {
+            registers,
+            sregs: [SegmentRegister::flat(); SEGMENT_REGISTERS_COUNT],
+            eflags: RESERVED_FLAG,
+            lazy_flags: LazyFlags::new(),
+            cpl: 0,
+            cr: [0; 5],
+            gdtr: DescriptorTable::default(),
+            idtr: DescriptorTable {
+                base: 0,
+                limit: 0x3ff,
+            },
+            tr: SegmentRegister::default(),
+            tlb: Tlb::new(),
+            memory: MemoryBus::new(size),
+            io,
+            irq_lines,
+            pic,
+            pit,
+            interrupt_shadow: false,
+            eip,
+            start_eip: eip,
+            decode: DecodeState::new(true),
+            instructions,
+            instructions0f,
+            tsc: 0,
+            halted: false,
+            hooks: HashMap::new(),
+        }
+    }
+
+    /// Creates an emulator in 16-bit real mode as after a BIOS boot, with all segment
+    /// registers zero, IP set to `ip` and SP set to `sp`.
+    pub fn new_real_mode(size: usize, ip: u16, sp: u16) -> Emulator {
+        let mut emu = Emulator::new(size, ip as u32, sp as u32);
+        emu.sregs = [SegmentRegister::real_mode(0); SEGMENT_REGISTERS_COUNT];
+        emu.decode = DecodeState::new(false);
+        emu
+    }
+
+    /// Copies `binary` into the base RAM starting at `address`.
+    pub fn load(&mut self, address: usize, binary: &[u8]) -> EmuResult {
+        let end = address + binary.len();
+        if end > self.memory.len() {
+            return Err(memory_fault(self, address.max(self.memory.len()) as u32));
+        }
+        self.memory.ram_mut()[address..end].copy_from_slice(binary);
+        Ok(())
+    }
+
+    /// Executes a single instruction, or takes a pending interrupt instead. Returns
+    /// `Ok(false)` once the program has finished by returning to linear address 0 or
+    /// halting with interrupts disabled. Guest exceptions are delivered to their
+    /// handlers; on any other error EIP and ESP are rewound to the faulting
+    /// instruction.
+    pub fn step(&mut self) -> EmuResult<bool> {
+        let shadow = std::mem::replace(&mut self.interrupt_shadow, false);
+        if !shadow && external_interrupt(self)? {
+            self.halted = false;
+            return Ok(true);
+        }
+        if self.halted {
+            if !is_interrupt(self) {
+                return Ok(false);
+            }
+            // Skip ahead to the next device update, which may raise an interrupt.
+            self.tsc = (self.tsc | (DEVICE_UPDATE_INTERVAL - 1)).wrapping_add(1);
+            self.io.update(self.tsc);
+            return Ok(true);
+        }
+        self.start_eip = self.eip;
+        let esp = self.registers[ESP];
+        let (ss, cpl) = (self.sregs[SS], self.cpl);
+        let result = self
+            .run_hook()
+            .and_then(|_| parse_prefixes(self))
+            .and_then(|_| get_code8(self, 0))
+            .and_then(|code| self.instructions[code as usize](self));
+        if let Err(err) = result {
+            self.eip = self.start_eip;
+            self.registers[ESP] = esp;
+            self.sregs[SS] = ss;
+            self.cpl = cpl;
+            deliver_exception(self, err)?;
+        }
+        self.tsc = self.tsc.wrapping_add(1);
+        if self.tsc & (DEVICE_UPDATE_INTERVAL - 1) == 0 {
+            self.io.update(self.tsc);
+        }
+        if self.halted && !is_interrupt(self) {
+            return Ok(false);
+        }
+        Ok(get_segment_base(self, CS).wrapping_add(self.eip) != 0x00)
+    }
+
+    fn run_hook(&mut self) -> EmuResult {
+        if self.hooks.is_empty() {
+            return Ok(());
+        }
+        let address = get_segment_base(self, CS).wrapping_add(self.eip);
+        match self.hooks.get(&address) {
+            Some(hook) => hook(self),
+            None => Ok(()),
+        }
+    }
+
+    /// Executes instructions until the program finishes, runs off the end of memory or
+    /// raises an error.
+    pub fn run(&mut self) -> EmuResult {
+        while (self.eip as usize) < self.memory.len() && self.step()? {}
+        Ok(())
+    }
 }
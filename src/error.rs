@@ -0,0 +1,117 @@
+use std::error::Error;
+use std::fmt;
+
+pub type EmuResult<T = ()> = Result<T, EmuError>;
+
+/// A guest fault or emulator limitation that stopped execution. `eip` is the
+/// address of the instruction that raised it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EmuError {
+    UnimplementedOpcode {
+        eip: u32,
+        bytes: Vec<u8>,
+    },
+    UnsupportedModRM {
+        eip: u32,
+        modrm: u8,
+    },
+    MemoryFault {
+        eip: u32,
+        address: u32,
+    },
+    UnknownInterrupt {
+        eip: u32,
+        vector: u8,
+    },
+    UnimplementedBiosFunction {
+        eip: u32,
+        vector: u8,
+        function: u8,
+    },
+    /// A CPU exception raised by the guest program. `step` delivers it through the
+    /// IVT or IDT and only returns it when no handler is installed.
+    Exception {
+        eip: u32,
+        vector: u8,
+        error_code: Option<u32>,
+    },
+    /// An exception was raised while delivering a double fault.
+    TripleFault {
+        eip: u32,
+    },
+}
+
+/// Exception vectors.
+pub const DIVIDE_ERROR: u8 = 0;
+pub const DEBUG: u8 = 1;
+pub const BREAKPOINT: u8 = 3;
+pub const OVERFLOW: u8 = 4;
+pub const INVALID_OPCODE: u8 = 6;
+pub const DOUBLE_FAULT: u8 = 8;
+pub const INVALID_TSS: u8 = 10;
+pub const SEGMENT_NOT_PRESENT: u8 = 11;
+pub const STACK_FAULT: u8 = 12;
+pub const GENERAL_PROTECTION: u8 = 13;
+pub const PAGE_FAULT: u8 = 14;
+
+impl EmuError {
+    pub fn eip(&self) -> u32 {
+        match *self {
+            EmuError::UnimplementedOpcode { eip, .. } => eip,
+            EmuError::UnsupportedModRM { eip, .. } => eip,
+            EmuError::MemoryFault { eip, .. } => eip,
+            EmuError::UnknownInterrupt { eip, .. } => eip,
+            EmuError::UnimplementedBiosFunction { eip, .. } => eip,
+            EmuError::Exception { eip, .. } => eip,
+            EmuError::TripleFault { eip } => eip,
+        }
+    }
+}
+
+impl fmt::Display for EmuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EmuError::UnimplementedOpcode { eip, bytes } => {
+                let bytes: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+                write!(f, "not implemented: {} (EIP = {:x})", bytes.join(" "), eip)
+            }
+            EmuError::UnsupportedModRM { eip, modrm } => write!(
+                f,
+                "not implemented ModRM mod = {}, rm = {} (EIP = {:x})",
+                modrm >> 6,
+                modrm & 0x07,
+                eip
+            ),
+            EmuError::MemoryFault { eip, address } => {
+                write!(f, "memory fault at {:x} (EIP = {:x})", address, eip)
+            }
+            EmuError::UnknownInterrupt { eip, vector } => {
+                write!(f, "unknown interrupt: {:x} (EIP = {:x})", vector, eip)
+            }
+            EmuError::UnimplementedBiosFunction {
+                eip,
+                vector,
+                function,
+            } => write!(
+                f,
+                "not implemented BIOS function: int {:x}, AH = {:x} (EIP = {:x})",
+                vector, function, eip
+            ),
+            EmuError::Exception {
+                eip,
+                vector,
+                error_code: Some(error_code),
+            } => write!(
+                f,
+                "exception {:x}, error code {:x} (EIP = {:x})",
+                vector, error_code, eip
+            ),
+            EmuError::Exception { eip, vector, .. } => {
+                write!(f, "exception {:x} (EIP = {:x})", vector, eip)
+            }
+            EmuError::TripleFault { eip } => write!(f, "triple fault (EIP = {:x})", eip),
+        }
+    }
+}
+
+impl Error for EmuError {}
@@ -0,0 +1,92 @@
+/// The kind of operation whose arithmetic flags are still pending.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlagOp {
+    /// EFLAGS is up to date.
+    None,
+    Add,
+    Sub,
+    Logic,
+    /// INC and DEC, which keep the previous CF.
+    Inc,
+    Dec,
+}
+
+/// The last flag-setting operation, kept so that CF, PF, AF, ZF, SF and OF are only
+/// computed when something reads them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LazyFlags {
+    pub op: FlagOp,
+    pub v1: u32,
+    pub v2: u32,
+    /// The untruncated result: `v1 + v2 (+ carry)`, a wrapping 64-bit `v1 - v2
+    /// (- borrow)` or the logical result. INC and DEC use `v2 = 1`.
+    pub result: u64,
+    pub bits: u32,
+    /// CF from before an INC or DEC.
+    pub carry: bool,
+}
+
+impl LazyFlags {
+    pub fn new() -> LazyFlags {
+        LazyFlags {
+            op: FlagOp::None,
+            v1: 0,
+            v2: 0,
+            result: 0,
+            bits: 32,
+            carry: false,
+        }
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.op != FlagOp::None
+    }
+
+    fn sign_bit(&self, value: u64) -> u64 {
+        (value >> (self.bits - 1)) & 1
+    }
+
+    pub fn carry(&self) -> bool {
+        match self.op {
+            FlagOp::Add | FlagOp::Sub => (self.result >> self.bits) & 1 != 0,
+            FlagOp::Inc | FlagOp::Dec => self.carry,
+            _ => false,
+        }
+    }
+
+    pub fn parity(&self) -> bool {
+        (self.result as u8).count_ones() & 1 == 0
+    }
+
+    pub fn aux_carry(&self) -> bool {
+        match self.op {
+            FlagOp::Logic | FlagOp::None => false,
+            _ => (self.v1 as u64 ^ self.v2 as u64 ^ self.result) & 0x10 != 0,
+        }
+    }
+
+    pub fn zero(&self) -> bool {
+        self.result & ((1 << self.bits) - 1) == 0
+    }
+
+    pub fn sign(&self) -> bool {
+        self.sign_bit(self.result) != 0
+    }
+
+    pub fn overflow(&self) -> bool {
+        let sign1 = self.sign_bit(self.v1 as u64);
+        let sign2 = self.sign_bit(self.v2 as u64);
+        let signr = self.sign_bit(self.result);
+        match self.op {
+            FlagOp::Add | FlagOp::Inc => sign1 == sign2 && sign1 != signr,
+            FlagOp::Sub | FlagOp::Dec => sign1 != sign2 && sign1 != signr,
+            _ => false,
+        }
+    }
+}
+
+impl Default for LazyFlags {
+    fn default() -> LazyFlags {
+        LazyFlags::new()
+    }
+}
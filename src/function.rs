@@ -1,31 +1,285 @@
-use std::convert::TryInto;
-
+use crate::descriptor::*;
 use crate::emulator::*;
+use crate::error::*;
+use crate::flags::*;
+use crate::paging::*;
 use crate::*;
 
-const CARRY_FLAG: u32 = 1;
-const ZERO_FLAG: u32 = 1 << 6;
-const SIGN_FLAG: u32 = 1 << 7;
-const OVERFLOW_FLAG: u32 = 1 << 11;
+pub const CARRY_FLAG: u32 = 1;
+/// Bit 1 of EFLAGS is reserved and always reads as 1.
+pub const RESERVED_FLAG: u32 = 1 << 1;
+pub const PARITY_FLAG: u32 = 1 << 2;
+pub const AUX_CARRY_FLAG: u32 = 1 << 4;
+pub const ZERO_FLAG: u32 = 1 << 6;
+pub const SIGN_FLAG: u32 = 1 << 7;
+pub const TRAP_FLAG: u32 = 1 << 8;
+pub const INTERRUPT_FLAG: u32 = 1 << 9;
+pub const DIRECTION_FLAG: u32 = 1 << 10;
+pub const OVERFLOW_FLAG: u32 = 1 << 11;
+pub const IOPL_MASK: u32 = 3 << 12;
+pub const NESTED_TASK_FLAG: u32 = 1 << 14;
+
+pub const CR0_PE: u32 = 1;
+pub const CR0_ET: u32 = 1 << 4;
+pub const CR0_WP: u32 = 1 << 16;
+pub const CR0_PG: u32 = 1 << 31;
+
+/// Flags that POPF may change.
+pub const POPF_MASK: u32 = CARRY_FLAG
+    | PARITY_FLAG
+    | AUX_CARRY_FLAG
+    | ZERO_FLAG
+    | SIGN_FLAG
+    | TRAP_FLAG
+    | INTERRUPT_FLAG
+    | DIRECTION_FLAG
+    | OVERFLOW_FLAG
+    | IOPL_MASK
+    | NESTED_TASK_FLAG;
+
+pub fn memory_fault(emu: &Emulator, address: u32) -> EmuError {
+    EmuError::MemoryFault {
+        eip: emu.start_eip,
+        address,
+    }
+}
+
+pub fn exception(emu: &Emulator, vector: u8) -> EmuError {
+    EmuError::Exception {
+        eip: emu.start_eip,
+        vector,
+        error_code: None,
+    }
+}
+
+pub fn exception_with_code(emu: &Emulator, vector: u8, error_code: u32) -> EmuError {
+    EmuError::Exception {
+        eip: emu.start_eip,
+        vector,
+        error_code: Some(error_code),
+    }
+}
+
+/// Sign-extends the low `size` bits of `value`.
+pub fn sign_extend(value: u32, size: u32) -> i32 {
+    ((value << (32 - size)) as i32) >> (32 - size)
+}
+
+pub fn is_protected_mode(emu: &Emulator) -> bool {
+    emu.cr[0] & CR0_PE != 0
+}
+
+/// Returns the current privilege level, which is always 0 in real mode.
+pub fn current_privilege_level(emu: &Emulator) -> u8 {
+    if is_protected_mode(emu) {
+        emu.cpl
+    } else {
+        0
+    }
+}
+
+pub fn io_privilege_level(emu: &Emulator) -> u8 {
+    ((emu.eflags & IOPL_MASK) >> 12) as u8
+}
+
+/// Raises #GP(0) unless running at CPL 0, for LGDT, MOV CRn, HLT and the like.
+pub fn check_privileged(emu: &Emulator) -> EmuResult {
+    if current_privilege_level(emu) != 0 {
+        return Err(exception_with_code(emu, GENERAL_PROTECTION, 0));
+    }
+    Ok(())
+}
+
+/// Raises #GP(0) if CPL is above IOPL, for CLI, STI, IN and OUT.
+pub fn check_io_privilege(emu: &Emulator) -> EmuResult {
+    if current_privilege_level(emu) > io_privilege_level(emu) {
+        return Err(exception_with_code(emu, GENERAL_PROTECTION, 0));
+    }
+    Ok(())
+}
+
+pub fn operand_size(emu: &Emulator) -> u32 {
+    emu.decode.operand_size
+}
+
+pub fn address_size(emu: &Emulator) -> u32 {
+    emu.decode.address_size
+}
+
+/// Returns the segment selected by a segment override prefix, or `default` if the
+/// instruction has none.
+pub fn override_segment(emu: &Emulator, default: usize) -> usize {
+    emu.decode.segment.unwrap_or(default)
+}
+
+pub fn get_segment_base(emu: &Emulator, index: usize) -> u32 {
+    emu.sregs[index].base
+}
+
+/// Checks `size` bits at `offset` against the limit of a segment and returns their
+/// linear address. Violations raise #SS(0) for SS and #GP(0) otherwise.
+pub fn segment_address(emu: &Emulator, index: usize, offset: u32, size: u32) -> EmuResult<u32> {
+    let sreg = &emu.sregs[index];
+    let last = offset.wrapping_add(size / 8 - 1);
+    let valid = if sreg.access & ACCESS_PRESENT == 0 {
+        false
+    } else if sreg.access & 0x1c == 0x14 {
+        // Expand-down data segment: valid offsets lie above the limit.
+        let upper = if sreg.big { 0xffffffff } else { 0xffff };
+        offset > sreg.limit && last >= offset && last <= upper
+    } else {
+        last >= offset && last <= sreg.limit
+    };
+    if valid {
+        Ok(sreg.base.wrapping_add(offset))
+    } else if index == SS {
+        Err(exception_with_code(emu, STACK_FAULT, 0))
+    } else {
+        Err(exception_with_code(emu, GENERAL_PROTECTION, 0))
+    }
+}
+
+/// Loads a segment register. In real mode the base is simply `selector << 4` and
+/// the rest of the cached descriptor is left untouched. In protected mode the
+/// descriptor is read from the GDT and checked for the register it is loaded into.
+pub fn load_segment(emu: &mut Emulator, index: usize, selector: u16) -> EmuResult {
+    let cpl = current_privilege_level(emu);
+    load_segment_with_cpl(emu, index, selector, cpl)
+}
+
+/// Loads a segment register, checking privilege against `cpl` rather than the
+/// current level. Loading CS sets its RPL and the CPL to `cpl`; the caller has
+/// already checked that the code segment may be entered at that level.
+pub fn load_segment_with_cpl(
+    emu: &mut Emulator,
+    index: usize,
+    selector: u16,
+    cpl: u8,
+) -> EmuResult {
+    if !is_protected_mode(emu) {
+        let sreg = &mut emu.sregs[index];
+        sreg.selector = selector;
+        sreg.base = (selector as u32) << 4;
+        sreg.access |= ACCESS_PRESENT;
+        return Ok(());
+    }
+
+    let error_code = (selector & 0xfffc) as u32;
+    if selector & 0xfffc == 0 {
+        if index == CS || index == SS {
+            return Err(exception_with_code(emu, GENERAL_PROTECTION, 0));
+        }
+        emu.sregs[index] = SegmentRegister {
+            selector,
+            ..SegmentRegister::default()
+        };
+        return Ok(());
+    }
+
+    let descriptor = read_descriptor(emu, selector)?;
+    let rpl = (selector & 3) as u8;
+    let valid = match index {
+        CS => descriptor.is_code(),
+        SS => descriptor.is_writable() && rpl == cpl && descriptor.dpl() == cpl,
+        _ => {
+            descriptor.is_readable()
+                && (descriptor.is_conforming() || descriptor.dpl() >= cpl.max(rpl))
+        }
+    };
+    if !valid {
+        return Err(exception_with_code(emu, GENERAL_PROTECTION, error_code));
+    }
+    if !descriptor.is_present() {
+        let vector = if index == SS {
+            STACK_FAULT
+        } else {
+            SEGMENT_NOT_PRESENT
+        };
+        return Err(exception_with_code(emu, vector, error_code));
+    }
+    mark_accessed(emu, selector, &descriptor)?;
+
+    let selector = if index == CS {
+        emu.cpl = cpl;
+        (selector & 0xfffc) | cpl as u16
+    } else {
+        selector
+    };
+    emu.sregs[index] = SegmentRegister {
+        selector,
+        base: descriptor.base,
+        limit: descriptor.limit,
+        big: descriptor.big,
+        access: descriptor.access | ACCESS_ACCESSED,
+    };
+    Ok(())
+}
+
+/// Sets EIP after a near branch, truncating it to IP for 16-bit operand size.
+pub fn set_eip(emu: &mut Emulator, value: u32) {
+    emu.eip = if operand_size(emu) == 16 {
+        value & 0xffff
+    } else {
+        value
+    };
+}
+
+/// Moves EIP past `length` bytes of the current instruction. The offset wraps
+/// around the top of the code segment, at 64K unless CS is a 32-bit segment.
+pub fn advance_eip(emu: &mut Emulator, length: u32) {
+    let eip = emu.eip.wrapping_add(length);
+    emu.eip = if emu.sregs[CS].big { eip } else { eip & 0xffff };
+}
+
+pub fn relative_jump(emu: &mut Emulator, length: u32, diff: i32) {
+    let target = emu.eip.wrapping_add(length).wrapping_add(diff as u32);
+    set_eip(emu, target);
+}
+
+pub fn get_code8(emu: &mut Emulator, index: usize) -> EmuResult<u8> {
+    let address = segment_address(emu, CS, emu.eip.wrapping_add(index as u32), 8)?;
+    Ok(get_memory8(emu, address)? as u8)
+}
 
-pub fn get_code8(emu: &Emulator, index: usize) -> u8 {
-    emu.memory[emu.eip + index]
+pub fn get_code16(emu: &mut Emulator, index: usize) -> EmuResult<u32> {
+    Ok(get_code8(emu, index)? as u32 | (get_code8(emu, index + 1)? as u32) << 8)
 }
 
-pub fn get_code32(emu: &Emulator, index: usize) -> u32 {
+pub fn get_code32(emu: &mut Emulator, index: usize) -> EmuResult<u32> {
     let mut ret: u32 = 0;
     for i in 0..4 {
-        ret |= (get_code8(emu, index + i) as u32) << (i * 8);
+        ret |= (get_code8(emu, index + i)? as u32) << (i * 8);
     }
-    ret
+    Ok(ret)
 }
 
-pub fn get_sign_code8(emu: &Emulator, index: usize) -> i8 {
-    emu.memory[emu.eip + index] as i8
+pub fn get_code(emu: &mut Emulator, index: usize, size: u32) -> EmuResult<u32> {
+    match size {
+        8 => Ok(get_code8(emu, index)? as u32),
+        16 => get_code16(emu, index),
+        _ => get_code32(emu, index),
+    }
+}
+
+pub fn get_sign_code8(emu: &mut Emulator, index: usize) -> EmuResult<i8> {
+    Ok(get_code8(emu, index)? as i8)
+}
+
+pub fn get_sign_code16(emu: &mut Emulator, index: usize) -> EmuResult<i16> {
+    Ok(get_code16(emu, index)? as i16)
+}
+
+pub fn get_sign_code32(emu: &mut Emulator, index: usize) -> EmuResult<i32> {
+    Ok(get_code32(emu, index)? as i32)
 }
 
-pub fn get_sign_code32(emu: &Emulator, index: usize) -> i32 {
-    get_code32(emu, index) as i32
+/// Reads a `size`-bit immediate and sign-extends it to 32 bits.
+pub fn get_sign_code(emu: &mut Emulator, index: usize, size: u32) -> EmuResult<i32> {
+    match size {
+        8 => Ok(get_sign_code8(emu, index)? as i32),
+        16 => Ok(get_sign_code16(emu, index)? as i32),
+        _ => get_sign_code32(emu, index),
+    }
 }
 
 pub fn get_register8(emu: &Emulator, index: usize) -> u8 {
@@ -36,10 +290,22 @@ pub fn get_register8(emu: &Emulator, index: usize) -> u8 {
     }
 }
 
+pub fn get_register16(emu: &Emulator, index: usize) -> u16 {
+    (emu.registers[index] & 0xffff) as u16
+}
+
 pub fn get_register32(emu: &Emulator, index: usize) -> u32 {
     emu.registers[index]
 }
 
+pub fn get_register(emu: &Emulator, index: usize, size: u32) -> u32 {
+    match size {
+        8 => get_register8(emu, index) as u32,
+        16 => get_register16(emu, index) as u32,
+        _ => get_register32(emu, index),
+    }
+}
+
 pub fn set_register8(emu: &mut Emulator, index: usize, value: u8) {
     if index < 4 {
         let r = emu.registers[index] & 0xffffff00;
@@ -50,46 +316,189 @@ pub fn set_register8(emu: &mut Emulator, index: usize, value: u8) {
     }
 }
 
+pub fn set_register16(emu: &mut Emulator, index: usize, value: u16) {
+    let r = emu.registers[index] & 0xffff0000;
+    emu.registers[index] = r | (value as u32);
+}
+
 pub fn set_register32(emu: &mut Emulator, index: usize, value: u32) {
     emu.registers[index] = value;
 }
 
-pub fn set_memory8(emu: &mut Emulator, address: u32, value: u32) {
-    emu.memory[address as usize] = (value & 0xff).try_into().unwrap();
+pub fn set_register(emu: &mut Emulator, index: usize, size: u32, value: u32) {
+    match size {
+        8 => set_register8(emu, index, value as u8),
+        16 => set_register16(emu, index, value as u16),
+        _ => set_register32(emu, index, value),
+    }
+}
+
+pub fn get_physical8(emu: &mut Emulator, address: u32) -> EmuResult<u32> {
+    match emu.memory.read8(address) {
+        Some(byte) => Ok(byte as u32),
+        None => Err(memory_fault(emu, address)),
+    }
+}
+
+pub fn get_physical32(emu: &mut Emulator, address: u32) -> EmuResult<u32> {
+    let mut ret = 0;
+    for i in 0..4 {
+        ret |= get_physical8(emu, address.wrapping_add(i))? << (8 * i);
+    }
+    Ok(ret)
+}
+
+pub fn set_physical8(emu: &mut Emulator, address: u32, value: u32) -> EmuResult {
+    if emu.memory.write8(address, value as u8) {
+        Ok(())
+    } else {
+        Err(memory_fault(emu, address))
+    }
+}
+
+pub fn set_physical32(emu: &mut Emulator, address: u32, value: u32) -> EmuResult {
+    for i in 0..4 {
+        set_physical8(emu, address.wrapping_add(i), value >> (i * 8))?;
+    }
+    Ok(())
+}
+
+fn get_system_memory8(emu: &mut Emulator, address: u32) -> EmuResult<u32> {
+    let address = translate_as(emu, address, false, false)?;
+    get_physical8(emu, address)
+}
+
+/// Reads memory as a supervisor access whatever the CPL, as the processor does for
+/// the descriptor tables and the TSS.
+pub fn get_system_memory16(emu: &mut Emulator, address: u32) -> EmuResult<u32> {
+    Ok(get_system_memory8(emu, address)? | get_system_memory8(emu, address.wrapping_add(1))? << 8)
 }
 
-pub fn set_memory32(emu: &mut Emulator, address: u32, value: u32) {
+pub fn get_system_memory32(emu: &mut Emulator, address: u32) -> EmuResult<u32> {
+    Ok(get_system_memory16(emu, address)?
+        | get_system_memory16(emu, address.wrapping_add(2))? << 16)
+}
+
+pub fn set_system_memory8(emu: &mut Emulator, address: u32, value: u32) -> EmuResult {
+    let address = translate_as(emu, address, true, false)?;
+    set_physical8(emu, address, value)
+}
+
+/// Translates both pages of a write that crosses a page boundary, so a page fault
+/// is raised before any byte is stored.
+fn check_page_crossing(emu: &mut Emulator, address: u32, size: u32) -> EmuResult {
+    let last = address.wrapping_add(size / 8 - 1);
+    if is_paging(emu) && (last ^ address) & 0xfffff000 != 0 {
+        translate(emu, address, true)?;
+        translate(emu, last & 0xfffff000, true)?;
+    }
+    Ok(())
+}
+
+pub fn set_memory8(emu: &mut Emulator, address: u32, value: u32) -> EmuResult {
+    let address = translate(emu, address, true)?;
+    set_physical8(emu, address, value)
+}
+
+pub fn set_memory16(emu: &mut Emulator, address: u32, value: u32) -> EmuResult {
+    check_page_crossing(emu, address, 16)?;
+    set_memory8(emu, address, value)?;
+    set_memory8(emu, address.wrapping_add(1), value >> 8)
+}
+
+pub fn set_memory32(emu: &mut Emulator, address: u32, value: u32) -> EmuResult {
+    check_page_crossing(emu, address, 32)?;
     for i in 0..4 {
-        set_memory8(emu, address + i, value >> (i * 8));
+        set_memory8(emu, address.wrapping_add(i), value >> (i * 8))?;
     }
+    Ok(())
 }
 
-pub fn get_memory8(emu: &Emulator, address: u32) -> u32 {
-    emu.memory[address as usize] as u32
+pub fn set_memory(emu: &mut Emulator, address: u32, size: u32, value: u32) -> EmuResult {
+    match size {
+        8 => set_memory8(emu, address, value),
+        16 => set_memory16(emu, address, value),
+        _ => set_memory32(emu, address, value),
+    }
 }
 
-pub fn get_memory32(emu: &Emulator, address: u32) -> u32 {
+pub fn get_memory8(emu: &mut Emulator, address: u32) -> EmuResult<u32> {
+    let address = translate(emu, address, false)?;
+    get_physical8(emu, address)
+}
+
+pub fn get_memory16(emu: &mut Emulator, address: u32) -> EmuResult<u32> {
+    Ok(get_memory8(emu, address)? | get_memory8(emu, address.wrapping_add(1))? << 8)
+}
+
+pub fn get_memory32(emu: &mut Emulator, address: u32) -> EmuResult<u32> {
     let mut ret = 0;
     for i in 0..4 {
-        ret |= get_memory8(emu, address + i) << (8 * i);
+        ret |= get_memory8(emu, address.wrapping_add(i))? << (8 * i);
+    }
+    Ok(ret)
+}
+
+pub fn get_memory(emu: &mut Emulator, address: u32, size: u32) -> EmuResult<u32> {
+    match size {
+        8 => get_memory8(emu, address),
+        16 => get_memory16(emu, address),
+        _ => get_memory32(emu, address),
     }
-    ret
 }
 
-pub fn push32(emu: &mut Emulator, value: u32) {
-    let address = get_register32(emu, ESP) - 4;
-    set_register32(emu, ESP, address);
-    set_memory32(emu, address, value);
+/// Returns the stack pointer, which is SP rather than ESP when SS is a 16-bit segment.
+pub fn get_stack_pointer(emu: &Emulator) -> u32 {
+    if emu.sregs[SS].big {
+        get_register32(emu, ESP)
+    } else {
+        get_register16(emu, ESP) as u32
+    }
+}
+
+pub fn set_stack_pointer(emu: &mut Emulator, value: u32) {
+    if emu.sregs[SS].big {
+        set_register32(emu, ESP, value);
+    } else {
+        set_register16(emu, ESP, value as u16);
+    }
 }
 
-pub fn pop32(emu: &mut Emulator) -> u32 {
-    let address = get_register32(emu, ESP);
-    let ret = get_memory32(emu, address);
-    set_register32(emu, ESP, address + 4);
-    ret
+pub fn push(emu: &mut Emulator, value: u32, size: u32) -> EmuResult {
+    let sp = get_stack_pointer(emu).wrapping_sub(size / 8);
+    let sp = if emu.sregs[SS].big { sp } else { sp & 0xffff };
+    let address = segment_address(emu, SS, sp, size)?;
+    set_memory(emu, address, size, value)?;
+    set_stack_pointer(emu, sp);
+    Ok(())
+}
+
+pub fn pop(emu: &mut Emulator, size: u32) -> EmuResult<u32> {
+    let sp = get_stack_pointer(emu);
+    let address = segment_address(emu, SS, sp, size)?;
+    let ret = get_memory(emu, address, size)?;
+    set_stack_pointer(emu, sp.wrapping_add(size / 8));
+    Ok(ret)
+}
+
+pub fn push16(emu: &mut Emulator, value: u32) -> EmuResult {
+    push(emu, value, 16)
+}
+
+pub fn pop16(emu: &mut Emulator) -> EmuResult<u32> {
+    pop(emu, 16)
+}
+
+pub fn push32(emu: &mut Emulator, value: u32) -> EmuResult {
+    push(emu, value, 32)
+}
+
+pub fn pop32(emu: &mut Emulator) -> EmuResult<u32> {
+    pop(emu, 32)
 }
 
 pub fn set_carry(emu: &mut Emulator, is_carry: bool) {
+    materialize_flags(emu);
     if is_carry {
         emu.eflags |= CARRY_FLAG;
     } else {
@@ -97,7 +506,26 @@ pub fn set_carry(emu: &mut Emulator, is_carry: bool) {
     }
 }
 
+pub fn set_parity(emu: &mut Emulator, is_parity: bool) {
+    materialize_flags(emu);
+    if is_parity {
+        emu.eflags |= PARITY_FLAG;
+    } else {
+        emu.eflags &= !PARITY_FLAG;
+    }
+}
+
+pub fn set_aux_carry(emu: &mut Emulator, is_aux_carry: bool) {
+    materialize_flags(emu);
+    if is_aux_carry {
+        emu.eflags |= AUX_CARRY_FLAG;
+    } else {
+        emu.eflags &= !AUX_CARRY_FLAG;
+    }
+}
+
 pub fn set_zero(emu: &mut Emulator, is_zero: bool) {
+    materialize_flags(emu);
     if is_zero {
         emu.eflags |= ZERO_FLAG;
     } else {
@@ -106,6 +534,7 @@ pub fn set_zero(emu: &mut Emulator, is_zero: bool) {
 }
 
 pub fn set_sign(emu: &mut Emulator, is_sign: bool) {
+    materialize_flags(emu);
     if is_sign {
         emu.eflags |= SIGN_FLAG;
     } else {
@@ -113,7 +542,32 @@ pub fn set_sign(emu: &mut Emulator, is_sign: bool) {
     }
 }
 
+pub fn set_trap(emu: &mut Emulator, is_trap: bool) {
+    if is_trap {
+        emu.eflags |= TRAP_FLAG;
+    } else {
+        emu.eflags &= !TRAP_FLAG;
+    }
+}
+
+pub fn set_interrupt(emu: &mut Emulator, is_interrupt: bool) {
+    if is_interrupt {
+        emu.eflags |= INTERRUPT_FLAG;
+    } else {
+        emu.eflags &= !INTERRUPT_FLAG;
+    }
+}
+
+pub fn set_direction(emu: &mut Emulator, is_direction: bool) {
+    if is_direction {
+        emu.eflags |= DIRECTION_FLAG;
+    } else {
+        emu.eflags &= !DIRECTION_FLAG;
+    }
+}
+
 pub fn set_overflow(emu: &mut Emulator, is_overflow: bool) {
+    materialize_flags(emu);
     if is_overflow {
         emu.eflags |= OVERFLOW_FLAG;
     } else {
@@ -122,28 +576,163 @@ pub fn set_overflow(emu: &mut Emulator, is_overflow: bool) {
 }
 
 pub fn is_carry(emu: &mut Emulator) -> bool {
-    emu.eflags & CARRY_FLAG != 0
+    if emu.lazy_flags.is_pending() {
+        emu.lazy_flags.carry()
+    } else {
+        emu.eflags & CARRY_FLAG != 0
+    }
+}
+
+pub fn is_parity(emu: &mut Emulator) -> bool {
+    if emu.lazy_flags.is_pending() {
+        emu.lazy_flags.parity()
+    } else {
+        emu.eflags & PARITY_FLAG != 0
+    }
+}
+
+pub fn is_aux_carry(emu: &mut Emulator) -> bool {
+    if emu.lazy_flags.is_pending() {
+        emu.lazy_flags.aux_carry()
+    } else {
+        emu.eflags & AUX_CARRY_FLAG != 0
+    }
 }
 
 pub fn is_zero(emu: &mut Emulator) -> bool {
-    emu.eflags & ZERO_FLAG != 0
+    if emu.lazy_flags.is_pending() {
+        emu.lazy_flags.zero()
+    } else {
+        emu.eflags & ZERO_FLAG != 0
+    }
 }
 
 pub fn is_sign(emu: &mut Emulator) -> bool {
-    emu.eflags & SIGN_FLAG != 0
+    if emu.lazy_flags.is_pending() {
+        emu.lazy_flags.sign()
+    } else {
+        emu.eflags & SIGN_FLAG != 0
+    }
+}
+
+pub fn is_trap(emu: &Emulator) -> bool {
+    emu.eflags & TRAP_FLAG != 0
+}
+
+pub fn is_interrupt(emu: &Emulator) -> bool {
+    emu.eflags & INTERRUPT_FLAG != 0
+}
+
+pub fn is_direction(emu: &Emulator) -> bool {
+    emu.eflags & DIRECTION_FLAG != 0
 }
 
 pub fn is_overflow(emu: &mut Emulator) -> bool {
-    emu.eflags & OVERFLOW_FLAG != 0
+    if emu.lazy_flags.is_pending() {
+        emu.lazy_flags.overflow()
+    } else {
+        emu.eflags & OVERFLOW_FLAG != 0
+    }
+}
+
+/// Evaluates the condition encoded in the low nibble of a Jcc, SETcc or CMOVcc
+/// opcode.
+pub fn condition(emu: &mut Emulator, code: u8) -> bool {
+    let result = match (code >> 1) & 0x07 {
+        0 => is_overflow(emu),
+        1 => is_carry(emu),
+        2 => is_zero(emu),
+        3 => is_carry(emu) || is_zero(emu),
+        4 => is_sign(emu),
+        5 => is_parity(emu),
+        6 => is_sign(emu) != is_overflow(emu),
+        _ => is_zero(emu) || is_sign(emu) != is_overflow(emu),
+    };
+    result != (code & 1 == 1)
+}
+
+/// Writes any pending arithmetic flags back into `emu.eflags`.
+pub fn materialize_flags(emu: &mut Emulator) {
+    let lazy = emu.lazy_flags;
+    if !lazy.is_pending() {
+        return;
+    }
+    let flags = [
+        (CARRY_FLAG, lazy.carry()),
+        (PARITY_FLAG, lazy.parity()),
+        (AUX_CARRY_FLAG, lazy.aux_carry()),
+        (ZERO_FLAG, lazy.zero()),
+        (SIGN_FLAG, lazy.sign()),
+        (OVERFLOW_FLAG, lazy.overflow()),
+    ];
+    for &(flag, is_set) in flags.iter() {
+        if is_set {
+            emu.eflags |= flag;
+        } else {
+            emu.eflags &= !flag;
+        }
+    }
+    emu.lazy_flags.op = FlagOp::None;
+}
+
+/// Flags that POPF and IRET may change: IOPL only at CPL 0 and IF only when CPL is
+/// at most IOPL.
+pub fn writable_flags(emu: &Emulator, size: u32) -> u32 {
+    let mut mask = POPF_MASK & (u32::MAX >> (32 - size));
+    let cpl = current_privilege_level(emu);
+    if cpl > 0 {
+        mask &= !IOPL_MASK;
+    }
+    if cpl > io_privilege_level(emu) {
+        mask &= !INTERRUPT_FLAG;
+    }
+    mask
+}
+
+/// Returns EFLAGS as seen by PUSHF.
+pub fn get_eflags(emu: &mut Emulator) -> u32 {
+    materialize_flags(emu);
+    emu.eflags | RESERVED_FLAG
+}
+
+/// Replaces the flags selected by `mask`, keeping the reserved bit set.
+pub fn set_eflags(emu: &mut Emulator, value: u32, mask: u32) {
+    materialize_flags(emu);
+    emu.eflags = (emu.eflags & !mask) | (value & mask) | RESERVED_FLAG;
+}
+
+fn record_flags(emu: &mut Emulator, op: FlagOp, v1: u32, v2: u32, result: u64, bits: u32) {
+    emu.lazy_flags = LazyFlags {
+        op,
+        v1,
+        v2,
+        result,
+        bits,
+        carry: false,
+    };
+}
+
+/// Records the arithmetic flags of `result = v1 + v2 (+ carry)` on `bits`-wide
+/// operands, where `result` is computed without truncation.
+pub fn update_eflags_add(emu: &mut Emulator, v1: u32, v2: u32, result: u64, bits: u32) {
+    record_flags(emu, FlagOp::Add, v1, v2, result, bits);
 }
 
-pub fn update_eflags_sub(emu: &mut Emulator, v1: u32, v2: u32, result: u64) {
-    let sign1 = v1 >> 31;
-    let sign2 = v2 >> 31;
-    let signr = (result >> 31) & 1;
+/// Records the arithmetic flags of `result = v1 - v2 (- borrow)` on `bits`-wide
+/// operands, where `result` is computed as a wrapping 64-bit subtraction.
+pub fn update_eflags_sub(emu: &mut Emulator, v1: u32, v2: u32, result: u64, bits: u32) {
+    record_flags(emu, FlagOp::Sub, v1, v2, result, bits);
+}
+
+/// Records the flags of `result = value + 1` or `value - 1`, keeping CF.
+pub fn update_eflags_inc_dec(emu: &mut Emulator, is_dec: bool, value: u32, result: u64, bits: u32) {
+    let carry = is_carry(emu);
+    let op = if is_dec { FlagOp::Dec } else { FlagOp::Inc };
+    record_flags(emu, op, value, 1, result, bits);
+    emu.lazy_flags.carry = carry;
+}
 
-    set_carry(emu, (result >> 32) != 0);
-    set_zero(emu, result == 0);
-    set_sign(emu, signr != 0);
-    set_overflow(emu, sign1 != sign2 && sign1 as u64 != signr);
+/// Records the flags of a bitwise AND, OR or XOR, which always clear CF, AF and OF.
+pub fn update_eflags_logic(emu: &mut Emulator, result: u32, bits: u32) {
+    record_flags(emu, FlagOp::Logic, 0, 0, result as u64, bits);
 }
@@ -1,368 +1,1632 @@
-use std::convert::TryInto;
-use std::process;
-
-use crate::bios::*;
+use crate::decode::*;
+use crate::descriptor::*;
 use crate::emulator::*;
+use crate::error::*;
 use crate::function::*;
-use crate::io::*;
+use crate::interrupt::*;
 use crate::modrm::*;
+use crate::task::*;
 use crate::*;
 
-type InstFunc = fn(&mut Emulator);
+pub type InstFunc = fn(&mut Emulator) -> EmuResult;
 pub type Insts = [InstFunc; 256];
 
-pub fn undefined(_emu: &mut Emulator) {}
+pub fn undefined(emu: &mut Emulator) -> EmuResult {
+    let mut bytes = vec![get_code8(emu, 0)?];
+    if bytes[0] == 0x0F {
+        bytes.push(get_code8(emu, 1)?);
+    }
+    Err(EmuError::UnimplementedOpcode {
+        eip: emu.start_eip,
+        bytes,
+    })
+}
 
-pub fn mov_r8_imm8(emu: &mut Emulator) {
-    let reg = get_code8(emu, 0) - 0xB0;
-    let value = get_code8(emu, 1);
+pub fn mov_r8_imm8(emu: &mut Emulator) -> EmuResult {
+    let reg = get_code8(emu, 0)? - 0xB0;
+    let value = get_code8(emu, 1)?;
     set_register8(emu, reg as usize, value);
-    emu.eip += 2;
+    advance_eip(emu, 2);
+    Ok(())
 }
 
-pub fn mov_r32_imm32(emu: &mut Emulator) {
-    let reg: u8 = get_code8(emu, 0) - 0xB8;
-    let value: u32 = get_code32(emu, 1);
-    emu.registers[reg as usize] = value;
-    emu.eip += 5;
+pub fn mov_r32_imm32(emu: &mut Emulator) -> EmuResult {
+    let size = operand_size(emu);
+    let reg = get_code8(emu, 0)? - 0xB8;
+    let value = get_code(emu, 1, size)?;
+    set_register(emu, reg as usize, size, value);
+    advance_eip(emu, 1 + size / 8);
+    Ok(())
 }
 
-pub fn mov_r8_rm8(emu: &mut Emulator) {
-    emu.eip += 1;
+pub fn mov_r8_rm8(emu: &mut Emulator) -> EmuResult {
+    advance_eip(emu, 1);
     let mut modrm = ModRM::default();
-    parse_modrm(emu, &mut modrm);
-    let rm8 = get_rm8(emu, &mut modrm);
+    parse_modrm(emu, &mut modrm)?;
+    let rm8 = get_rm8(emu, &modrm)?;
     set_r8(emu, &modrm, rm8);
+    Ok(())
 }
 
-pub fn mov_rm32_imm32(emu: &mut Emulator) {
-    emu.eip += 1;
+pub fn mov_rm32_imm32(emu: &mut Emulator) -> EmuResult {
+    let size = operand_size(emu);
+    advance_eip(emu, 1);
     let mut modrm = ModRM::default();
-    parse_modrm(emu, &mut modrm);
-    let value = get_code32(emu, 0);
-    emu.eip += 4;
-    set_rm32(emu, &modrm, value);
+    parse_modrm(emu, &mut modrm)?;
+    let value = get_code(emu, 0, size)?;
+    advance_eip(emu, size / 8);
+    set_rm(emu, &modrm, size, value)
 }
 
-pub fn mov_rm32_r32(emu: &mut Emulator) {
-    emu.eip += 1;
+pub fn mov_rm32_r32(emu: &mut Emulator) -> EmuResult {
+    let size = operand_size(emu);
+    advance_eip(emu, 1);
     let mut modrm = ModRM::default();
-    parse_modrm(emu, &mut modrm);
-    let r32 = get_r32(emu, &mut modrm);
-    set_rm32(emu, &mut modrm, r32)
+    parse_modrm(emu, &mut modrm)?;
+    let r32 = get_r(emu, &modrm, size);
+    set_rm(emu, &modrm, size, r32)
 }
 
-pub fn mov_r32_rm32(emu: &mut Emulator) {
-    emu.eip += 1;
+pub fn mov_r32_rm32(emu: &mut Emulator) -> EmuResult {
+    let size = operand_size(emu);
+    advance_eip(emu, 1);
     let mut modrm = ModRM::default();
-    parse_modrm(emu, &mut modrm);
-    let rm32 = get_rm32(emu, &mut modrm);
-    set_r32(emu, &modrm, rm32);
+    parse_modrm(emu, &mut modrm)?;
+    let rm32 = get_rm(emu, &modrm, size)?;
+    set_r(emu, &modrm, size, rm32);
+    Ok(())
+}
+
+fn inc_dec(emu: &mut Emulator, value: u32, is_dec: bool, size: u32) -> u32 {
+    let result = if is_dec {
+        (value as u64).wrapping_sub(1)
+    } else {
+        value as u64 + 1
+    };
+    update_eflags_inc_dec(emu, is_dec, value, result, size);
+    result as u32 & (u32::MAX >> (32 - size))
+}
+
+/// INC (0x40+r) and DEC (0x48+r).
+pub fn inc_dec_r32(emu: &mut Emulator) -> EmuResult {
+    let size = operand_size(emu);
+    let code = get_code8(emu, 0)?;
+    let reg = (code & 0x07) as usize;
+    let value = get_register(emu, reg, size);
+    let result = inc_dec(emu, value, code >= 0x48, size);
+    set_register(emu, reg, size, result);
+    advance_eip(emu, 1);
+    Ok(())
+}
+
+pub fn push_r32(emu: &mut Emulator) -> EmuResult {
+    let size = operand_size(emu);
+    let reg = get_code8(emu, 0)? - 0x50;
+    push(emu, get_register(emu, reg as usize, size), size)?;
+    advance_eip(emu, 1);
+    Ok(())
+}
+
+pub fn pop_r32(emu: &mut Emulator) -> EmuResult {
+    let size = operand_size(emu);
+    let reg = get_code8(emu, 0)? - 0x58;
+    let value = pop(emu, size)?;
+    set_register(emu, reg as usize, size, value);
+    advance_eip(emu, 1);
+    Ok(())
+}
+
+pub fn push_imm32(emu: &mut Emulator) -> EmuResult {
+    let size = operand_size(emu);
+    let value = get_code(emu, 1, size)?;
+    push(emu, value, size)?;
+    advance_eip(emu, 1 + size / 8);
+    Ok(())
+}
+
+pub fn push_imm8(emu: &mut Emulator) -> EmuResult {
+    let value = get_sign_code8(emu, 1)?;
+    push(emu, value as u32, operand_size(emu))?;
+    advance_eip(emu, 2);
+    Ok(())
+}
+
+pub fn mov_rm8_r8(emu: &mut Emulator) -> EmuResult {
+    advance_eip(emu, 1);
+    let mut modrm = ModRM::default();
+    parse_modrm(emu, &mut modrm)?;
+    let r8 = get_r8(emu, &modrm);
+    set_rm8(emu, &modrm, r8)
+}
+
+fn port_in(emu: &mut Emulator, port: u16, size: u32) -> EmuResult {
+    check_io_privilege(emu)?;
+    emu.io.sync(port, emu.tsc);
+    let value = emu.io.read(port, size);
+    set_register(emu, EAX, size, value);
+    Ok(())
 }
 
-pub fn inc_r32(emu: &mut Emulator) {
-    let reg = get_code8(emu, 0) - 0x40;
-    let value = get_register32(emu, reg as usize) + 1;
-    set_register32(emu, reg as usize, value);
-    emu.eip += 1;
+fn port_out(emu: &mut Emulator, port: u16, size: u32) -> EmuResult {
+    check_io_privilege(emu)?;
+    let value = get_register(emu, EAX, size);
+    emu.io.sync(port, emu.tsc);
+    emu.io.write(port, size, value);
+    Ok(())
 }
 
-pub fn push_r32(emu: &mut Emulator) {
-    let reg = get_code8(emu, 0) - 0x50;
-    push32(emu, get_register32(emu, reg.try_into().unwrap()));
-    emu.eip += 1;
+pub fn in_al_imm8(emu: &mut Emulator) -> EmuResult {
+    let port = get_code8(emu, 1)? as u16;
+    port_in(emu, port, 8)?;
+    advance_eip(emu, 2);
+    Ok(())
 }
 
-pub fn pop_r32(emu: &mut Emulator) {
-    let reg = get_code8(emu, 0) - 0x58;
-    let value = pop32(emu);
-    set_register32(emu, reg.try_into().unwrap(), value);
-    emu.eip += 1;
+pub fn in_eax_imm8(emu: &mut Emulator) -> EmuResult {
+    let port = get_code8(emu, 1)? as u16;
+    let size = operand_size(emu);
+    port_in(emu, port, size)?;
+    advance_eip(emu, 2);
+    Ok(())
 }
 
-pub fn push_imm32(emu: &mut Emulator) {
-    let value = get_code32(emu, 1);
-    push32(emu, value);
-    emu.eip += 5;
+pub fn out_imm8_al(emu: &mut Emulator) -> EmuResult {
+    let port = get_code8(emu, 1)? as u16;
+    port_out(emu, port, 8)?;
+    advance_eip(emu, 2);
+    Ok(())
 }
 
-pub fn push_imm8(emu: &mut Emulator) {
-    let value = get_code8(emu, 1);
-    push32(emu, value.into());
-    emu.eip += 2;
+pub fn out_imm8_eax(emu: &mut Emulator) -> EmuResult {
+    let port = get_code8(emu, 1)? as u16;
+    let size = operand_size(emu);
+    port_out(emu, port, size)?;
+    advance_eip(emu, 2);
+    Ok(())
+}
+
+pub fn in_al_dx(emu: &mut Emulator) -> EmuResult {
+    let port = get_register16(emu, EDX);
+    port_in(emu, port, 8)?;
+    advance_eip(emu, 1);
+    Ok(())
+}
+
+pub fn in_eax_dx(emu: &mut Emulator) -> EmuResult {
+    let port = get_register16(emu, EDX);
+    let size = operand_size(emu);
+    port_in(emu, port, size)?;
+    advance_eip(emu, 1);
+    Ok(())
+}
+
+pub fn out_dx_al(emu: &mut Emulator) -> EmuResult {
+    let port = get_register16(emu, EDX);
+    port_out(emu, port, 8)?;
+    advance_eip(emu, 1);
+    Ok(())
+}
+
+pub fn out_dx_eax(emu: &mut Emulator) -> EmuResult {
+    let port = get_register16(emu, EDX);
+    let size = operand_size(emu);
+    port_out(emu, port, size)?;
+    advance_eip(emu, 1);
+    Ok(())
+}
+
+pub fn inc_dec_rm(emu: &mut Emulator, modrm: &ModRM, size: u32) -> EmuResult {
+    let value = get_rm(emu, modrm, size)?;
+    let result = inc_dec(emu, value, modrm.opecode == 1, size);
+    set_rm(emu, modrm, size, result)
+}
+
+fn unimplemented_group(emu: &Emulator, code: u8, modrm: &ModRM) -> EmuError {
+    EmuError::UnimplementedOpcode {
+        eip: emu.start_eip,
+        bytes: vec![code, modrm_code(modrm)],
+    }
+}
+
+/// Performs the ALU operation selected by `op` (ADD, OR, ADC, SBB, AND, SUB, XOR, CMP
+/// in opcode order) on `bits`-wide operands, updates the flags and returns the result.
+pub fn alu(emu: &mut Emulator, op: u8, v1: u32, v2: u32, bits: u32) -> u32 {
+    let mask = u32::MAX >> (32 - bits);
+    match op {
+        0 => {
+            let result = v1 as u64 + v2 as u64;
+            update_eflags_add(emu, v1, v2, result, bits);
+            result as u32 & mask
+        }
+        1 => {
+            let result = v1 | v2;
+            update_eflags_logic(emu, result, bits);
+            result
+        }
+        2 => {
+            let result = v1 as u64 + v2 as u64 + is_carry(emu) as u64;
+            update_eflags_add(emu, v1, v2, result, bits);
+            result as u32 & mask
+        }
+        3 => {
+            let result = (v1 as u64).wrapping_sub(v2 as u64 + is_carry(emu) as u64);
+            update_eflags_sub(emu, v1, v2, result, bits);
+            result as u32 & mask
+        }
+        4 => {
+            let result = v1 & v2;
+            update_eflags_logic(emu, result, bits);
+            result
+        }
+        6 => {
+            let result = v1 ^ v2;
+            update_eflags_logic(emu, result, bits);
+            result
+        }
+        _ => {
+            let result = (v1 as u64).wrapping_sub(v2 as u64);
+            update_eflags_sub(emu, v1, v2, result, bits);
+            result as u32 & mask
+        }
+    }
 }
 
-pub fn add_rm32_imm8(emu: &mut Emulator, modrm: &ModRM) {
-    let rm32 = get_rm32(emu, modrm);
-    let imm8 = get_sign_code8(emu, 0);
-    emu.eip += 1;
-    set_rm32(emu, modrm, rm32 + imm8 as u32);
+pub fn alu_rm8_r8(emu: &mut Emulator) -> EmuResult {
+    let op = get_code8(emu, 0)? >> 3;
+    advance_eip(emu, 1);
+    let mut modrm = ModRM::default();
+    parse_modrm(emu, &mut modrm)?;
+    let rm8 = get_rm8(emu, &modrm)?;
+    let r8 = get_r8(emu, &modrm);
+
+    let result = alu(emu, op, rm8 as u32, r8 as u32, 8);
+    if op != 7 {
+        set_rm8(emu, &modrm, result as u8)?;
+    }
+    Ok(())
 }
 
-pub fn add_rm32_r32(emu: &mut Emulator) {
-    emu.eip += 1;
+pub fn alu_rm32_r32(emu: &mut Emulator) -> EmuResult {
+    let size = operand_size(emu);
+    let op = get_code8(emu, 0)? >> 3;
+    advance_eip(emu, 1);
     let mut modrm = ModRM::default();
-    parse_modrm(emu, &mut modrm);
-    let rm32 = get_rm32(emu, &mut modrm);
-    let r32 = get_r32(emu, &modrm);
-    set_rm32(emu, &modrm, rm32 + r32);
+    parse_modrm(emu, &mut modrm)?;
+    let rm32 = get_rm(emu, &modrm, size)?;
+    let r32 = get_r(emu, &modrm, size);
+
+    let result = alu(emu, op, rm32, r32, size);
+    if op != 7 {
+        set_rm(emu, &modrm, size, result)?;
+    }
+    Ok(())
 }
 
-pub fn mov_rm8_r8(emu: &mut Emulator) {
-    emu.eip += 1;
+pub fn alu_r8_rm8(emu: &mut Emulator) -> EmuResult {
+    let op = get_code8(emu, 0)? >> 3;
+    advance_eip(emu, 1);
     let mut modrm = ModRM::default();
-    parse_modrm(emu, &mut modrm);
+    parse_modrm(emu, &mut modrm)?;
     let r8 = get_r8(emu, &modrm);
-    set_rm8(emu, &modrm, r8);
+    let rm8 = get_rm8(emu, &modrm)?;
+
+    let result = alu(emu, op, r8 as u32, rm8 as u32, 8);
+    if op != 7 {
+        set_r8(emu, &modrm, result as u8);
+    }
+    Ok(())
 }
 
-pub fn cmp_rm32_imm8(emu: &mut Emulator, modrm: &ModRM) {
-    let rm32 = get_rm32(emu, modrm);
-    let imm8 = get_sign_code8(emu, 0);
-    emu.eip += 1;
-    let result = rm32 as u64 - imm8 as u64;
-    update_eflags_sub(emu, rm32, imm8 as u32, result);
+pub fn alu_r32_rm32(emu: &mut Emulator) -> EmuResult {
+    let size = operand_size(emu);
+    let op = get_code8(emu, 0)? >> 3;
+    advance_eip(emu, 1);
+    let mut modrm = ModRM::default();
+    parse_modrm(emu, &mut modrm)?;
+    let r32 = get_r(emu, &modrm, size);
+    let rm32 = get_rm(emu, &modrm, size)?;
+
+    let result = alu(emu, op, r32, rm32, size);
+    if op != 7 {
+        set_r(emu, &modrm, size, result);
+    }
+    Ok(())
 }
 
-pub fn sub_rm32_imm8(emu: &mut Emulator, modrm: &ModRM) {
-    let rm32 = get_rm32(emu, modrm);
-    let imm8 = get_sign_code8(emu, 0) as u32;
-    emu.eip += 1;
-    let result = rm32 as u64 - imm8 as u64;
-    set_rm32(emu, modrm, rm32 - imm8);
-    update_eflags_sub(emu, rm32, imm8, result);
+pub fn alu_al_imm8(emu: &mut Emulator) -> EmuResult {
+    let op = get_code8(emu, 0)? >> 3;
+    let value = get_code8(emu, 1)?;
+    let al = get_register8(emu, AL);
+
+    let result = alu(emu, op, al as u32, value as u32, 8);
+    if op != 7 {
+        set_register8(emu, AL, result as u8);
+    }
+    advance_eip(emu, 2);
+    Ok(())
 }
 
-pub fn in_al_dx(emu: &mut Emulator) {
-    let address = get_register32(emu, EDX) & 0xffff;
-    let value = io_in8(address);
-    set_register8(emu, AL, value);
-    emu.eip += 1;
+pub fn alu_eax_imm32(emu: &mut Emulator) -> EmuResult {
+    let size = operand_size(emu);
+    let op = get_code8(emu, 0)? >> 3;
+    let value = get_code(emu, 1, size)?;
+    let eax = get_register(emu, EAX, size);
+
+    let result = alu(emu, op, eax, value, size);
+    if op != 7 {
+        set_register(emu, EAX, size, result);
+    }
+    advance_eip(emu, 1 + size / 8);
+    Ok(())
 }
 
-pub fn out_dx_al(emu: &mut Emulator) {
-    let address = get_register32(emu, EDX) & 0xffff;
-    let value = get_register8(emu, AL);
-    io_out8(address, value);
-    emu.eip += 1;
+pub fn code_80(emu: &mut Emulator) -> EmuResult {
+    advance_eip(emu, 1);
+    let mut modrm = ModRM::default();
+    parse_modrm(emu, &mut modrm)?;
+    let rm8 = get_rm8(emu, &modrm)?;
+    let imm8 = get_code8(emu, 0)?;
+    advance_eip(emu, 1);
+
+    let result = alu(emu, modrm.opecode, rm8 as u32, imm8 as u32, 8);
+    if modrm.opecode != 7 {
+        set_rm8(emu, &modrm, result as u8)?;
+    }
+    Ok(())
 }
 
-pub fn inc_rm32(emu: &mut Emulator, modrm: &ModRM) {
-    let value = get_rm32(emu, modrm);
-    set_rm32(emu, modrm, value + 1);
+pub fn code_81(emu: &mut Emulator) -> EmuResult {
+    let size = operand_size(emu);
+    advance_eip(emu, 1);
+    let mut modrm = ModRM::default();
+    parse_modrm(emu, &mut modrm)?;
+    let rm32 = get_rm(emu, &modrm, size)?;
+    let imm32 = get_code(emu, 0, size)?;
+    advance_eip(emu, size / 8);
+
+    let result = alu(emu, modrm.opecode, rm32, imm32, size);
+    if modrm.opecode != 7 {
+        set_rm(emu, &modrm, size, result)?;
+    }
+    Ok(())
 }
 
-pub fn code_83(emu: &mut Emulator) {
-    emu.eip += 1;
+pub fn code_83(emu: &mut Emulator) -> EmuResult {
+    let size = operand_size(emu);
+    advance_eip(emu, 1);
     let mut modrm = ModRM::default();
-    parse_modrm(emu, &mut modrm);
+    parse_modrm(emu, &mut modrm)?;
+    let rm32 = get_rm(emu, &modrm, size)?;
+    let imm8 = get_sign_code8(emu, 0)? as u32 & (u32::MAX >> (32 - size));
+    advance_eip(emu, 1);
 
-    match modrm.opecode {
+    let result = alu(emu, modrm.opecode, rm32, imm8, size);
+    if modrm.opecode != 7 {
+        set_rm(emu, &modrm, size, result)?;
+    }
+    Ok(())
+}
+
+/// Performs the shift or rotate selected by `op` (ROL, ROR, RCL, RCR, SHL, SHR, SAL,
+/// SAR in ModRM order) on a `bits`-wide operand, updates the flags and returns the
+/// result. The count is masked to 5 bits; a zero count changes nothing.
+pub fn shift(emu: &mut Emulator, op: u8, value: u32, count: u32, bits: u32) -> u32 {
+    let count = count & 0x1f;
+    if count == 0 {
+        return value;
+    }
+    let mask = u32::MAX >> (32 - bits);
+    let msb = |v: u32| (v >> (bits - 1)) & 1 == 1;
+
+    match op {
         0 => {
-            add_rm32_imm8(emu, &modrm);
+            let count = count % bits;
+            let result = ((value as u64) << count | (value as u64) >> (bits - count)) as u32 & mask;
+            let carry = result & 1 == 1;
+            set_carry(emu, carry);
+            set_overflow(emu, msb(result) ^ carry);
+            result
+        }
+        1 => {
+            let count = count % bits;
+            let result = ((value as u64) >> count | (value as u64) << (bits - count)) as u32 & mask;
+            set_carry(emu, msb(result));
+            set_overflow(emu, msb(result) ^ msb(result << 1));
+            result
+        }
+        2 | 3 => {
+            let count = count % (bits + 1);
+            let wide_mask = u64::MAX >> (63 - bits);
+            let wide = (is_carry(emu) as u64) << bits | value as u64;
+            let wide = if op == 2 {
+                (wide << count | wide >> (bits + 1 - count)) & wide_mask
+            } else {
+                (wide >> count | wide << (bits + 1 - count)) & wide_mask
+            };
+            let result = wide as u32 & mask;
+            let carry = (wide >> bits) & 1 == 1;
+            if op == 2 {
+                set_overflow(emu, msb(result) ^ carry);
+            } else {
+                let overflow = msb(value) ^ is_carry(emu);
+                set_overflow(emu, overflow);
+            }
+            set_carry(emu, carry);
+            result
         }
         5 => {
-            sub_rm32_imm8(emu, &modrm);
+            let result = value >> count;
+            update_eflags_logic(emu, result, bits);
+            set_carry(emu, (value as u64 >> (count - 1)) & 1 == 1);
+            set_overflow(emu, msb(value));
+            result
         }
         7 => {
-            cmp_rm32_imm8(emu, &modrm);
+            let signed = sign_extend(value, bits);
+            let result = (signed >> count) as u32 & mask;
+            update_eflags_logic(emu, result, bits);
+            set_carry(emu, (signed >> (count - 1)) & 1 == 1);
+            result
         }
         _ => {
-            println!("not implemented: 83 {}", modrm.opecode);
-            process::exit(1);
+            let wide = (value as u64) << count;
+            let result = wide as u32 & mask;
+            let carry = (wide >> bits) & 1 == 1;
+            update_eflags_logic(emu, result, bits);
+            set_carry(emu, carry);
+            set_overflow(emu, msb(result) ^ carry);
+            result
         }
     }
 }
 
-pub fn code_ff(emu: &mut Emulator) {
-    emu.eip += 1;
+/// Shift group: C0/C1 shift by imm8, D0/D1 by 1 and D2/D3 by CL.
+pub fn code_shift(emu: &mut Emulator) -> EmuResult {
+    let code = get_code8(emu, 0)?;
+    let size = if code & 1 == 0 { 8 } else { operand_size(emu) };
+    advance_eip(emu, 1);
+    let mut modrm = ModRM::default();
+    parse_modrm(emu, &mut modrm)?;
+    let rm = get_rm(emu, &modrm, size)?;
+    let count = match code {
+        0xC0 | 0xC1 => {
+            let count = get_code8(emu, 0)?;
+            advance_eip(emu, 1);
+            count as u32
+        }
+        0xD0 | 0xD1 => 1,
+        _ => get_register8(emu, CL) as u32,
+    };
+
+    let result = shift(emu, modrm.opecode, rm, count, size);
+    set_rm(emu, &modrm, size, result)
+}
+
+/// SHLD (0F A4/A5) and SHRD (0F AC/AD), shifting by imm8 or CL.
+pub fn double_shift(emu: &mut Emulator) -> EmuResult {
+    let size = operand_size(emu);
+    let code = get_code8(emu, 1)?;
+    advance_eip(emu, 2);
+    let mut modrm = ModRM::default();
+    parse_modrm(emu, &mut modrm)?;
+    let dest = get_rm(emu, &modrm, size)?;
+    let src = get_r(emu, &modrm, size);
+    let count = if code & 1 == 0 {
+        let count = get_code8(emu, 0)?;
+        advance_eip(emu, 1);
+        count as u32 & 0x1f
+    } else {
+        get_register8(emu, CL) as u32 & 0x1f
+    };
+    if count == 0 {
+        return Ok(());
+    }
+
+    let mask = u32::MAX >> (32 - size);
+    let (result, carry) = if code < 0xA8 {
+        let wide = (dest as u128) << size | src as u128;
+        let result = (wide << count >> size) as u32 & mask;
+        (result, (wide << (count - 1) >> (size * 2 - 1)) & 1 == 1)
+    } else {
+        let wide = (src as u128) << size | dest as u128;
+        let result = (wide >> count) as u32 & mask;
+        (result, (wide >> (count - 1)) & 1 == 1)
+    };
+    update_eflags_logic(emu, result, size);
+    set_carry(emu, carry);
+    set_overflow(emu, ((result ^ dest) >> (size - 1)) & 1 == 1);
+    set_rm(emu, &modrm, size, result)
+}
+
+/// Reads the double-width dividend AX, DX:AX or EDX:EAX.
+fn get_dividend(emu: &Emulator, size: u32) -> u64 {
+    if size == 8 {
+        get_register16(emu, EAX) as u64
+    } else {
+        (get_register(emu, EDX, size) as u64) << size | get_register(emu, EAX, size) as u64
+    }
+}
+
+/// Stores a double-width product into AX, DX:AX or EDX:EAX.
+fn set_product(emu: &mut Emulator, size: u32, value: u64) {
+    if size == 8 {
+        set_register16(emu, EAX, value as u16);
+    } else {
+        set_register(emu, EAX, size, value as u32);
+        set_register(emu, EDX, size, (value >> size) as u32);
+    }
+}
+
+/// Stores a quotient and remainder into AL:AH, AX:DX or EAX:EDX.
+fn set_quotient(emu: &mut Emulator, size: u32, quotient: u32, remainder: u32) {
+    if size == 8 {
+        set_register8(emu, AL, quotient as u8);
+        set_register8(emu, AH, remainder as u8);
+    } else {
+        set_register(emu, EAX, size, quotient);
+        set_register(emu, EDX, size, remainder);
+    }
+}
+
+fn mul(emu: &mut Emulator, value: u32, size: u32) {
+    let product = get_register(emu, EAX, size) as u64 * value as u64;
+    set_product(emu, size, product);
+    let overflow = product >> size != 0;
+    set_carry(emu, overflow);
+    set_overflow(emu, overflow);
+}
+
+/// Multiplies two signed `size`-bit values, sets CF and OF if the product does not
+/// fit in `size` bits and returns the full product.
+fn imul(emu: &mut Emulator, v1: u32, v2: u32, size: u32) -> i64 {
+    let product = sign_extend(v1, size) as i64 * sign_extend(v2, size) as i64;
+    let overflow = product != sign_extend(product as u32, size) as i64;
+    set_carry(emu, overflow);
+    set_overflow(emu, overflow);
+    product
+}
+
+fn div(emu: &mut Emulator, value: u32, size: u32) -> EmuResult {
+    let mask = u32::MAX >> (32 - size);
+    let dividend = get_dividend(emu, size);
+    if value == 0 || dividend / value as u64 > mask as u64 {
+        return Err(exception(emu, DIVIDE_ERROR));
+    }
+    let quotient = dividend / value as u64;
+    let remainder = dividend % value as u64;
+    set_quotient(emu, size, quotient as u32, remainder as u32);
+    Ok(())
+}
+
+fn idiv(emu: &mut Emulator, value: u32, size: u32) -> EmuResult {
+    let dividend = get_dividend(emu, size);
+    let dividend = match size {
+        32 => dividend as i64,
+        _ => sign_extend(dividend as u32, size * 2) as i64,
+    };
+    let divisor = sign_extend(value, size) as i64;
+    let quotient = match dividend.checked_div(divisor) {
+        Some(quotient) if quotient == sign_extend(quotient as u32, size) as i64 => quotient,
+        _ => return Err(exception(emu, DIVIDE_ERROR)),
+    };
+    let remainder = dividend % divisor;
+    set_quotient(emu, size, quotient as u32, remainder as u32);
+    Ok(())
+}
+
+fn unary_group(emu: &mut Emulator, code: u8, size: u32) -> EmuResult {
+    advance_eip(emu, 1);
     let mut modrm = ModRM::default();
-    parse_modrm(emu, &mut modrm);
+    parse_modrm(emu, &mut modrm)?;
+    let rm = get_rm(emu, &modrm, size)?;
 
     match modrm.opecode {
-        0 => {
-            inc_rm32(emu, &modrm);
+        0 | 1 => {
+            let imm = get_code(emu, 0, size)?;
+            advance_eip(emu, size / 8);
+            alu(emu, 4, rm, imm, size);
         }
-        _ => {
-            println!("not implemented: FF {}", modrm.opecode);
-            process::exit(1);
+        2 => set_rm(emu, &modrm, size, !rm)?,
+        3 => {
+            let result = alu(emu, 5, 0, rm, size);
+            set_rm(emu, &modrm, size, result)?;
         }
+        4 => mul(emu, rm, size),
+        5 => {
+            let acc = get_register(emu, EAX, size);
+            let product = imul(emu, acc, rm, size);
+            set_product(emu, size, product as u64);
+        }
+        6 => div(emu, rm, size)?,
+        7 => idiv(emu, rm, size)?,
+        _ => return Err(unimplemented_group(emu, code, &modrm)),
     }
+    Ok(())
+}
+
+pub fn code_f6(emu: &mut Emulator) -> EmuResult {
+    unary_group(emu, 0xF6, 8)
 }
 
-pub fn call_rel32(emu: &mut Emulator) {
-    let diff = get_sign_code32(emu, 1);
-    push32(emu, emu.eip as u32 + 5);
-    emu.eip += diff as usize + 5;
+pub fn code_f7(emu: &mut Emulator) -> EmuResult {
+    let size = operand_size(emu);
+    unary_group(emu, 0xF7, size)
 }
 
-pub fn ret(emu: &mut Emulator) {
-    emu.eip = pop32(emu).try_into().unwrap();
+pub fn imul_r32_rm32_imm32(emu: &mut Emulator) -> EmuResult {
+    let size = operand_size(emu);
+    advance_eip(emu, 1);
+    let mut modrm = ModRM::default();
+    parse_modrm(emu, &mut modrm)?;
+    let rm32 = get_rm(emu, &modrm, size)?;
+    let imm32 = get_code(emu, 0, size)?;
+    advance_eip(emu, size / 8);
+
+    let product = imul(emu, rm32, imm32, size);
+    set_r(emu, &modrm, size, product as u32);
+    Ok(())
 }
 
-pub fn leave(emu: &mut Emulator) {
-    let ebp: u32 = get_register32(emu, EBP);
-    set_register32(emu, ESP, ebp);
-    let value = pop32(emu);
-    set_register32(emu, EBP, value);
-    emu.eip += 1;
+pub fn imul_r32_rm32_imm8(emu: &mut Emulator) -> EmuResult {
+    let size = operand_size(emu);
+    advance_eip(emu, 1);
+    let mut modrm = ModRM::default();
+    parse_modrm(emu, &mut modrm)?;
+    let rm32 = get_rm(emu, &modrm, size)?;
+    let imm8 = get_sign_code8(emu, 0)? as u32;
+    advance_eip(emu, 1);
+
+    let product = imul(emu, rm32, imm8, size);
+    set_r(emu, &modrm, size, product as u32);
+    Ok(())
 }
 
-pub fn short_jump(emu: &mut Emulator) {
-    let diff: i8 = get_sign_code8(emu, 1) + 2;
-    if diff > 0 {
-        emu.eip += diff as usize;
-    } else {
-        emu.eip -= diff.abs() as usize;
+pub fn imul_r32_rm32(emu: &mut Emulator) -> EmuResult {
+    let size = operand_size(emu);
+    advance_eip(emu, 2);
+    let mut modrm = ModRM::default();
+    parse_modrm(emu, &mut modrm)?;
+    let rm32 = get_rm(emu, &modrm, size)?;
+    let r32 = get_r(emu, &modrm, size);
+
+    let product = imul(emu, r32, rm32, size);
+    set_r(emu, &modrm, size, product as u32);
+    Ok(())
+}
+
+/// Escape into the two-byte opcode map. Its handlers are entered with EIP still at
+/// the 0x0F byte.
+pub fn code_0f(emu: &mut Emulator) -> EmuResult {
+    let code = get_code8(emu, 1)?;
+    emu.instructions0f[code as usize](emu)
+}
+
+/// MOV r32, CRn (0F 20) and MOV CRn, r32 (0F 22). The r/m field always names a
+/// general register.
+pub fn mov_cr(emu: &mut Emulator) -> EmuResult {
+    let code = get_code8(emu, 1)?;
+    advance_eip(emu, 2);
+    let mut modrm = ModRM::default();
+    parse_modrm(emu, &mut modrm)?;
+    check_privileged(emu)?;
+    let cr = modrm.reg_index as usize;
+    if cr == 1 || cr > 4 {
+        return Err(exception(emu, INVALID_OPCODE));
+    }
+    let reg = modrm.rm as usize;
+
+    if code == 0x20 {
+        emu.registers[reg] = emu.cr[cr];
+        return Ok(());
+    }
+    let mut value = emu.registers[reg];
+    if cr == 0 {
+        value |= CR0_ET;
+        if value & CR0_PG != 0 && value & CR0_PE == 0 {
+            return Err(exception_with_code(emu, GENERAL_PROTECTION, 0));
+        }
     }
+    emu.cr[cr] = value;
+    if cr != 2 {
+        emu.tlb.flush();
+    }
+    Ok(())
 }
 
-pub fn near_jump(emu: &mut Emulator) {
-    let diff: i32 = get_sign_code32(emu, 1) + 5;
-    if diff > 0 {
-        emu.eip += diff as usize;
+/// Stores GDTR or IDTR as a 16-bit limit followed by the base, of which only 24 bits
+/// are kept with a 16-bit operand size.
+fn store_descriptor_table(emu: &mut Emulator, modrm: &ModRM, table: DescriptorTable) -> EmuResult {
+    let address = calc_linear_address(emu, modrm, 48)?;
+    let mask = if operand_size(emu) == 16 {
+        0x00ffffff
     } else {
-        emu.eip -= diff.abs() as usize;
-    }
+        0xffffffff
+    };
+    set_memory16(emu, address, table.limit as u32)?;
+    set_memory32(emu, address.wrapping_add(2), table.base & mask)
 }
 
-pub fn cmp_al_imm8(emu: &mut Emulator) {
-    let value = get_code8(emu, 1);
-    let al = get_register8(emu, AL);
-    let result = if al > value {
-        al as u64 - value as u64
+fn load_descriptor_table(emu: &mut Emulator, modrm: &ModRM) -> EmuResult<DescriptorTable> {
+    let address = calc_linear_address(emu, modrm, 48)?;
+    let mask = if operand_size(emu) == 16 {
+        0x00ffffff
     } else {
-        (al as i32 - value as i32).abs() as u64
+        0xffffffff
     };
-    update_eflags_sub(emu, al.into(), value.into(), result);
-    emu.eip += 2;
+    let limit = get_memory16(emu, address)? as u16;
+    let base = get_memory32(emu, address.wrapping_add(2))? & mask;
+    Ok(DescriptorTable { base, limit })
+}
+
+/// STR and LTR. Local descriptor tables and VERR/VERW are not supported.
+pub fn code_0f_00(emu: &mut Emulator) -> EmuResult {
+    advance_eip(emu, 2);
+    let mut modrm = ModRM::default();
+    parse_modrm(emu, &mut modrm)?;
+    if !is_protected_mode(emu) {
+        return Err(exception(emu, INVALID_OPCODE));
+    }
+
+    match modrm.opecode {
+        1 => {
+            let size = if modrm.modval == 3 {
+                operand_size(emu)
+            } else {
+                16
+            };
+            set_rm(emu, &modrm, size, emu.tr.selector as u32)
+        }
+        3 => {
+            check_privileged(emu)?;
+            let selector = get_rm(emu, &modrm, 16)?;
+            load_task_register(emu, selector as u16)
+        }
+        _ => Err(EmuError::UnimplementedOpcode {
+            eip: emu.start_eip,
+            bytes: vec![0x0F, 0x00, modrm_code(&modrm)],
+        }),
+    }
+}
+
+/// SGDT, SIDT, LGDT, LIDT, SMSW, LMSW and INVLPG.
+pub fn code_0f_01(emu: &mut Emulator) -> EmuResult {
+    advance_eip(emu, 2);
+    let mut modrm = ModRM::default();
+    parse_modrm(emu, &mut modrm)?;
+    if modrm.modval == 3 && (modrm.opecode < 4 || modrm.opecode == 7) {
+        return Err(exception(emu, INVALID_OPCODE));
+    }
+    if let 2 | 3 | 6 | 7 = modrm.opecode {
+        check_privileged(emu)?;
+    }
+
+    match modrm.opecode {
+        0 => store_descriptor_table(emu, &modrm, emu.gdtr),
+        1 => store_descriptor_table(emu, &modrm, emu.idtr),
+        2 => {
+            emu.gdtr = load_descriptor_table(emu, &modrm)?;
+            Ok(())
+        }
+        3 => {
+            emu.idtr = load_descriptor_table(emu, &modrm)?;
+            Ok(())
+        }
+        4 => {
+            let size = if modrm.modval == 3 {
+                operand_size(emu)
+            } else {
+                16
+            };
+            set_rm(emu, &modrm, size, emu.cr[0])
+        }
+        6 => {
+            // LMSW loads PE, MP, EM and TS but cannot clear PE.
+            let value = get_rm(emu, &modrm, 16)? & 0x0f;
+            emu.cr[0] = (emu.cr[0] & !0x0e) | value | (emu.cr[0] & CR0_PE);
+            Ok(())
+        }
+        7 => {
+            let offset = calc_memory_address(emu, &modrm)?;
+            let segment = override_segment(emu, default_segment(emu, &modrm));
+            let linear = get_segment_base(emu, segment).wrapping_add(offset);
+            emu.tlb.invalidate(linear);
+            Ok(())
+        }
+        _ => Err(EmuError::UnimplementedOpcode {
+            eip: emu.start_eip,
+            bytes: vec![0x0F, 0x01, modrm_code(&modrm)],
+        }),
+    }
+}
+
+pub fn jcc_rel32(emu: &mut Emulator) -> EmuResult {
+    let size = operand_size(emu);
+    let cc = get_code8(emu, 1)? & 0x0F;
+    let mut diff = 0;
+    if condition(emu, cc) {
+        diff = get_sign_code(emu, 2, size)?;
+    }
+    relative_jump(emu, 2 + size / 8, diff);
+    Ok(())
 }
 
-pub fn cmp_eax_imm32(emu: &mut Emulator) {
-    let value = get_code32(emu, 1);
-    let eax = get_register32(emu, EAX);
-    let result = eax - value;
-    update_eflags_sub(emu, eax, value, result.into());
-    emu.eip += 5;
+pub fn setcc_rm8(emu: &mut Emulator) -> EmuResult {
+    let cc = get_code8(emu, 1)? & 0x0F;
+    advance_eip(emu, 2);
+    let mut modrm = ModRM::default();
+    parse_modrm(emu, &mut modrm)?;
+    let value = condition(emu, cc);
+    set_rm8(emu, &modrm, value as u8)
 }
 
-pub fn cmp_r32_rm32(emu: &mut Emulator) {
-    emu.eip += 1;
+pub fn cmovcc_r32_rm32(emu: &mut Emulator) -> EmuResult {
+    let size = operand_size(emu);
+    let cc = get_code8(emu, 1)? & 0x0F;
+    advance_eip(emu, 2);
     let mut modrm = ModRM::default();
-    parse_modrm(emu, &mut modrm);
-    let r32 = get_r32(emu, &modrm);
-    let rm32 = get_rm32(emu, &modrm);
-    let result = if r32 > rm32 {
-        r32 as u64 - rm32 as u64
+    parse_modrm(emu, &mut modrm)?;
+    let rm32 = get_rm(emu, &modrm, size)?;
+    if condition(emu, cc) {
+        set_r(emu, &modrm, size, rm32);
+    }
+    Ok(())
+}
+
+/// MOVZX (0F B6/B7) and MOVSX (0F BE/BF).
+pub fn movx_r32_rm(emu: &mut Emulator) -> EmuResult {
+    let size = operand_size(emu);
+    let code = get_code8(emu, 1)?;
+    let src_size = if code & 1 == 0 { 8 } else { 16 };
+    advance_eip(emu, 2);
+    let mut modrm = ModRM::default();
+    parse_modrm(emu, &mut modrm)?;
+    let value = get_rm(emu, &modrm, src_size)?;
+    let value = if code & 0x08 == 0 {
+        value
     } else {
-        (r32 as i64 - rm32 as i64).abs() as u64
+        sign_extend(value, src_size) as u32
     };
-    update_eflags_sub(emu, r32, rm32, result);
+    set_r(emu, &modrm, size, value);
+    Ok(())
 }
 
-pub fn js(emu: &mut Emulator) {
-    let mut diff = 0;
-    if is_sign(emu) {
-        diff = get_sign_code8(emu, 1);
+/// Performs BT, BTS, BTR or BTC (`op` 4 to 7 as in the 0F BA group) on bit `offset`
+/// of the r/m operand. A register offset may address memory outside the operand.
+fn bit_test(emu: &mut Emulator, modrm: &ModRM, op: u8, offset: u32, size: u32) -> EmuResult {
+    let bit = offset & (size - 1);
+    let address = if modrm.modval == 3 {
+        None
+    } else {
+        let shift = size.trailing_zeros();
+        let displacement = (sign_extend(offset, size) >> shift) << (shift - 3);
+        let offset = calc_memory_address(emu, modrm)?.wrapping_add(displacement as u32);
+        let segment = override_segment(emu, default_segment(emu, modrm));
+        Some(segment_address(emu, segment, offset, size)?)
+    };
+    let value = match address {
+        Some(address) => get_memory(emu, address, size)?,
+        None => get_register(emu, modrm.rm as usize, size),
+    };
+    set_carry(emu, (value >> bit) & 1 == 1);
+
+    let result = match op {
+        5 => value | 1 << bit,
+        6 => value & !(1 << bit),
+        7 => value ^ 1 << bit,
+        _ => return Ok(()),
+    };
+    match address {
+        Some(address) => set_memory(emu, address, size, result),
+        None => {
+            set_register(emu, modrm.rm as usize, size, result);
+            Ok(())
+        }
     }
-    emu.eip += diff as usize + 2;
 }
 
-pub fn jns(emu: &mut Emulator) {
-    let mut diff = 0;
-    if !is_sign(emu) {
-        diff = get_sign_code8(emu, 1);
+/// BT (0F A3), BTS (0F AB), BTR (0F B3) and BTC (0F BB) with a register bit offset.
+pub fn bt_rm32_r32(emu: &mut Emulator) -> EmuResult {
+    let size = operand_size(emu);
+    let op = 4 + ((get_code8(emu, 1)? >> 3) & 0x03);
+    advance_eip(emu, 2);
+    let mut modrm = ModRM::default();
+    parse_modrm(emu, &mut modrm)?;
+    let offset = get_r(emu, &modrm, size);
+    bit_test(emu, &modrm, op, offset, size)
+}
+
+pub fn code_0f_ba(emu: &mut Emulator) -> EmuResult {
+    let size = operand_size(emu);
+    advance_eip(emu, 2);
+    let mut modrm = ModRM::default();
+    parse_modrm(emu, &mut modrm)?;
+    if modrm.opecode < 4 {
+        return Err(EmuError::UnimplementedOpcode {
+            eip: emu.start_eip,
+            bytes: vec![0x0F, 0xBA, modrm_code(&modrm)],
+        });
     }
-    emu.eip += diff as usize + 2;
+    let imm8 = get_code8(emu, 0)?;
+    advance_eip(emu, 1);
+    bit_test(emu, &modrm, modrm.opecode, imm8 as u32 & (size - 1), size)
 }
 
-pub fn jc(emu: &mut Emulator) {
-    let mut diff = 0;
-    if is_carry(emu) {
-        diff = get_sign_code8(emu, 1);
+/// BSF (0F BC) and BSR (0F BD). The destination is left unchanged for a zero source.
+pub fn bsf_bsr(emu: &mut Emulator) -> EmuResult {
+    let size = operand_size(emu);
+    let code = get_code8(emu, 1)?;
+    advance_eip(emu, 2);
+    let mut modrm = ModRM::default();
+    parse_modrm(emu, &mut modrm)?;
+    let value = get_rm(emu, &modrm, size)?;
+
+    set_zero(emu, value == 0);
+    if value != 0 {
+        let index = if code == 0xBC {
+            value.trailing_zeros()
+        } else {
+            31 - value.leading_zeros()
+        };
+        set_r(emu, &modrm, size, index);
     }
-    emu.eip += diff as usize + 2;
+    Ok(())
 }
 
-pub fn jnc(emu: &mut Emulator) {
-    let mut diff = 0;
-    if !is_carry(emu) {
-        diff = get_sign_code8(emu, 1);
+pub fn bswap(emu: &mut Emulator) -> EmuResult {
+    let reg = (get_code8(emu, 1)? - 0xC8) as usize;
+    emu.registers[reg] = emu.registers[reg].swap_bytes();
+    advance_eip(emu, 2);
+    Ok(())
+}
+
+/// Reports a family 6 processor with TSC and CMOV support.
+pub fn cpuid(emu: &mut Emulator) -> EmuResult {
+    let (eax, ebx, ecx, edx) = match emu.registers[EAX] {
+        // "GenuineIntel"
+        0 => (1, 0x756e6547, 0x6c65746e, 0x49656e69),
+        1 => (0x00000633, 0, 0, 1 << 4 | 1 << 15),
+        _ => (0, 0, 0, 0),
+    };
+    emu.registers[EAX] = eax;
+    emu.registers[EBX] = ebx;
+    emu.registers[ECX] = ecx;
+    emu.registers[EDX] = edx;
+    advance_eip(emu, 2);
+    Ok(())
+}
+
+pub fn rdtsc(emu: &mut Emulator) -> EmuResult {
+    emu.registers[EAX] = emu.tsc as u32;
+    emu.registers[EDX] = (emu.tsc >> 32) as u32;
+    advance_eip(emu, 2);
+    Ok(())
+}
+
+pub fn pushf(emu: &mut Emulator) -> EmuResult {
+    let size = operand_size(emu);
+    let eflags = get_eflags(emu);
+    push(emu, eflags, size)?;
+    advance_eip(emu, 1);
+    Ok(())
+}
+
+pub fn popf(emu: &mut Emulator) -> EmuResult {
+    let size = operand_size(emu);
+    let value = pop(emu, size)?;
+    let mask = writable_flags(emu, size);
+    set_eflags(emu, value, mask);
+    advance_eip(emu, 1);
+    Ok(())
+}
+
+pub fn sahf(emu: &mut Emulator) -> EmuResult {
+    let ah = get_register8(emu, AH) as u32;
+    let mask = SIGN_FLAG | ZERO_FLAG | AUX_CARRY_FLAG | PARITY_FLAG | CARRY_FLAG;
+    set_eflags(emu, ah, mask);
+    advance_eip(emu, 1);
+    Ok(())
+}
+
+pub fn lahf(emu: &mut Emulator) -> EmuResult {
+    let eflags = get_eflags(emu);
+    set_register8(emu, AH, eflags as u8);
+    advance_eip(emu, 1);
+    Ok(())
+}
+
+pub fn cmc(emu: &mut Emulator) -> EmuResult {
+    let carry = is_carry(emu);
+    set_carry(emu, !carry);
+    advance_eip(emu, 1);
+    Ok(())
+}
+
+/// CLC, STC, CLI, STI, CLD and STD (0xF8 to 0xFD).
+pub fn set_flag(emu: &mut Emulator) -> EmuResult {
+    let code = get_code8(emu, 0)?;
+    let value = code & 1 == 1;
+    match code {
+        0xF8 | 0xF9 => set_carry(emu, value),
+        0xFA | 0xFB => {
+            check_io_privilege(emu)?;
+            // STI takes effect after the next instruction.
+            if value && !is_interrupt(emu) {
+                emu.interrupt_shadow = true;
+            }
+            set_interrupt(emu, value)
+        }
+        _ => set_direction(emu, value),
     }
-    emu.eip += diff as usize + 2;
+    advance_eip(emu, 1);
+    Ok(())
 }
 
-pub fn jz(emu: &mut Emulator) {
-    let mut diff = 0;
-    if is_zero(emu) {
-        diff = get_sign_code8(emu, 1);
+pub fn code_fe(emu: &mut Emulator) -> EmuResult {
+    advance_eip(emu, 1);
+    let mut modrm = ModRM::default();
+    parse_modrm(emu, &mut modrm)?;
+
+    match modrm.opecode {
+        0 | 1 => inc_dec_rm(emu, &modrm, 8),
+        _ => Err(unimplemented_group(emu, 0xFE, &modrm)),
     }
-    emu.eip += diff as usize + 2;
 }
 
-pub fn jnz(emu: &mut Emulator) {
-    let mut diff = 0;
-    if !is_zero(emu) {
-        diff = get_sign_code8(emu, 1);
+pub fn code_ff(emu: &mut Emulator) -> EmuResult {
+    advance_eip(emu, 1);
+    let mut modrm = ModRM::default();
+    parse_modrm(emu, &mut modrm)?;
+
+    match modrm.opecode {
+        0 | 1 => inc_dec_rm(emu, &modrm, operand_size(emu)),
+        3 => call_far_m(emu, &modrm),
+        5 => jmp_far_m(emu, &modrm),
+        _ => Err(unimplemented_group(emu, 0xFF, &modrm)),
     }
-    emu.eip += diff as usize + 2;
 }
 
-pub fn jo(emu: &mut Emulator) {
+pub fn call_rel32(emu: &mut Emulator) -> EmuResult {
+    let size = operand_size(emu);
+    let diff = get_sign_code(emu, 1, size)?;
+    let next = emu.eip.wrapping_add(1 + size / 8);
+    push(emu, next, size)?;
+    relative_jump(emu, 1 + size / 8, diff);
+    Ok(())
+}
+
+pub fn ret(emu: &mut Emulator) -> EmuResult {
+    let value = pop(emu, operand_size(emu))?;
+    set_eip(emu, value);
+    Ok(())
+}
+
+pub fn leave(emu: &mut Emulator) -> EmuResult {
+    let size = operand_size(emu);
+    let ebp = get_register32(emu, EBP);
+    set_stack_pointer(emu, ebp);
+    let value = pop(emu, size)?;
+    set_register(emu, EBP, size, value);
+    advance_eip(emu, 1);
+    Ok(())
+}
+
+pub fn short_jump(emu: &mut Emulator) -> EmuResult {
+    let diff = get_sign_code8(emu, 1)?;
+    relative_jump(emu, 2, diff as i32);
+    Ok(())
+}
+
+pub fn near_jump(emu: &mut Emulator) -> EmuResult {
+    let size = operand_size(emu);
+    let diff = get_sign_code(emu, 1, size)?;
+    relative_jump(emu, 1 + size / 8, diff);
+    Ok(())
+}
+
+pub fn jcc_rel8(emu: &mut Emulator) -> EmuResult {
+    let cc = get_code8(emu, 0)? & 0x0F;
     let mut diff = 0;
-    if is_overflow(emu) {
-        diff = get_sign_code8(emu, 1);
+    if condition(emu, cc) {
+        diff = get_sign_code8(emu, 1)?;
     }
-    emu.eip += diff as usize + 2;
+    relative_jump(emu, 2, diff as i32);
+    Ok(())
 }
 
-pub fn jno(emu: &mut Emulator) {
-    let mut diff = 0;
-    if !is_overflow(emu) {
-        diff = get_sign_code8(emu, 1);
+pub fn mov_rm16_sreg(emu: &mut Emulator) -> EmuResult {
+    advance_eip(emu, 1);
+    let mut modrm = ModRM::default();
+    parse_modrm(emu, &mut modrm)?;
+    if modrm.reg_index as usize >= SEGMENT_REGISTERS_COUNT {
+        return Err(unimplemented_group(emu, 0x8C, &modrm));
+    }
+
+    let selector = emu.sregs[modrm.reg_index as usize].selector as u32;
+    let size = if modrm.modval == 3 {
+        operand_size(emu)
+    } else {
+        16
+    };
+    set_rm(emu, &modrm, size, selector)
+}
+
+pub fn mov_sreg_rm16(emu: &mut Emulator) -> EmuResult {
+    advance_eip(emu, 1);
+    let mut modrm = ModRM::default();
+    parse_modrm(emu, &mut modrm)?;
+    let index = modrm.reg_index as usize;
+    if index == CS || index >= SEGMENT_REGISTERS_COUNT {
+        return Err(unimplemented_group(emu, 0x8E, &modrm));
     }
-    emu.eip += diff as usize + 2;
+
+    let selector = get_rm(emu, &modrm, 16)?;
+    load_segment(emu, index, selector as u16)?;
+    emu.interrupt_shadow = index == SS;
+    Ok(())
 }
 
-pub fn jl(emu: &mut Emulator) {
-    let mut diff = 0;
-    if is_sign(emu) != is_overflow(emu) {
-        diff = get_sign_code8(emu, 1);
+fn opcode_segment(code: u8) -> usize {
+    ((code >> 3) & 0x07) as usize
+}
+
+/// Length of a one-byte or 0x0F-prefixed segment push/pop opcode.
+fn sreg_opcode_length(emu: &mut Emulator) -> EmuResult<u32> {
+    if get_code8(emu, 0)? == 0x0F {
+        Ok(2)
+    } else {
+        Ok(1)
     }
-    emu.eip += diff as usize + 2;
 }
 
-pub fn jle(emu: &mut Emulator) {
-    let mut diff = 0;
-    if is_zero(emu) || (is_sign(emu) != is_overflow(emu)) {
-        diff = get_sign_code8(emu, 1);
+pub fn push_sreg(emu: &mut Emulator) -> EmuResult {
+    let length = sreg_opcode_length(emu)?;
+    let index = opcode_segment(get_code8(emu, length as usize - 1)?);
+    let selector = emu.sregs[index].selector as u32;
+    push(emu, selector, operand_size(emu))?;
+    advance_eip(emu, length);
+    Ok(())
+}
+
+pub fn pop_sreg(emu: &mut Emulator) -> EmuResult {
+    let length = sreg_opcode_length(emu)?;
+    let index = opcode_segment(get_code8(emu, length as usize - 1)?);
+    let selector = pop(emu, operand_size(emu))?;
+    load_segment(emu, index, selector as u16)?;
+    emu.interrupt_shadow = index == SS;
+    advance_eip(emu, length);
+    Ok(())
+}
+
+/// Checks the destination of a protected-mode far JMP or CALL. A code segment must
+/// be enterable at the CPL; a call gate is returned with its target code segment.
+fn far_destination(emu: &mut Emulator, selector: u16) -> EmuResult<Option<(Gate, Descriptor)>> {
+    if selector & 0xfffc == 0 {
+        return Err(exception_with_code(emu, GENERAL_PROTECTION, 0));
+    }
+    let error_code = (selector & 0xfffc) as u32;
+    let cpl = current_privilege_level(emu);
+    let rpl = (selector & 3) as u8;
+    let (low, high) = read_raw_descriptor(emu, selector)?;
+    let descriptor = Descriptor::from_raw(low, high);
+
+    if descriptor.is_code() {
+        let allowed = if descriptor.is_conforming() {
+            descriptor.dpl() <= cpl
+        } else {
+            rpl <= cpl && descriptor.dpl() == cpl
+        };
+        if !allowed {
+            return Err(exception_with_code(emu, GENERAL_PROTECTION, error_code));
+        }
+        if !descriptor.is_present() {
+            return Err(exception_with_code(emu, SEGMENT_NOT_PRESENT, error_code));
+        }
+        return Ok(None);
+    }
+
+    let gate = Gate::from_raw(low, high);
+    let call_gate = matches!(gate.gate_type(), CALL_GATE16 | CALL_GATE32);
+    if !call_gate || gate.dpl() < cpl.max(rpl) {
+        return Err(exception_with_code(emu, GENERAL_PROTECTION, error_code));
+    }
+    if !gate.is_present() {
+        return Err(exception_with_code(emu, SEGMENT_NOT_PRESENT, error_code));
     }
-    emu.eip += diff as usize + 2;
+    let code = read_gate_target(emu, gate.selector)?;
+    Ok(Some((gate, code)))
 }
 
-pub fn swi(emu: &mut Emulator) {
-    let index = get_code8(emu, 1);
-    emu.eip += 2;
+fn far_jump(emu: &mut Emulator, selector: u16, offset: u32) -> EmuResult {
+    if !is_protected_mode(emu) {
+        load_segment(emu, CS, selector)?;
+        set_eip(emu, offset);
+        return Ok(());
+    }
 
-    match index {
-        0x10 => {
-            bios_video(emu);
+    let cpl = current_privilege_level(emu);
+    match far_destination(emu, selector)? {
+        None => {
+            load_segment_with_cpl(emu, CS, selector, cpl)?;
+            set_eip(emu, offset);
         }
-        _ => {
-            println!("unknown interrupt: {:x}", index);
+        Some((gate, code)) => {
+            // A jump through a call gate cannot change the privilege level.
+            if !code.is_conforming() && code.dpl() != cpl {
+                let error_code = (gate.selector & 0xfffc) as u32;
+                return Err(exception_with_code(emu, GENERAL_PROTECTION, error_code));
+            }
+            load_segment_with_cpl(emu, CS, gate.selector, cpl)?;
+            emu.eip = gate.offset;
+        }
+    }
+    Ok(())
+}
+
+/// Reads the parameters a call gate copies from the caller's stack, topmost first.
+fn read_stack_params(emu: &mut Emulator, count: u8, size: u32) -> EmuResult<Vec<u32>> {
+    let sp = get_stack_pointer(emu);
+    let mut params = Vec::with_capacity(count as usize);
+    for i in 0..count as u32 {
+        let address = segment_address(emu, SS, sp.wrapping_add(i * size / 8), size)?;
+        params.push(get_memory(emu, address, size)?);
+    }
+    Ok(params)
+}
+
+fn far_call(emu: &mut Emulator, selector: u16, offset: u32, next: u32) -> EmuResult {
+    let size = operand_size(emu);
+    let cs = emu.sregs[CS].selector as u32;
+    let gate = if is_protected_mode(emu) {
+        far_destination(emu, selector)?
+    } else {
+        None
+    };
+    let (gate, code) = match gate {
+        Some(gate) => gate,
+        None => {
+            push(emu, cs, size)?;
+            push(emu, next, size)?;
+            return far_jump(emu, selector, offset);
+        }
+    };
+
+    // A call through a gate to a more privileged non-conforming segment switches to
+    // the inner stack and copies the parameters across.
+    let cpl = current_privilege_level(emu);
+    let size = gate.size();
+    let new_cpl = if !code.is_conforming() && code.dpl() < cpl {
+        let params = read_stack_params(emu, gate.param_count, size)?;
+        let (ss, esp) = switch_stack(emu, code.dpl())?;
+        push(emu, ss, size)?;
+        push(emu, esp, size)?;
+        for &param in params.iter().rev() {
+            push(emu, param, size)?;
+        }
+        code.dpl()
+    } else {
+        cpl
+    };
+    push(emu, cs, size)?;
+    push(emu, next, size)?;
+    load_segment_with_cpl(emu, CS, gate.selector, new_cpl)?;
+    emu.eip = gate.offset;
+    Ok(())
+}
+
+/// Checks the code segment a far return or IRET goes back to and returns the
+/// privilege level it runs at, which cannot be more privileged than the CPL.
+fn return_privilege_level(emu: &mut Emulator, selector: u16) -> EmuResult<u8> {
+    if selector & 0xfffc == 0 {
+        return Err(exception_with_code(emu, GENERAL_PROTECTION, 0));
+    }
+    let error_code = (selector & 0xfffc) as u32;
+    let rpl = (selector & 3) as u8;
+    let descriptor = read_descriptor(emu, selector)?;
+    let valid = descriptor.is_code()
+        && rpl >= current_privilege_level(emu)
+        && if descriptor.is_conforming() {
+            descriptor.dpl() <= rpl
+        } else {
+            descriptor.dpl() == rpl
+        };
+    if !valid {
+        return Err(exception_with_code(emu, GENERAL_PROTECTION, error_code));
+    }
+    if !descriptor.is_present() {
+        return Err(exception_with_code(emu, SEGMENT_NOT_PRESENT, error_code));
+    }
+    Ok(rpl)
+}
+
+/// Completes a far return or IRET after EIP and CS have been popped, releasing
+/// `release` bytes of parameters. Returning to an outer privilege level also pops
+/// SS:ESP.
+fn far_return(emu: &mut Emulator, selector: u16, offset: u32, release: u32) -> EmuResult {
+    if !is_protected_mode(emu) {
+        far_jump(emu, selector, offset)?;
+        let sp = get_stack_pointer(emu).wrapping_add(release);
+        set_stack_pointer(emu, sp);
+        return Ok(());
+    }
+
+    let size = operand_size(emu);
+    let cpl = current_privilege_level(emu);
+    let rpl = return_privilege_level(emu, selector)?;
+    let sp = get_stack_pointer(emu).wrapping_add(release);
+    set_stack_pointer(emu, sp);
+    if rpl > cpl {
+        let esp = pop(emu, size)?;
+        let ss = pop(emu, size)?;
+        load_segment_with_cpl(emu, SS, ss as u16, rpl)?;
+        set_stack_pointer(emu, esp.wrapping_add(release));
+        clear_inaccessible_segments(emu, rpl);
+    }
+    load_segment_with_cpl(emu, CS, selector, rpl)?;
+    set_eip(emu, offset);
+    Ok(())
+}
+
+fn get_far_pointer(emu: &mut Emulator, modrm: &ModRM) -> EmuResult<(u16, u32)> {
+    if modrm.modval == 3 {
+        return Err(unimplemented_group(emu, 0xFF, modrm));
+    }
+    let size = operand_size(emu);
+    let address = calc_linear_address(emu, modrm, size + 16)?;
+    let offset = get_memory(emu, address, size)?;
+    let selector = get_memory16(emu, address.wrapping_add(size / 8))?;
+    Ok((selector as u16, offset))
+}
+
+pub fn jmp_far(emu: &mut Emulator) -> EmuResult {
+    let size = operand_size(emu);
+    let offset = get_code(emu, 1, size)?;
+    let selector = get_code16(emu, 1 + (size / 8) as usize)?;
+    far_jump(emu, selector as u16, offset)
+}
+
+pub fn call_far(emu: &mut Emulator) -> EmuResult {
+    let size = operand_size(emu);
+    let offset = get_code(emu, 1, size)?;
+    let selector = get_code16(emu, 1 + (size / 8) as usize)?;
+    let next = emu.eip.wrapping_add(3 + size / 8);
+    far_call(emu, selector as u16, offset, next)
+}
+
+pub fn jmp_far_m(emu: &mut Emulator, modrm: &ModRM) -> EmuResult {
+    let (selector, offset) = get_far_pointer(emu, modrm)?;
+    far_jump(emu, selector, offset)
+}
+
+pub fn call_far_m(emu: &mut Emulator, modrm: &ModRM) -> EmuResult {
+    let (selector, offset) = get_far_pointer(emu, modrm)?;
+    let next = emu.eip;
+    far_call(emu, selector, offset, next)
+}
+
+pub fn retf(emu: &mut Emulator) -> EmuResult {
+    let size = operand_size(emu);
+    let offset = pop(emu, size)?;
+    let selector = pop(emu, size)?;
+    far_return(emu, selector as u16, offset, 0)
+}
+
+pub fn retf_imm16(emu: &mut Emulator) -> EmuResult {
+    let bytes = get_code16(emu, 1)?;
+    let size = operand_size(emu);
+    let offset = pop(emu, size)?;
+    let selector = pop(emu, size)?;
+    far_return(emu, selector as u16, offset, bytes)
+}
+
+pub fn test_al_imm8(emu: &mut Emulator) -> EmuResult {
+    let value = get_code8(emu, 1)?;
+    let al = get_register8(emu, AL);
+    alu(emu, 4, al as u32, value as u32, 8);
+    advance_eip(emu, 2);
+    Ok(())
+}
+
+pub fn test_eax_imm32(emu: &mut Emulator) -> EmuResult {
+    let size = operand_size(emu);
+    let value = get_code(emu, 1, size)?;
+    let eax = get_register(emu, EAX, size);
+    alu(emu, 4, eax, value, size);
+    advance_eip(emu, 1 + size / 8);
+    Ok(())
+}
+
+fn string_size(emu: &mut Emulator) -> EmuResult<u32> {
+    if get_code8(emu, 0)? & 1 == 0 {
+        Ok(8)
+    } else {
+        Ok(operand_size(emu))
+    }
+}
+
+fn get_string_index(emu: &Emulator, reg: usize) -> u32 {
+    get_register(emu, reg, address_size(emu))
+}
+
+/// Steps ESI or EDI (SI or DI with 16-bit addressing) by one element in the direction
+/// given by DF.
+fn advance_string_index(emu: &mut Emulator, reg: usize, size: u32) {
+    let value = get_string_index(emu, reg);
+    let value = if is_direction(emu) {
+        value.wrapping_sub(size / 8)
+    } else {
+        value.wrapping_add(size / 8)
+    };
+    set_register(emu, reg, address_size(emu), value);
+}
+
+fn string_source(emu: &Emulator, size: u32) -> EmuResult<u32> {
+    let segment = override_segment(emu, DS);
+    segment_address(emu, segment, get_string_index(emu, ESI), size)
+}
+
+fn string_destination(emu: &Emulator, size: u32) -> EmuResult<u32> {
+    segment_address(emu, ES, get_string_index(emu, EDI), size)
+}
+
+/// Runs `body` once, or ECX times (CX with 16-bit addressing) under a REP prefix. For
+/// CMPS and SCAS (`compares`) REPE/REPNE also stop once ZF no longer matches. The
+/// counter and index registers are updated per iteration so a fault leaves the
+/// instruction restartable.
+fn repeat_string(
+    emu: &mut Emulator,
+    size: u32,
+    compares: bool,
+    body: fn(&mut Emulator, u32) -> EmuResult,
+) -> EmuResult {
+    let rep = match emu.decode.rep {
+        Some(rep) => rep,
+        None => return body(emu, size),
+    };
+    let counter_size = address_size(emu);
+
+    loop {
+        let count = get_register(emu, ECX, counter_size);
+        if count == 0 {
+            return Ok(());
+        }
+        body(emu, size)?;
+        set_register(emu, ECX, counter_size, count - 1);
+
+        if compares {
+            let zero = is_zero(emu);
+            if (rep == RepPrefix::Rep && !zero) || (rep == RepPrefix::Repne && zero) {
+                return Ok(());
+            }
         }
     }
 }
 
+fn movs_once(emu: &mut Emulator, size: u32) -> EmuResult {
+    let value = get_memory(emu, string_source(emu, size)?, size)?;
+    set_memory(emu, string_destination(emu, size)?, size, value)?;
+    advance_string_index(emu, ESI, size);
+    advance_string_index(emu, EDI, size);
+    Ok(())
+}
+
+fn cmps_once(emu: &mut Emulator, size: u32) -> EmuResult {
+    let src = get_memory(emu, string_source(emu, size)?, size)?;
+    let dest = get_memory(emu, string_destination(emu, size)?, size)?;
+    alu(emu, 7, src, dest, size);
+    advance_string_index(emu, ESI, size);
+    advance_string_index(emu, EDI, size);
+    Ok(())
+}
+
+fn stos_once(emu: &mut Emulator, size: u32) -> EmuResult {
+    let value = get_register(emu, EAX, size);
+    set_memory(emu, string_destination(emu, size)?, size, value)?;
+    advance_string_index(emu, EDI, size);
+    Ok(())
+}
+
+fn lods_once(emu: &mut Emulator, size: u32) -> EmuResult {
+    let value = get_memory(emu, string_source(emu, size)?, size)?;
+    set_register(emu, EAX, size, value);
+    advance_string_index(emu, ESI, size);
+    Ok(())
+}
+
+fn scas_once(emu: &mut Emulator, size: u32) -> EmuResult {
+    let value = get_register(emu, EAX, size);
+    let dest = get_memory(emu, string_destination(emu, size)?, size)?;
+    alu(emu, 7, value, dest, size);
+    advance_string_index(emu, EDI, size);
+    Ok(())
+}
+
+pub fn movs(emu: &mut Emulator) -> EmuResult {
+    let size = string_size(emu)?;
+    advance_eip(emu, 1);
+    repeat_string(emu, size, false, movs_once)
+}
+
+pub fn cmps(emu: &mut Emulator) -> EmuResult {
+    let size = string_size(emu)?;
+    advance_eip(emu, 1);
+    repeat_string(emu, size, true, cmps_once)
+}
+
+pub fn stos(emu: &mut Emulator) -> EmuResult {
+    let size = string_size(emu)?;
+    advance_eip(emu, 1);
+    repeat_string(emu, size, false, stos_once)
+}
+
+pub fn lods(emu: &mut Emulator) -> EmuResult {
+    let size = string_size(emu)?;
+    advance_eip(emu, 1);
+    repeat_string(emu, size, false, lods_once)
+}
+
+pub fn scas(emu: &mut Emulator) -> EmuResult {
+    let size = string_size(emu)?;
+    advance_eip(emu, 1);
+    repeat_string(emu, size, true, scas_once)
+}
+
+pub fn swi(emu: &mut Emulator) -> EmuResult {
+    let index = get_code8(emu, 1)?;
+    software_interrupt(emu, index, 2)
+}
+
+pub fn int3(emu: &mut Emulator) -> EmuResult {
+    software_interrupt(emu, BREAKPOINT, 1)
+}
+
+pub fn into(emu: &mut Emulator) -> EmuResult {
+    if is_overflow(emu) {
+        return software_interrupt(emu, OVERFLOW, 1);
+    }
+    advance_eip(emu, 1);
+    Ok(())
+}
+
+pub fn iret(emu: &mut Emulator) -> EmuResult {
+    let size = operand_size(emu);
+    let eip = pop(emu, size)?;
+    let cs = pop(emu, size)?;
+    let eflags = pop(emu, size)?;
+    let mask = writable_flags(emu, size);
+    far_return(emu, cs as u16, eip, 0)?;
+    set_eflags(emu, eflags, mask);
+    Ok(())
+}
+
+pub fn hlt(emu: &mut Emulator) -> EmuResult {
+    check_privileged(emu)?;
+    advance_eip(emu, 1);
+    emu.halted = true;
+    Ok(())
+}
+
 pub fn init_instructions(instructions: &mut Insts) {
-    instructions[0x01] = add_rm32_r32;
+    for i in 0..8 {
+        instructions[i * 8] = alu_rm8_r8;
+        instructions[i * 8 + 1] = alu_rm32_r32;
+        instructions[i * 8 + 2] = alu_r8_rm8;
+        instructions[i * 8 + 3] = alu_r32_rm32;
+        instructions[i * 8 + 4] = alu_al_imm8;
+        instructions[i * 8 + 5] = alu_eax_imm32;
+    }
 
-    instructions[0x3B] = cmp_r32_rm32;
-    instructions[0x3C] = cmp_al_imm8;
-    instructions[0x3D] = cmp_eax_imm32;
+    instructions[0x06] = push_sreg;
+    instructions[0x07] = pop_sreg;
+    instructions[0x0E] = push_sreg;
+    instructions[0x0F] = code_0f;
+    instructions[0x16] = push_sreg;
+    instructions[0x17] = pop_sreg;
+    instructions[0x1E] = push_sreg;
+    instructions[0x1F] = pop_sreg;
 
     for i in 0..8 {
-        instructions[0x40 + i] = inc_r32;
+        instructions[0x40 + i] = inc_dec_r32;
+        instructions[0x48 + i] = inc_dec_r32;
     }
     for i in 0..8 {
         instructions[0x50 + i] = push_r32;
@@ -372,24 +1636,43 @@ pub fn init_instructions(instructions: &mut Insts) {
     }
 
     instructions[0x68] = push_imm32;
+    instructions[0x69] = imul_r32_rm32_imm32;
     instructions[0x6A] = push_imm8;
+    instructions[0x6B] = imul_r32_rm32_imm8;
 
-    instructions[0x70] = jo;
-    instructions[0x71] = jno;
-    instructions[0x72] = jc;
-    instructions[0x73] = jnc;
-    instructions[0x74] = jz;
-    instructions[0x75] = jnz;
-    instructions[0x78] = js;
-    instructions[0x79] = jns;
-    instructions[0x7C] = jl;
-    instructions[0x7E] = jle;
+    for i in 0..16 {
+        instructions[0x70 + i] = jcc_rel8;
+    }
 
+    instructions[0x80] = code_80;
+    instructions[0x81] = code_81;
+    instructions[0x82] = code_80;
     instructions[0x83] = code_83;
     instructions[0x88] = mov_rm8_r8;
     instructions[0x89] = mov_rm32_r32;
     instructions[0x8A] = mov_r8_rm8;
     instructions[0x8B] = mov_r32_rm32;
+    instructions[0x8C] = mov_rm16_sreg;
+    instructions[0x8E] = mov_sreg_rm16;
+
+    instructions[0x9A] = call_far;
+    instructions[0x9C] = pushf;
+    instructions[0x9D] = popf;
+    instructions[0x9E] = sahf;
+    instructions[0x9F] = lahf;
+
+    instructions[0xA4] = movs;
+    instructions[0xA5] = movs;
+    instructions[0xA6] = cmps;
+    instructions[0xA7] = cmps;
+    instructions[0xA8] = test_al_imm8;
+    instructions[0xA9] = test_eax_imm32;
+    instructions[0xAA] = stos;
+    instructions[0xAB] = stos;
+    instructions[0xAC] = lods;
+    instructions[0xAD] = lods;
+    instructions[0xAE] = scas;
+    instructions[0xAF] = scas;
 
     for i in 0..8 {
         instructions[0xB0 + i] = mov_r8_imm8;
@@ -398,16 +1681,85 @@ pub fn init_instructions(instructions: &mut Insts) {
         instructions[0xB8 + i] = mov_r32_imm32;
     }
 
+    instructions[0xC0] = code_shift;
+    instructions[0xC1] = code_shift;
     instructions[0xC3] = ret;
     instructions[0xC7] = mov_rm32_imm32;
     instructions[0xC9] = leave;
+    instructions[0xCA] = retf_imm16;
+    instructions[0xCB] = retf;
 
+    instructions[0xCC] = int3;
     instructions[0xCD] = swi;
+    instructions[0xCE] = into;
+    instructions[0xCF] = iret;
 
+    instructions[0xD0] = code_shift;
+    instructions[0xD1] = code_shift;
+    instructions[0xD2] = code_shift;
+    instructions[0xD3] = code_shift;
+
+    instructions[0xE4] = in_al_imm8;
+    instructions[0xE5] = in_eax_imm8;
+    instructions[0xE6] = out_imm8_al;
+    instructions[0xE7] = out_imm8_eax;
     instructions[0xE8] = call_rel32;
     instructions[0xE9] = near_jump;
+    instructions[0xEA] = jmp_far;
     instructions[0xEB] = short_jump;
     instructions[0xEC] = in_al_dx;
+    instructions[0xED] = in_eax_dx;
     instructions[0xEE] = out_dx_al;
+    instructions[0xEF] = out_dx_eax;
+    instructions[0xF4] = hlt;
+    instructions[0xF5] = cmc;
+    instructions[0xF6] = code_f6;
+    instructions[0xF7] = code_f7;
+    for i in 0..6 {
+        instructions[0xF8 + i] = set_flag;
+    }
+    instructions[0xFE] = code_fe;
     instructions[0xFF] = code_ff;
 }
+
+/// Fills the table for the two-byte opcode map, indexed by the byte after 0x0F.
+pub fn init_instructions0f(instructions: &mut Insts) {
+    instructions[0x00] = code_0f_00;
+    instructions[0x01] = code_0f_01;
+    instructions[0x20] = mov_cr;
+    instructions[0x22] = mov_cr;
+    instructions[0x31] = rdtsc;
+
+    for i in 0..16 {
+        instructions[0x40 + i] = cmovcc_r32_rm32;
+        instructions[0x80 + i] = jcc_rel32;
+        instructions[0x90 + i] = setcc_rm8;
+    }
+
+    instructions[0xA0] = push_sreg;
+    instructions[0xA1] = pop_sreg;
+    instructions[0xA2] = cpuid;
+    instructions[0xA3] = bt_rm32_r32;
+    instructions[0xA4] = double_shift;
+    instructions[0xA5] = double_shift;
+    instructions[0xA8] = push_sreg;
+    instructions[0xA9] = pop_sreg;
+    instructions[0xAB] = bt_rm32_r32;
+    instructions[0xAC] = double_shift;
+    instructions[0xAD] = double_shift;
+    instructions[0xAF] = imul_r32_rm32;
+
+    instructions[0xB3] = bt_rm32_r32;
+    instructions[0xB6] = movx_r32_rm;
+    instructions[0xB7] = movx_r32_rm;
+    instructions[0xBA] = code_0f_ba;
+    instructions[0xBB] = bt_rm32_r32;
+    instructions[0xBC] = bsf_bsr;
+    instructions[0xBD] = bsf_bsr;
+    instructions[0xBE] = movx_r32_rm;
+    instructions[0xBF] = movx_r32_rm;
+
+    for i in 0..8 {
+        instructions[0xC8 + i] = bswap;
+    }
+}
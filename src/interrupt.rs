@@ -0,0 +1,197 @@
+use crate::descriptor::*;
+use crate::emulator::*;
+use crate::error::*;
+use crate::function::*;
+use crate::task::*;
+use crate::*;
+
+/// Pushes the interrupt frame and transfers control to the handler for `vector`,
+/// returning to `return_eip`. Returns `Ok(false)` if the real-mode vector is null,
+/// which is taken to mean that no handler is installed.
+pub fn interrupt(
+    emu: &mut Emulator,
+    vector: u8,
+    error_code: Option<u32>,
+    return_eip: u32,
+) -> EmuResult<bool> {
+    dispatch(emu, vector, error_code, return_eip, false)
+}
+
+fn dispatch(
+    emu: &mut Emulator,
+    vector: u8,
+    error_code: Option<u32>,
+    return_eip: u32,
+    software: bool,
+) -> EmuResult<bool> {
+    if is_protected_mode(emu) {
+        // A fault after the switch to the inner stack leaves the interrupted stack
+        // and privilege level in place.
+        let (ss, esp, cpl) = (emu.sregs[SS], emu.registers[ESP], emu.cpl);
+        if let Err(err) = protected_mode_interrupt(emu, vector, error_code, return_eip, software) {
+            emu.sregs[SS] = ss;
+            emu.registers[ESP] = esp;
+            emu.cpl = cpl;
+            return Err(err);
+        }
+        return Ok(true);
+    }
+
+    let offset = vector as u32 * 4;
+    if offset + 3 > emu.idtr.limit as u32 {
+        return Err(exception_with_code(emu, GENERAL_PROTECTION, offset + 2));
+    }
+    let entry = get_memory32(emu, emu.idtr.base.wrapping_add(offset))?;
+    if entry == 0 {
+        return Ok(false);
+    }
+
+    // Real mode has no error codes; the handler finds the same frame for every vector.
+    let size = if emu.sregs[CS].big { 32 } else { 16 };
+    push_interrupt_frame(emu, size, return_eip, None)?;
+    set_interrupt(emu, false);
+    set_trap(emu, false);
+    load_segment(emu, CS, (entry >> 16) as u16)?;
+    emu.eip = entry & 0xffff;
+    Ok(true)
+}
+
+fn push_interrupt_frame(
+    emu: &mut Emulator,
+    size: u32,
+    return_eip: u32,
+    error_code: Option<u32>,
+) -> EmuResult {
+    let eflags = get_eflags(emu);
+    let cs = emu.sregs[CS].selector as u32;
+    push(emu, eflags, size)?;
+    push(emu, cs, size)?;
+    push(emu, return_eip, size)?;
+    if let Some(error_code) = error_code {
+        push(emu, error_code, size)?;
+    }
+    Ok(())
+}
+
+/// Delivers an interrupt through an interrupt or trap gate in the IDT, switching to
+/// the stack in the TSS when the handler runs at a more privileged level. Software
+/// interrupts also require the gate's DPL to be at least the CPL.
+fn protected_mode_interrupt(
+    emu: &mut Emulator,
+    vector: u8,
+    error_code: Option<u32>,
+    return_eip: u32,
+    software: bool,
+) -> EmuResult {
+    let offset = vector as u32 * 8;
+    let idt_error = offset + 2;
+    if offset + 7 > emu.idtr.limit as u32 {
+        return Err(exception_with_code(emu, GENERAL_PROTECTION, idt_error));
+    }
+    let low = get_system_memory32(emu, emu.idtr.base.wrapping_add(offset))?;
+    let high = get_system_memory32(emu, emu.idtr.base.wrapping_add(offset + 4))?;
+    let gate = Gate::from_raw(low, high);
+
+    match gate.gate_type() {
+        INTERRUPT_GATE16 | TRAP_GATE16 | INTERRUPT_GATE32 | TRAP_GATE32 => {}
+        _ => return Err(exception_with_code(emu, GENERAL_PROTECTION, idt_error)),
+    }
+    let cpl = current_privilege_level(emu);
+    if software && gate.dpl() < cpl {
+        return Err(exception_with_code(emu, GENERAL_PROTECTION, idt_error));
+    }
+    if !gate.is_present() {
+        return Err(exception_with_code(emu, SEGMENT_NOT_PRESENT, idt_error));
+    }
+    let code = read_gate_target(emu, gate.selector)?;
+
+    let size = gate.size();
+    let new_cpl = if !code.is_conforming() && code.dpl() < cpl {
+        let (ss, esp) = switch_stack(emu, code.dpl())?;
+        push(emu, ss, size)?;
+        push(emu, esp, size)?;
+        code.dpl()
+    } else {
+        cpl
+    };
+    push_interrupt_frame(emu, size, return_eip, error_code)?;
+    set_trap(emu, false);
+    set_eflags(emu, 0, NESTED_TASK_FLAG);
+    // Interrupt gates disable interrupts, trap gates leave IF alone.
+    if gate.gate_type() & 1 == 0 {
+        set_interrupt(emu, false);
+    }
+    load_segment_with_cpl(emu, CS, gate.selector, new_cpl)?;
+    emu.eip = gate.offset;
+    Ok(())
+}
+
+/// Delivers the guest exception carried by `err` and returns `Ok(())`, or hands the
+/// error back if it is a host-side error or no handler is installed. Opcodes the
+/// emulator does not know raise #UD. EIP must point at the faulting instruction.
+pub fn deliver_exception(emu: &mut Emulator, err: EmuError) -> EmuResult {
+    let (vector, error_code) = match err {
+        EmuError::Exception {
+            vector, error_code, ..
+        } => (vector, error_code),
+        EmuError::UnimplementedOpcode { .. } | EmuError::UnsupportedModRM { .. } => {
+            (INVALID_OPCODE, None)
+        }
+        _ => return Err(err),
+    };
+
+    let eip = emu.eip;
+    match interrupt(emu, vector, error_code, eip) {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(err),
+        Err(EmuError::Exception { .. }) => match interrupt(emu, DOUBLE_FAULT, Some(0), eip) {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(err),
+            Err(_) => Err(EmuError::TripleFault { eip }),
+        },
+        Err(other) => Err(other),
+    }
+}
+
+/// Takes the interrupt the PIC is requesting if IF is set, returning to the current
+/// EIP. Returns whether one was taken; an IRQ whose real-mode vector is null is
+/// acknowledged and ended, so it does not hold off the IRQs below it.
+pub fn external_interrupt(emu: &mut Emulator) -> EmuResult<bool> {
+    if !is_interrupt(emu) {
+        return Ok(false);
+    }
+    let vector = {
+        let mut pic = emu.pic.borrow_mut();
+        if !pic.has_interrupt() {
+            return Ok(false);
+        }
+        pic.acknowledge()
+    };
+    let eip = emu.eip;
+    let esp = emu.registers[ESP];
+    match interrupt(emu, vector, None, eip) {
+        Ok(true) => Ok(true),
+        Ok(false) => {
+            emu.pic.borrow_mut().end_of_interrupt(vector);
+            Ok(false)
+        }
+        Err(err) => {
+            emu.registers[ESP] = esp;
+            deliver_exception(emu, err)?;
+            Ok(true)
+        }
+    }
+}
+
+/// INT n, INT3 and INTO. The return address is the next instruction.
+pub fn software_interrupt(emu: &mut Emulator, vector: u8, length: u32) -> EmuResult {
+    let next = emu.eip.wrapping_add(length);
+    if dispatch(emu, vector, None, next, true)? {
+        Ok(())
+    } else {
+        Err(EmuError::UnknownInterrupt {
+            eip: emu.start_eip,
+            vector,
+        })
+    }
+}
@@ -1,17 +1,138 @@
-use libc::{getchar, putchar};
+use std::cell::RefCell;
+use std::rc::Rc;
 
-pub fn io_in8(address: u32) -> u8 {
-    match address {
-        0x03f8 => unsafe { getchar() as u8 },
-        _ => 0,
+/// A device attached to the I/O port bus. Ports are passed as absolute addresses.
+/// Wider accesses default to consecutive byte accesses on the same device.
+pub trait IoDevice {
+    fn in8(&mut self, port: u16) -> u8;
+
+    fn out8(&mut self, port: u16, value: u8);
+
+    fn in16(&mut self, port: u16) -> u16 {
+        self.in8(port) as u16 | (self.in8(port.wrapping_add(1)) as u16) << 8
+    }
+
+    fn out16(&mut self, port: u16, value: u16) {
+        self.out8(port, value as u8);
+        self.out8(port.wrapping_add(1), (value >> 8) as u8);
+    }
+
+    fn in32(&mut self, port: u16) -> u32 {
+        self.in16(port) as u32 | (self.in16(port.wrapping_add(2)) as u32) << 16
+    }
+
+    fn out32(&mut self, port: u16, value: u32) {
+        self.out16(port, value as u16);
+        self.out16(port.wrapping_add(2), (value >> 16) as u16);
+    }
+
+    /// Called periodically with the time-stamp counter so the device can poll its
+    /// host side and raise interrupts without being accessed. A device registered
+    /// at several ranges is called once for each.
+    fn update(&mut self, _now: u64) {}
+}
+
+/// Lets one device be registered at several port ranges, or be inspected by the
+/// host while attached.
+impl<T: IoDevice> IoDevice for Rc<RefCell<T>> {
+    fn in8(&mut self, port: u16) -> u8 {
+        self.borrow_mut().in8(port)
+    }
+
+    fn out8(&mut self, port: u16, value: u8) {
+        self.borrow_mut().out8(port, value)
+    }
+
+    fn in16(&mut self, port: u16) -> u16 {
+        self.borrow_mut().in16(port)
+    }
+
+    fn out16(&mut self, port: u16, value: u16) {
+        self.borrow_mut().out16(port, value)
+    }
+
+    fn in32(&mut self, port: u16) -> u32 {
+        self.borrow_mut().in32(port)
     }
+
+    fn out32(&mut self, port: u16, value: u32) {
+        self.borrow_mut().out32(port, value)
+    }
+
+    fn update(&mut self, now: u64) {
+        self.borrow_mut().update(now)
+    }
+}
+
+struct PortRange {
+    base: u16,
+    count: u16,
+    device: Box<dyn IoDevice>,
+}
+
+/// The I/O port address space. Reads from unclaimed ports return all ones, as on a
+/// floating bus, and writes to them are dropped.
+#[derive(Default)]
+pub struct IoBus {
+    ranges: Vec<PortRange>,
 }
 
-pub fn io_out8(address: u32, value: u8) {
-    match address {
-        0x03f8 => unsafe {
-            putchar(value as i32);
-        },
-        _ => {}
+impl IoBus {
+    pub fn new() -> IoBus {
+        IoBus { ranges: Vec::new() }
+    }
+
+    /// Attaches `device` to `count` ports starting at `base`. A later registration
+    /// takes over ports that an earlier one also claims.
+    pub fn register(&mut self, base: u16, count: u16, device: Box<dyn IoDevice>) {
+        self.ranges.push(PortRange {
+            base,
+            count,
+            device,
+        });
+    }
+
+    fn device(&mut self, port: u16) -> Option<&mut Box<dyn IoDevice>> {
+        self.ranges
+            .iter_mut()
+            .rev()
+            .find(|range| port.wrapping_sub(range.base) < range.count)
+            .map(|range| &mut range.device)
+    }
+
+    /// Reads `size` bits from `port`.
+    pub fn read(&mut self, port: u16, size: u32) -> u32 {
+        match self.device(port) {
+            Some(device) => match size {
+                8 => device.in8(port) as u32,
+                16 => device.in16(port) as u32,
+                _ => device.in32(port),
+            },
+            None => u32::MAX >> (32 - size),
+        }
+    }
+
+    /// Writes the low `size` bits of `value` to `port`.
+    pub fn write(&mut self, port: u16, size: u32, value: u32) {
+        if let Some(device) = self.device(port) {
+            match size {
+                8 => device.out8(port, value as u8),
+                16 => device.out16(port, value as u16),
+                _ => device.out32(port, value),
+            }
+        }
+    }
+
+    /// Brings the device at `port` up to `now` ahead of a guest access.
+    pub fn sync(&mut self, port: u16, now: u64) {
+        if let Some(device) = self.device(port) {
+            device.update(now);
+        }
+    }
+
+    pub fn update(&mut self, now: u64) {
+        for range in self.ranges.iter_mut() {
+            range.device.update(now);
+        }
     }
 }
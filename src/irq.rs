@@ -0,0 +1,77 @@
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+#[derive(Debug, Default)]
+struct State {
+    /// The lines driven high by each source, indexed by `IrqLine::source`.
+    sources: RefCell<Vec<u16>>,
+    levels: Cell<u16>,
+    /// Lines that have risen since the interrupt controller last looked, so a
+    /// pulse shorter than its sampling interval is not missed.
+    edges: Cell<u16>,
+}
+
+/// The sixteen ISA interrupt request lines, shared between the devices that drive
+/// them and the interrupt controller that samples them. A line several devices
+/// share is high while any of them drives it high.
+#[derive(Clone, Debug, Default)]
+pub struct IrqLines {
+    state: Rc<State>,
+}
+
+impl IrqLines {
+    pub fn new() -> IrqLines {
+        IrqLines::default()
+    }
+
+    /// Returns the handle a device uses to drive line `irq`. Every call adds a
+    /// separate source; clones of the handle drive the same one.
+    pub fn line(&self, irq: u8) -> IrqLine {
+        let mut sources = self.state.sources.borrow_mut();
+        sources.push(0);
+        IrqLine {
+            lines: self.clone(),
+            irq,
+            source: sources.len() - 1,
+        }
+    }
+
+    /// The current level of every line, IRQ0 in bit 0.
+    pub fn levels(&self) -> u16 {
+        self.state.levels.get()
+    }
+
+    /// Returns the lines that have risen since the last call, IRQ0 in bit 0.
+    pub fn take_edges(&self) -> u16 {
+        self.state.edges.replace(0)
+    }
+}
+
+/// One interrupt request line.
+#[derive(Clone, Debug)]
+pub struct IrqLine {
+    lines: IrqLines,
+    irq: u8,
+    source: usize,
+}
+
+impl IrqLine {
+    pub fn irq(&self) -> u8 {
+        self.irq
+    }
+
+    pub fn set(&self, level: bool) {
+        let state = &self.lines.state;
+        let mut sources = state.sources.borrow_mut();
+        let driven = &mut sources[self.source];
+        *driven = *driven & !(1 << self.irq) | (level as u16) << self.irq;
+        let old = state.levels.get();
+        let levels = sources.iter().fold(0, |levels, driven| levels | driven);
+        state.edges.set(state.edges.get() | levels & !old);
+        state.levels.set(levels);
+    }
+
+    pub fn is_raised(&self) -> bool {
+        self.lines.levels() & (1 << self.irq) != 0
+    }
+}
@@ -0,0 +1,61 @@
+pub mod bios;
+pub mod decode;
+pub mod descriptor;
+pub mod emulator;
+pub mod error;
+pub mod flags;
+pub mod function;
+pub mod instruction;
+pub mod interrupt;
+pub mod io;
+pub mod irq;
+pub mod memory;
+pub mod modrm;
+pub mod paging;
+pub mod pic;
+pub mod pit;
+pub mod task;
+pub mod uart;
+
+pub use decode::{DecodeState, RepPrefix};
+pub use descriptor::{Descriptor, Gate};
+pub use emulator::{DescriptorTable, Emulator, Hook, SegmentRegister};
+pub use error::{EmuError, EmuResult};
+pub use flags::{FlagOp, LazyFlags};
+pub use instruction::{init_instructions, init_instructions0f, undefined, InstFunc, Insts};
+pub use io::{IoBus, IoDevice};
+pub use irq::{IrqLine, IrqLines};
+pub use memory::{MemoryBus, MmioDevice};
+pub use modrm::{calc_memory_address, parse_modrm, ModRM};
+pub use paging::Tlb;
+pub use pic::Pic;
+pub use pit::Pit;
+pub use uart::{SerialBackend, StreamBackend, Uart};
+
+pub const EAX: usize = 0;
+pub const ECX: usize = 1;
+pub const EDX: usize = 2;
+pub const EBX: usize = 3;
+pub const ESP: usize = 4;
+pub const EBP: usize = 5;
+pub const ESI: usize = 6;
+pub const EDI: usize = 7;
+pub const AL: usize = EAX;
+pub const CL: usize = ECX;
+pub const DL: usize = EDX;
+pub const BL: usize = EBX;
+pub const AH: usize = AL + 4;
+pub const CH: usize = CL + 4;
+pub const DH: usize = DL + 4;
+pub const BH: usize = BL + 4;
+pub const REGISTERS_NAME: [&str; 8] = ["EAX", "ECX", "EDX", "EBX", "ESP", "EBP", "ESI", "EDI"];
+pub const REGISTERS_COUNT: usize = 8;
+
+pub const ES: usize = 0;
+pub const CS: usize = 1;
+pub const SS: usize = 2;
+pub const DS: usize = 3;
+pub const FS: usize = 4;
+pub const GS: usize = 5;
+pub const SEGMENT_REGISTERS_NAME: [&str; 6] = ["ES", "CS", "SS", "DS", "FS", "GS"];
+pub const SEGMENT_REGISTERS_COUNT: usize = 6;
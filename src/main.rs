@@ -1,61 +1,62 @@
 use clap::{App, Arg};
+use std::cell::RefCell;
 use std::fs;
+use std::io;
 use std::path::Path;
 use std::process;
+use std::rc::Rc;
 
-mod bios;
-mod emulator;
-mod function;
-mod instruction;
-mod io;
-mod modrm;
+use x86emu::function::*;
+use x86emu::uart::COM_PORTS;
+use x86emu::*;
 
-use emulator::*;
-use function::*;
-use instruction::*;
-
-const EAX: usize = 0;
-// const ECX: usize = 1;
-const EDX: usize = 2;
-const EBX: usize = 3;
-const ESP: usize = 4;
-const EBP: usize = 5;
-// const ESI: usize = 6;
-// const EDI: usize = 7;
-const AL: usize = EAX;
-// const CL: usize = ECX;
-// const DL: usize = EDX;
-const BL: usize = EBX;
-const AH: usize = AL + 4;
-// const CH: usize = CL + 4;
-// const DH: usize = DL + 4;
-// const BH: usize = BL + 4;
-const REGISTERS_NAME: [&str; 8] = ["EAX", "ECX", "EDX", "EBX", "ESP", "EBP", "ESI", "EDI"];
-const REGISTERS_COUNT: usize = 8;
 const MEMORY_SIZE: usize = 1024 * 1024;
 
-fn create_emu(eip: usize, esp: u32) -> Emulator {
-    let mut registers = [0; REGISTERS_COUNT];
-    registers[ESP] = esp;
-    return Emulator {
-        registers: registers,
-        eflags: 0,
-        memory: Vec::new(),
-        eip: eip,
-    };
+const COM_NAMES: [&str; 4] = ["com1", "com2", "com3", "com4"];
+
+/// Opens a serial backend from `stdio`, `file:PATH`, `pipe:INPUT,OUTPUT` or
+/// `unix:PATH`.
+fn serial_backend(spec: &str) -> io::Result<StreamBackend> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidInput, "unknown serial backend");
+    if spec == "stdio" {
+        return Ok(StreamBackend::stdio());
+    }
+    let mut parts = spec.splitn(2, ':');
+    let kind = parts.next().unwrap_or("");
+    let path = parts.next().ok_or_else(invalid)?;
+    match kind {
+        "file" => StreamBackend::file(path),
+        "pipe" => {
+            let mut paths = path.splitn(2, ',');
+            let input = paths.next().unwrap_or("");
+            let output = paths.next().ok_or_else(invalid)?;
+            StreamBackend::pipe(input, output)
+        }
+        "unix" => StreamBackend::unix_socket(path),
+        _ => Err(invalid()),
+    }
 }
 
 fn dump_registers(emu: &Emulator) {
-    for i in 0..REGISTERS_COUNT {
-        println!("{} = {:x}", REGISTERS_NAME[i], emu.registers[i]);
+    for (name, value) in REGISTERS_NAME.iter().zip(emu.registers.iter()) {
+        println!("{} = {:x}", name, value);
     }
     println!("EIP = {:x}", emu.eip);
+    for (name, sreg) in SEGMENT_REGISTERS_NAME.iter().zip(emu.sregs.iter()) {
+        println!("{} = {:x}", name, sreg.selector);
+    }
 }
 
 fn main() {
     let matches = App::new("x86emu")
         .arg(Arg::with_name("output").index(1))
-        .arg(Arg::new("quiet").short('q').long("quiet"))
+        .arg(Arg::with_name("quiet").short('q').long("quiet"))
+        .arg(Arg::with_name("real").short('r').long("real"))
+        .args(
+            COM_NAMES
+                .iter()
+                .map(|name| Arg::with_name(name).long(name).takes_value(true)),
+        )
         .get_matches();
 
     let output = match matches.value_of("output") {
@@ -66,46 +67,73 @@ fn main() {
         }
     };
 
-    let quiet = if matches.is_present("quiet") {
-        true
+    let quiet = matches.is_present("quiet");
+
+    let mut emu = if matches.is_present("real") {
+        Emulator::new_real_mode(MEMORY_SIZE, 0x7c00, 0x7c00)
     } else {
-        false
+        Emulator::new(MEMORY_SIZE, 0x7c00, 0x7c00)
     };
 
-    let mut emu = create_emu(0x7c00, 0x7c00);
-
     let path = Path::new(&output);
     let display = path.display();
     let binary = match fs::read(path) {
-        Err(why) => panic!("couldn't read {}: {}", display, why),
+        Err(why) => {
+            eprintln!("couldn't read {}: {}", display, why);
+            process::exit(1);
+        }
         Ok(binary) => binary,
     };
-    emu.memory = vec![0; 0x7c00];
-    emu.memory.extend(binary);
-
-    let mut instructions: Insts = [undefined; 256];
-    init_instructions(&mut instructions);
-
-    while emu.eip < MEMORY_SIZE {
-        let code = get_code8(&emu, 0) as usize;
-        // dump_registers(&emu);
-
-        if !quiet {
-            println!("EIP = {}, Code = {:x}", emu.eip, code);
-        }
+    if let Err(err) = emu.load(0x7c00, &binary) {
+        eprintln!("couldn't load {}: {}", display, err);
+        process::exit(1);
+    }
+    // COM1 talks to the terminal unless told otherwise.
+    for (i, name) in COM_NAMES.iter().enumerate() {
+        let spec = match matches.value_of(name) {
+            Some(spec) => spec,
+            None if i == 0 => "stdio",
+            None => continue,
+        };
+        let backend = match serial_backend(spec) {
+            Ok(backend) => backend,
+            Err(why) => {
+                eprintln!("couldn't open {} backend {}: {}", name, spec, why);
+                process::exit(1);
+            }
+        };
+        let (base, irq) = COM_PORTS[i];
+        let uart = Uart::new(base, emu.irq_lines.line(irq), Box::new(backend));
+        emu.io
+            .register(base, 8, Box::new(Rc::new(RefCell::new(uart))));
+    }
+    if let Err(err) = bios::install_bios(&mut emu) {
+        eprintln!("couldn't install BIOS: {}", err);
+        process::exit(1);
+    }
 
-        if instructions[code] as usize == undefined as usize {
-            println!("Not implemented: {:x}", code);
-            break;
+    let mut status = 0;
+    while (emu.eip as usize) < MEMORY_SIZE {
+        if !quiet && !emu.halted {
+            if let Ok(code) = get_code8(&mut emu, 0) {
+                println!("EIP = {}, Code = {:x}", emu.eip, code);
+            }
         }
 
-        instructions[code](&mut emu);
-
-        if emu.eip == 0x00 {
-            println!("end of program.");
-            break;
+        match emu.step() {
+            Ok(true) => {}
+            Ok(false) => {
+                println!("end of program.");
+                break;
+            }
+            Err(err) => {
+                println!("{}", err);
+                status = 1;
+                break;
+            }
         }
     }
 
     dump_registers(&emu);
+    process::exit(status);
 }
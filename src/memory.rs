@@ -0,0 +1,142 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// A device mapped into the physical address space. Offsets are relative to the
+/// start of the region; wider accesses are made of byte accesses.
+pub trait MmioDevice {
+    fn read8(&mut self, offset: u32) -> u8;
+
+    fn write8(&mut self, offset: u32, value: u8);
+}
+
+/// Lets the host keep a handle on a mapped device.
+impl<T: MmioDevice> MmioDevice for Rc<RefCell<T>> {
+    fn read8(&mut self, offset: u32) -> u8 {
+        self.borrow_mut().read8(offset)
+    }
+
+    fn write8(&mut self, offset: u32, value: u8) {
+        self.borrow_mut().write8(offset, value)
+    }
+}
+
+enum RegionKind {
+    Ram(Vec<u8>),
+    /// Reads return the image; guest writes are ignored.
+    Rom(Vec<u8>),
+    Mmio(Box<dyn MmioDevice>),
+}
+
+struct Region {
+    base: u32,
+    size: u32,
+    kind: RegionKind,
+}
+
+/// The physical address space: RAM from address 0, overlaid by RAM, ROM and MMIO
+/// regions registered at fixed ranges. A later region takes precedence over an
+/// earlier one and over the base RAM.
+pub struct MemoryBus {
+    ram: Vec<u8>,
+    regions: Vec<Region>,
+    /// Lowest address covered by a region, so plain RAM accesses skip the search.
+    regions_start: u32,
+}
+
+impl MemoryBus {
+    /// Creates a bus with `size` bytes of zeroed RAM at address 0.
+    pub fn new(size: usize) -> MemoryBus {
+        MemoryBus {
+            ram: vec![0; size],
+            regions: Vec::new(),
+            regions_start: u32::MAX,
+        }
+    }
+
+    /// Size of the base RAM.
+    pub fn len(&self) -> usize {
+        self.ram.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ram.is_empty()
+    }
+
+    /// The base RAM, without any regions mapped over it.
+    pub fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    pub fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn add_region(&mut self, base: u32, size: u32, kind: RegionKind) {
+        self.regions_start = self.regions_start.min(base);
+        self.regions.push(Region { base, size, kind });
+    }
+
+    /// Maps `size` bytes of zeroed RAM at `base`.
+    pub fn add_ram(&mut self, base: u32, size: u32) {
+        self.add_region(base, size, RegionKind::Ram(vec![0; size as usize]));
+    }
+
+    /// Maps a write-protected copy of `image` at `base`.
+    pub fn add_rom(&mut self, base: u32, image: Vec<u8>) {
+        let size = image.len() as u32;
+        self.add_region(base, size, RegionKind::Rom(image));
+    }
+
+    /// Maps `device` over `size` bytes at `base`.
+    pub fn add_mmio(&mut self, base: u32, size: u32, device: Box<dyn MmioDevice>) {
+        self.add_region(base, size, RegionKind::Mmio(device));
+    }
+
+    fn region(&mut self, address: u32) -> Option<(&mut RegionKind, u32)> {
+        self.regions
+            .iter_mut()
+            .rev()
+            .find(|region| address.wrapping_sub(region.base) < region.size)
+            .map(|region| (&mut region.kind, address - region.base))
+    }
+
+    /// Reads a byte, or returns `None` if nothing is mapped at `address`.
+    #[inline]
+    pub fn read8(&mut self, address: u32) -> Option<u8> {
+        if address < self.regions_start {
+            return self.ram.get(address as usize).copied();
+        }
+        match self.region(address) {
+            Some((RegionKind::Ram(data), offset)) | Some((RegionKind::Rom(data), offset)) => {
+                Some(data[offset as usize])
+            }
+            Some((RegionKind::Mmio(device), offset)) => Some(device.read8(offset)),
+            None => self.ram.get(address as usize).copied(),
+        }
+    }
+
+    /// Writes a byte, returning false if nothing is mapped at `address`. Writes to
+    /// ROM are dropped.
+    #[inline]
+    pub fn write8(&mut self, address: u32, value: u8) -> bool {
+        if address < self.regions_start {
+            return match self.ram.get_mut(address as usize) {
+                Some(byte) => {
+                    *byte = value;
+                    true
+                }
+                None => false,
+            };
+        }
+        match self.region(address) {
+            Some((RegionKind::Ram(data), offset)) => data[offset as usize] = value,
+            Some((RegionKind::Rom(_), _)) => {}
+            Some((RegionKind::Mmio(device), offset)) => device.write8(offset, value),
+            None => match self.ram.get_mut(address as usize) {
+                Some(byte) => *byte = value,
+                None => return false,
+            },
+        }
+        true
+    }
+}
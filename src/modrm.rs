@@ -1,8 +1,9 @@
-use std::process;
-
 use crate::emulator::*;
+use crate::error::*;
 use crate::function::*;
+use crate::*;
 
+#[derive(Default)]
 pub struct ModRM {
     pub modval: u8,
     pub opecode: u8,
@@ -10,109 +11,199 @@ pub struct ModRM {
     pub rm: u8,
     pub sib: u8,
     pub disp8: i8,
+    pub disp16: u16,
     pub disp32: u32,
 }
 
-impl Default for ModRM {
-    fn default() -> ModRM {
-        ModRM {
-            modval: 0,
-            opecode: 0,
-            reg_index: 0,
-            rm: 0,
-            sib: 0,
-            disp8: 0,
-            disp32: 0,
-        }
-    }
-}
-
-pub fn parse_modrm(emu: &mut Emulator, modrm: &mut ModRM) {
-    let code = get_code8(emu, 0);
+pub fn parse_modrm(emu: &mut Emulator, modrm: &mut ModRM) -> EmuResult {
+    let code = get_code8(emu, 0)?;
     modrm.modval = (code & 0xC0) >> 6;
     modrm.opecode = (code & 0x38) >> 3;
     modrm.reg_index = (code & 0x38) >> 3;
     modrm.rm = code & 0x07;
 
-    emu.eip += 1;
+    advance_eip(emu, 1);
+
+    if address_size(emu) == 16 {
+        if (modrm.modval == 0 && modrm.rm == 6) || modrm.modval == 2 {
+            modrm.disp16 = get_code16(emu, 0)? as u16;
+            advance_eip(emu, 2);
+        } else if modrm.modval == 1 {
+            modrm.disp8 = get_sign_code8(emu, 0)?;
+            advance_eip(emu, 1);
+        }
+        return Ok(());
+    }
 
     if modrm.modval != 3 && modrm.rm == 4 {
-        modrm.sib = get_code8(emu, 0);
-        emu.eip += 1;
+        modrm.sib = get_code8(emu, 0)?;
+        advance_eip(emu, 1);
     }
 
-    if (modrm.modval == 0 && modrm.rm == 5) || modrm.modval == 2 {
-        modrm.disp32 = get_sign_code32(emu, 0) as u32;
-        emu.eip += 4;
+    let no_base = modrm.modval == 0 && modrm.rm == 4 && (modrm.sib & 0x07) == 5;
+    if (modrm.modval == 0 && modrm.rm == 5) || no_base || modrm.modval == 2 {
+        modrm.disp32 = get_sign_code32(emu, 0)? as u32;
+        advance_eip(emu, 4);
     } else if modrm.modval == 1 {
-        modrm.disp8 = get_sign_code8(emu, 0);
-        emu.eip += 1;
+        modrm.disp8 = get_sign_code8(emu, 0)?;
+        advance_eip(emu, 1);
+    }
+    Ok(())
+}
+
+pub fn modrm_code(modrm: &ModRM) -> u8 {
+    (modrm.modval << 6) | (modrm.reg_index << 3) | modrm.rm
+}
+
+fn unsupported_modrm(emu: &Emulator, modrm: &ModRM) -> EmuError {
+    EmuError::UnsupportedModRM {
+        eip: emu.start_eip,
+        modrm: modrm_code(modrm),
+    }
+}
+
+fn calc_sib(emu: &Emulator, modrm: &ModRM) -> u32 {
+    let scale = modrm.sib >> 6;
+    let index = (modrm.sib >> 3) & 0x07;
+    let base = modrm.sib & 0x07;
+
+    let mut address = if modrm.modval == 0 && base == 5 {
+        modrm.disp32
+    } else {
+        get_register32(emu, base as usize)
+    };
+    if index != 4 {
+        address = address.wrapping_add(get_register32(emu, index as usize) << scale);
+    }
+    address
+}
+
+fn calc_memory_address16(emu: &Emulator, modrm: &ModRM) -> u32 {
+    let bx = get_register16(emu, EBX) as u32;
+    let bp = get_register16(emu, EBP) as u32;
+    let si = get_register16(emu, ESI) as u32;
+    let di = get_register16(emu, EDI) as u32;
+
+    let base = match modrm.rm {
+        0 => bx + si,
+        1 => bx + di,
+        2 => bp + si,
+        3 => bp + di,
+        4 => si,
+        5 => di,
+        6 if modrm.modval == 0 => modrm.disp16 as u32,
+        6 => bp,
+        _ => bx,
+    };
+    let disp = match modrm.modval {
+        1 => modrm.disp8 as u32,
+        2 => modrm.disp16 as u32,
+        _ => 0,
+    };
+    base.wrapping_add(disp) & 0xffff
+}
+
+/// Returns the segment an operand is addressed through by default: SS when the
+/// address is based on (E)BP or ESP, DS otherwise.
+pub fn default_segment(emu: &Emulator, modrm: &ModRM) -> usize {
+    let stack_based = if address_size(emu) == 16 {
+        modrm.rm == 2 || modrm.rm == 3 || (modrm.rm == 6 && modrm.modval != 0)
+    } else if modrm.rm == 4 {
+        let base = modrm.sib & 0x07;
+        base == 4 || (base == 5 && modrm.modval != 0)
+    } else {
+        modrm.rm == 5 && modrm.modval != 0
+    };
+
+    if stack_based {
+        SS
+    } else {
+        DS
     }
 }
 
-pub fn calc_memory_address(emu: &mut Emulator, modrm: &ModRM) -> u32 {
+/// Calculates the effective address (the offset within the segment) of a memory operand.
+pub fn calc_memory_address(emu: &mut Emulator, modrm: &ModRM) -> EmuResult<u32> {
+    if modrm.modval != 3 && address_size(emu) == 16 {
+        return Ok(calc_memory_address16(emu, modrm));
+    }
+
     if modrm.modval == 0 {
         if modrm.rm == 4 {
-            println!("not implemented ModRM mod = 0, rm = 4");
-            process::exit(1);
+            Ok(calc_sib(emu, modrm))
         } else if modrm.rm == 5 {
-            return modrm.disp32;
+            Ok(modrm.disp32)
         } else {
-            return get_register32(emu, modrm.rm as usize);
+            Ok(get_register32(emu, modrm.rm as usize))
         }
     } else if modrm.modval == 1 {
         if modrm.rm == 4 {
-            println!("not implemented ModRM mod = 1, rm = 4");
-            process::exit(1);
+            Ok(calc_sib(emu, modrm).wrapping_add(modrm.disp8 as u32))
         } else {
-            return get_register32(emu, modrm.rm as usize) + modrm.disp8 as u32;
+            Ok(get_register32(emu, modrm.rm as usize).wrapping_add(modrm.disp8 as u32))
         }
     } else if modrm.modval == 2 {
         if modrm.rm == 4 {
-            println!("not implemented ModRM mod = 2, rm = 4");
-            process::exit(1);
+            Ok(calc_sib(emu, modrm).wrapping_add(modrm.disp32))
         } else {
-            return get_register32(emu, modrm.rm as usize) + modrm.disp32;
+            Ok(get_register32(emu, modrm.rm as usize).wrapping_add(modrm.disp32))
         }
     } else {
-        println!("not implemented ModRM mod = 3");
-        process::exit(1);
+        Err(unsupported_modrm(emu, modrm))
     }
 }
 
-pub fn set_rm8(emu: &mut Emulator, modrm: &ModRM, value: u8) {
+/// Calculates the linear address of a `size`-bit memory operand in its default or
+/// overriding segment, checking it against the segment limit.
+pub fn calc_linear_address(emu: &mut Emulator, modrm: &ModRM, size: u32) -> EmuResult<u32> {
+    let offset = calc_memory_address(emu, modrm)?;
+    let segment = override_segment(emu, default_segment(emu, modrm));
+    segment_address(emu, segment, offset, size)
+}
+
+pub fn set_rm8(emu: &mut Emulator, modrm: &ModRM, value: u8) -> EmuResult {
     if modrm.modval == 3 {
         set_register8(emu, modrm.rm as usize, value);
+        Ok(())
     } else {
-        let address = calc_memory_address(emu, modrm);
-        set_memory8(emu, address, value as u32);
+        let address = calc_linear_address(emu, modrm, 8)?;
+        set_memory8(emu, address, value as u32)
     }
 }
 
-pub fn set_rm32(emu: &mut Emulator, modrm: &ModRM, value: u32) {
+pub fn set_rm32(emu: &mut Emulator, modrm: &ModRM, value: u32) -> EmuResult {
+    set_rm(emu, modrm, 32, value)
+}
+
+pub fn set_rm(emu: &mut Emulator, modrm: &ModRM, size: u32, value: u32) -> EmuResult {
     if modrm.modval == 3 {
-        set_register32(emu, modrm.rm as usize, value);
+        set_register(emu, modrm.rm as usize, size, value);
+        Ok(())
     } else {
-        let address = calc_memory_address(emu, modrm);
-        set_memory32(emu, address, value);
+        let address = calc_linear_address(emu, modrm, size)?;
+        set_memory(emu, address, size, value)
     }
 }
 
-pub fn get_rm8(emu: &mut Emulator, modrm: &ModRM) -> u8 {
+pub fn get_rm8(emu: &mut Emulator, modrm: &ModRM) -> EmuResult<u8> {
     if modrm.modval == 3 {
-        get_register8(emu, modrm.rm as usize)
+        Ok(get_register8(emu, modrm.rm as usize))
     } else {
-        let address = calc_memory_address(emu, modrm);
-        get_memory8(emu, address) as u8
+        let address = calc_linear_address(emu, modrm, 8)?;
+        Ok(get_memory8(emu, address)? as u8)
     }
 }
 
-pub fn get_rm32(emu: &mut Emulator, modrm: &ModRM) -> u32 {
+pub fn get_rm32(emu: &mut Emulator, modrm: &ModRM) -> EmuResult<u32> {
+    get_rm(emu, modrm, 32)
+}
+
+pub fn get_rm(emu: &mut Emulator, modrm: &ModRM, size: u32) -> EmuResult<u32> {
     if modrm.modval == 3 {
-        return get_register32(emu, modrm.rm as usize);
+        Ok(get_register(emu, modrm.rm as usize, size))
     } else {
-        let address = calc_memory_address(emu, modrm);
-        return get_memory32(emu, address);
+        let address = calc_linear_address(emu, modrm, size)?;
+        get_memory(emu, address, size)
     }
 }
 
@@ -124,6 +215,10 @@ pub fn set_r32(emu: &mut Emulator, modrm: &ModRM, value: u32) {
     set_register32(emu, modrm.reg_index as usize, value);
 }
 
+pub fn set_r(emu: &mut Emulator, modrm: &ModRM, size: u32, value: u32) {
+    set_register(emu, modrm.reg_index as usize, size, value);
+}
+
 pub fn get_r8(emu: &Emulator, modrm: &ModRM) -> u8 {
     get_register8(emu, modrm.reg_index as usize)
 }
@@ -131,3 +226,7 @@ pub fn get_r8(emu: &Emulator, modrm: &ModRM) -> u8 {
 pub fn get_r32(emu: &Emulator, modrm: &ModRM) -> u32 {
     get_register32(emu, modrm.reg_index as usize)
 }
+
+pub fn get_r(emu: &Emulator, modrm: &ModRM, size: u32) -> u32 {
+    get_register(emu, modrm.reg_index as usize, size)
+}
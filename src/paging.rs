@@ -0,0 +1,186 @@
+use crate::emulator::*;
+use crate::error::*;
+use crate::function::*;
+
+pub const PAGE_PRESENT: u32 = 1;
+pub const PAGE_WRITABLE: u32 = 1 << 1;
+pub const PAGE_USER: u32 = 1 << 2;
+pub const PAGE_ACCESSED: u32 = 1 << 5;
+pub const PAGE_DIRTY: u32 = 1 << 6;
+/// Page size bit of a page directory entry, mapping a 4MB page when CR4.PSE is set.
+pub const PAGE_SIZE: u32 = 1 << 7;
+
+pub const CR4_PSE: u32 = 1 << 4;
+
+const PF_PROTECTION: u32 = 1;
+const PF_WRITE: u32 = 1 << 1;
+const PF_USER: u32 = 1 << 2;
+
+const TLB_SIZE: usize = 256;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct TlbEntry {
+    valid: bool,
+    page: u32,
+    frame: u32,
+    writable: bool,
+    user: bool,
+    /// The dirty bit is already set, so writes need no page walk.
+    dirty: bool,
+}
+
+/// A direct-mapped cache of 4KB page translations. 4MB pages are cached per 4KB
+/// page.
+#[derive(Clone, Debug)]
+pub struct Tlb {
+    entries: Vec<TlbEntry>,
+}
+
+impl Tlb {
+    pub fn new() -> Tlb {
+        Tlb {
+            entries: vec![TlbEntry::default(); TLB_SIZE],
+        }
+    }
+
+    pub fn flush(&mut self) {
+        for entry in self.entries.iter_mut() {
+            entry.valid = false;
+        }
+    }
+
+    /// Drops the translation of the page containing `linear`, as INVLPG does.
+    pub fn invalidate(&mut self, linear: u32) {
+        let page = linear >> 12;
+        let entry = &mut self.entries[page as usize % TLB_SIZE];
+        if entry.page == page {
+            entry.valid = false;
+        }
+    }
+}
+
+impl Default for Tlb {
+    fn default() -> Tlb {
+        Tlb::new()
+    }
+}
+
+pub fn is_paging(emu: &Emulator) -> bool {
+    emu.cr[0] & CR0_PG != 0
+}
+
+fn is_allowed(emu: &Emulator, writable: bool, user_page: bool, write: bool, user: bool) -> bool {
+    if user && !user_page {
+        return false;
+    }
+    // Supervisor writes ignore R/W unless CR0.WP is set.
+    !write || writable || (!user && emu.cr[0] & CR0_WP == 0)
+}
+
+fn page_fault(
+    emu: &mut Emulator,
+    linear: u32,
+    protection: bool,
+    write: bool,
+    user: bool,
+) -> EmuError {
+    emu.cr[2] = linear;
+    let mut error_code = 0;
+    if protection {
+        error_code |= PF_PROTECTION;
+    }
+    if write {
+        error_code |= PF_WRITE;
+    }
+    if user {
+        error_code |= PF_USER;
+    }
+    exception_with_code(emu, PAGE_FAULT, error_code)
+}
+
+/// Translates a linear address to a physical one for a read or write at the
+/// current privilege level, walking the page tables on a TLB miss.
+pub fn translate(emu: &mut Emulator, linear: u32, write: bool) -> EmuResult<u32> {
+    if !is_paging(emu) {
+        return Ok(linear);
+    }
+    let user = current_privilege_level(emu) == 3;
+    translate_as(emu, linear, write, user)
+}
+
+/// Translates a linear address for a user or supervisor access, whatever the CPL.
+/// Descriptor table and TSS accesses are always supervisor accesses.
+pub fn translate_as(emu: &mut Emulator, linear: u32, write: bool, user: bool) -> EmuResult<u32> {
+    if !is_paging(emu) {
+        return Ok(linear);
+    }
+
+    let page = linear >> 12;
+    let entry = emu.tlb.entries[page as usize % TLB_SIZE];
+    if entry.valid
+        && entry.page == page
+        && (!write || entry.dirty)
+        && is_allowed(emu, entry.writable, entry.user, write, user)
+    {
+        return Ok(entry.frame | (linear & 0xfff));
+    }
+
+    let pde_address = (emu.cr[3] & 0xfffff000) + ((linear >> 22) << 2);
+    let pde = get_physical32(emu, pde_address)?;
+    if pde & PAGE_PRESENT == 0 {
+        return Err(page_fault(emu, linear, false, write, user));
+    }
+
+    let entry = if pde & PAGE_SIZE != 0 && emu.cr[4] & CR4_PSE != 0 {
+        let writable = pde & PAGE_WRITABLE != 0;
+        let user_page = pde & PAGE_USER != 0;
+        if !is_allowed(emu, writable, user_page, write, user) {
+            return Err(page_fault(emu, linear, true, write, user));
+        }
+        let pde = update_entry(emu, pde_address, pde, write)?;
+        TlbEntry {
+            valid: true,
+            page,
+            frame: (pde & 0xffc00000) | (linear & 0x003ff000),
+            writable,
+            user: user_page,
+            dirty: pde & PAGE_DIRTY != 0,
+        }
+    } else {
+        let pte_address = (pde & 0xfffff000) + (((linear >> 12) & 0x3ff) << 2);
+        let pte = get_physical32(emu, pte_address)?;
+        if pte & PAGE_PRESENT == 0 {
+            return Err(page_fault(emu, linear, false, write, user));
+        }
+        let writable = pde & pte & PAGE_WRITABLE != 0;
+        let user_page = pde & pte & PAGE_USER != 0;
+        if !is_allowed(emu, writable, user_page, write, user) {
+            return Err(page_fault(emu, linear, true, write, user));
+        }
+        update_entry(emu, pde_address, pde, false)?;
+        let pte = update_entry(emu, pte_address, pte, write)?;
+        TlbEntry {
+            valid: true,
+            page,
+            frame: pte & 0xfffff000,
+            writable,
+            user: user_page,
+            dirty: pte & PAGE_DIRTY != 0,
+        }
+    };
+
+    emu.tlb.entries[page as usize % TLB_SIZE] = entry;
+    Ok(entry.frame | (linear & 0xfff))
+}
+
+/// Sets the accessed bit, and the dirty bit for a write, of a paging entry.
+fn update_entry(emu: &mut Emulator, address: u32, entry: u32, write: bool) -> EmuResult<u32> {
+    let mut updated = entry | PAGE_ACCESSED;
+    if write {
+        updated |= PAGE_DIRTY;
+    }
+    if updated != entry {
+        set_physical32(emu, address, updated)?;
+    }
+    Ok(updated)
+}
@@ -0,0 +1,372 @@
+use crate::io::*;
+use crate::irq::*;
+
+/// The master input the slave's interrupt output is wired to on PC boards.
+const CASCADE_IRQ: u8 = 2;
+
+const ICW1: u8 = 1 << 4;
+const ICW1_ICW4: u8 = 1;
+const ICW1_SINGLE: u8 = 1 << 1;
+const ICW1_LEVEL: u8 = 1 << 3;
+
+const ICW4_AUTO_EOI: u8 = 1 << 1;
+const ICW4_SPECIAL_FULLY_NESTED: u8 = 1 << 4;
+
+const OCW3: u8 = 1 << 3;
+const OCW3_READ_ISR: u8 = 1;
+const OCW3_READ_REGISTER: u8 = 1 << 1;
+const OCW3_POLL: u8 = 1 << 2;
+const OCW3_SPECIAL_MASK: u8 = 1 << 5;
+const OCW3_SET_SPECIAL_MASK: u8 = 1 << 6;
+
+/// The initialization word the data port expects next.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Init {
+    Ready,
+    Icw2,
+    Icw3,
+    Icw4,
+}
+
+/// One 8259A.
+#[derive(Clone, Debug)]
+struct Controller {
+    master: bool,
+    irr: u8,
+    isr: u8,
+    imr: u8,
+    icw1: u8,
+    vector_base: u8,
+    /// ICW3: the inputs with a slave attached on a master, the cascade ID on a
+    /// slave.
+    cascade: u8,
+    init: Init,
+    /// The input with the lowest priority. The one after it has the highest.
+    lowest_priority: u8,
+    auto_eoi: bool,
+    rotate_on_auto_eoi: bool,
+    special_fully_nested: bool,
+    special_mask: bool,
+    read_isr: bool,
+    poll: bool,
+}
+
+impl Controller {
+    /// A controller as the PC BIOS programs it: edge triggered, cascaded, 8086 mode
+    /// and nothing masked.
+    fn new(master: bool) -> Controller {
+        Controller {
+            master,
+            irr: 0,
+            isr: 0,
+            imr: 0,
+            icw1: ICW1 | ICW1_ICW4,
+            vector_base: if master { 0x08 } else { 0x70 },
+            cascade: if master {
+                1 << CASCADE_IRQ
+            } else {
+                CASCADE_IRQ
+            },
+            init: Init::Ready,
+            lowest_priority: 7,
+            auto_eoi: false,
+            rotate_on_auto_eoi: false,
+            special_fully_nested: false,
+            special_mask: false,
+            read_isr: false,
+            poll: false,
+        }
+    }
+
+    fn is_level_triggered(&self) -> bool {
+        self.icw1 & ICW1_LEVEL != 0
+    }
+
+    fn is_cascade(&self, irq: u8) -> bool {
+        self.master && self.icw1 & ICW1_SINGLE == 0 && self.cascade & (1 << irq) != 0
+    }
+
+    fn vector(&self, irq: u8) -> u8 {
+        self.vector_base | irq
+    }
+
+    /// Latches requests from the inputs that have risen, or from the input levels
+    /// when level triggered. An edge-triggered request stays latched until it is
+    /// acknowledged, even if its input has fallen again.
+    fn set_inputs(&mut self, levels: u8, edges: u8) {
+        if self.is_level_triggered() {
+            self.irr = levels;
+        } else {
+            self.irr |= edges;
+        }
+    }
+
+    /// 0 for the input with the highest priority, 7 for the lowest.
+    fn priority(&self, irq: u8) -> u8 {
+        irq.wrapping_sub(self.lowest_priority).wrapping_sub(1) & 7
+    }
+
+    fn highest(&self, bits: u8) -> Option<u8> {
+        (1..=8)
+            .map(|i| self.lowest_priority.wrapping_add(i) & 7)
+            .find(|irq| bits & (1 << irq) != 0)
+    }
+
+    /// The request the controller would signal now: the highest-priority unmasked
+    /// one, provided no interrupt of the same or higher priority is in service.
+    fn pending(&self) -> Option<u8> {
+        let irq = self.highest(self.irr & !self.imr)?;
+        let mut in_service = self.isr;
+        if self.special_mask {
+            in_service &= !self.imr;
+        }
+        // A slave can interrupt its own handler with a request of higher priority.
+        if self.special_fully_nested && self.is_cascade(irq) {
+            in_service &= !(1 << irq);
+        }
+        match self.highest(in_service) {
+            Some(current) if self.priority(current) <= self.priority(irq) => None,
+            _ => Some(irq),
+        }
+    }
+
+    fn acknowledge(&mut self, irq: u8) {
+        if !self.is_level_triggered() {
+            self.irr &= !(1 << irq);
+        }
+        if !self.auto_eoi {
+            self.isr |= 1 << irq;
+        } else if self.rotate_on_auto_eoi {
+            self.lowest_priority = irq;
+        }
+    }
+
+    /// Clears the in-service bit for `irq`, or for the highest-priority interrupt in
+    /// service if `irq` is `None`. Returns the input that was cleared.
+    fn end_of_interrupt(&mut self, irq: Option<u8>) -> Option<u8> {
+        let irq = match irq {
+            Some(irq) => irq,
+            None => self.highest(self.isr)?,
+        };
+        self.isr &= !(1 << irq);
+        Some(irq)
+    }
+
+    /// OCW2: end of interrupt and priority rotation.
+    fn write_ocw2(&mut self, value: u8) {
+        let irq = value & 7;
+        match value >> 5 {
+            0b000 => self.rotate_on_auto_eoi = false,
+            0b001 => {
+                self.end_of_interrupt(None);
+            }
+            0b011 => {
+                self.end_of_interrupt(Some(irq));
+            }
+            0b100 => self.rotate_on_auto_eoi = true,
+            0b101 => {
+                if let Some(irq) = self.end_of_interrupt(None) {
+                    self.lowest_priority = irq;
+                }
+            }
+            0b110 => self.lowest_priority = irq,
+            0b111 => {
+                self.end_of_interrupt(Some(irq));
+                self.lowest_priority = irq;
+            }
+            _ => {}
+        }
+    }
+
+    /// OCW3: register selection, poll and special mask mode.
+    fn write_ocw3(&mut self, value: u8) {
+        if value & OCW3_READ_REGISTER != 0 {
+            self.read_isr = value & OCW3_READ_ISR != 0;
+        }
+        if value & OCW3_SET_SPECIAL_MASK != 0 {
+            self.special_mask = value & OCW3_SPECIAL_MASK != 0;
+        }
+        self.poll = value & OCW3_POLL != 0;
+    }
+
+    /// ICW1 restarts initialization. Requests already latched are dropped, and
+    /// inputs that are high must fall and rise again to be recognized.
+    fn write_icw1(&mut self, value: u8) {
+        *self = Controller {
+            icw1: value,
+            vector_base: self.vector_base,
+            cascade: self.cascade,
+            init: Init::Icw2,
+            ..Controller::new(self.master)
+        };
+    }
+
+    fn write(&mut self, register: u16, value: u8) {
+        if register == 0 {
+            if value & ICW1 != 0 {
+                self.write_icw1(value);
+            } else if value & OCW3 != 0 {
+                self.write_ocw3(value);
+            } else {
+                self.write_ocw2(value);
+            }
+            return;
+        }
+
+        let needs_icw4 = self.icw1 & ICW1_ICW4 != 0;
+        let after_icw3 = if needs_icw4 { Init::Icw4 } else { Init::Ready };
+        self.init = match self.init {
+            Init::Ready => {
+                self.imr = value;
+                Init::Ready
+            }
+            Init::Icw2 => {
+                self.vector_base = value & 0xf8;
+                if self.icw1 & ICW1_SINGLE != 0 {
+                    after_icw3
+                } else {
+                    Init::Icw3
+                }
+            }
+            Init::Icw3 => {
+                self.cascade = value;
+                after_icw3
+            }
+            Init::Icw4 => {
+                self.auto_eoi = value & ICW4_AUTO_EOI != 0;
+                self.special_fully_nested = value & ICW4_SPECIAL_FULLY_NESTED != 0;
+                Init::Ready
+            }
+        };
+    }
+
+    fn read(&mut self, register: u16) -> u8 {
+        if register == 1 {
+            return self.imr;
+        }
+        if self.poll {
+            // A poll acknowledges the request it reports.
+            self.poll = false;
+            return match self.pending() {
+                Some(irq) => {
+                    self.acknowledge(irq);
+                    0x80 | irq
+                }
+                None => 0,
+            };
+        }
+        if self.read_isr {
+            self.isr
+        } else {
+            self.irr
+        }
+    }
+}
+
+/// The master and slave 8259A of a PC/AT. Both sample the shared IRQ lines: IRQ0 to
+/// IRQ7 go to the master, IRQ8 to IRQ15 to the slave, whose output is wired to the
+/// master's IRQ2 input. The controllers start programmed as the BIOS leaves them,
+/// with the master's vectors at 0x08 and the slave's at 0x70.
+#[derive(Clone, Debug)]
+pub struct Pic {
+    master: Controller,
+    slave: Controller,
+    slave_output: bool,
+    lines: IrqLines,
+}
+
+impl Pic {
+    pub fn new(lines: IrqLines) -> Pic {
+        Pic {
+            master: Controller::new(true),
+            slave: Controller::new(false),
+            slave_output: false,
+            lines,
+        }
+    }
+
+    /// Samples the IRQ lines and passes the slave's output on to the master.
+    fn update(&mut self) {
+        let levels = self.lines.levels();
+        let edges = self.lines.take_edges();
+        self.slave
+            .set_inputs((levels >> 8) as u8, (edges >> 8) as u8);
+
+        let slave_output = self.slave.pending().is_some();
+        let slave_edge = slave_output && !self.slave_output;
+        self.slave_output = slave_output;
+        let cascade = !(1 << CASCADE_IRQ);
+        self.master.set_inputs(
+            levels as u8 & cascade | (slave_output as u8) << CASCADE_IRQ,
+            edges as u8 & cascade | (slave_edge as u8) << CASCADE_IRQ,
+        );
+    }
+
+    /// Whether the master's interrupt output to the processor is raised.
+    pub fn has_interrupt(&mut self) -> bool {
+        self.update();
+        self.master.pending().is_some()
+    }
+
+    /// The processor's interrupt acknowledge cycle: marks the request in service and
+    /// returns its vector. Without a request the spurious IRQ7 vector is returned.
+    pub fn acknowledge(&mut self) -> u8 {
+        self.update();
+        let vector = match self.master.pending() {
+            Some(irq) if self.master.is_cascade(irq) => {
+                self.master.acknowledge(irq);
+                match self.slave.pending() {
+                    Some(irq) => {
+                        self.slave.acknowledge(irq);
+                        self.slave.vector(irq)
+                    }
+                    None => self.slave.vector(7),
+                }
+            }
+            Some(irq) => {
+                self.master.acknowledge(irq);
+                self.master.vector(irq)
+            }
+            None => self.master.vector(7),
+        };
+        self.update();
+        vector
+    }
+
+    /// Ends the request acknowledged as `vector`, as a specific EOI to the
+    /// controller that raised it and, for the slave, to the master's cascade input.
+    pub fn end_of_interrupt(&mut self, vector: u8) {
+        let irq = vector & 7;
+        if vector & 0xf8 == self.slave.vector_base {
+            self.slave.end_of_interrupt(Some(irq));
+            self.master.end_of_interrupt(Some(self.slave.cascade & 7));
+        } else if vector & 0xf8 == self.master.vector_base {
+            self.master.end_of_interrupt(Some(irq));
+        }
+        self.update();
+    }
+
+    fn controller(&mut self, port: u16) -> &mut Controller {
+        if port & 0x80 != 0 {
+            &mut self.slave
+        } else {
+            &mut self.master
+        }
+    }
+}
+
+/// Ports 0x20 and 0x21 select the master, 0xA0 and 0xA1 the slave.
+impl IoDevice for Pic {
+    fn in8(&mut self, port: u16) -> u8 {
+        self.update();
+        let value = self.controller(port).read(port & 1);
+        self.update();
+        value
+    }
+
+    fn out8(&mut self, port: u16, value: u8) {
+        self.update();
+        self.controller(port).write(port & 1, value);
+        self.update();
+    }
+}
@@ -0,0 +1,403 @@
+use crate::io::*;
+use crate::irq::*;
+
+/// The virtual clock runs one instruction per cycle of the IBM PC's 4.77 MHz
+/// processor clock, and the PIT's 1.19 MHz input is that clock divided by four.
+pub const INSTRUCTIONS_PER_TICK: u64 = 4;
+
+/// Frequency of the PIT's input clock in Hz.
+pub const TICKS_PER_SECOND: u64 = 1_193_182;
+
+/// The DRAM refresh request toggles port 0x61 bit 4 about every 15 microseconds.
+const REFRESH_TICKS: u64 = 18;
+
+const PORT_B: u16 = 0x61;
+const PORT_B_GATE2: u8 = 1;
+const PORT_B_SPEAKER: u8 = 1 << 1;
+const PORT_B_REFRESH: u8 = 1 << 4;
+const PORT_B_OUT2: u8 = 1 << 5;
+
+const ACCESS_LATCH: u8 = 0;
+const ACCESS_LOW: u8 = 1;
+const ACCESS_HIGH: u8 = 2;
+
+const STATUS_OUTPUT: u8 = 1 << 7;
+const STATUS_NULL_COUNT: u8 = 1 << 6;
+
+fn from_bcd(value: u16) -> u32 {
+    (0..4)
+        .rev()
+        .fold(0, |n, digit| n * 10 + (value >> (digit * 4) & 0xf) as u32)
+}
+
+fn to_bcd(value: u32) -> u16 {
+    (0..4).fold(0, |bcd, digit| {
+        bcd | ((value / 10u32.pow(digit) % 10) as u16) << (digit * 4)
+    })
+}
+
+/// One counter of the 8254.
+#[derive(Clone, Debug)]
+struct Channel {
+    mode: u8,
+    bcd: bool,
+    access: u8,
+    /// The count register as last written by the guest.
+    count: u32,
+    /// The count the counter is running with.
+    reload: u32,
+    /// A count has been written since the control word.
+    loaded: bool,
+    /// Modes 1 and 5 count only after a rising edge on the gate.
+    triggered: bool,
+    gate: bool,
+    null_count: bool,
+    /// Clock ticks counted since the counter was last started.
+    elapsed: u64,
+    /// A count written in modes 2 and 3 while counting takes over at the end of
+    /// the current period, given as a value of `elapsed`.
+    next_reload: Option<(u32, u64)>,
+    low_written: Option<u8>,
+    read_high: bool,
+    latch: Option<u16>,
+    status: Option<u8>,
+}
+
+impl Channel {
+    fn new(mode: u8, count: u32) -> Channel {
+        Channel {
+            mode,
+            bcd: false,
+            access: ACCESS_LOW | ACCESS_HIGH,
+            count,
+            reload: count,
+            loaded: true,
+            triggered: false,
+            gate: true,
+            null_count: false,
+            elapsed: 0,
+            next_reload: None,
+            low_written: None,
+            read_high: false,
+            latch: None,
+            status: None,
+        }
+    }
+
+    fn modulus(&self) -> u32 {
+        if self.bcd {
+            10000
+        } else {
+            0x10000
+        }
+    }
+
+    fn is_counting(&self) -> bool {
+        self.loaded
+            && match self.mode {
+                1 | 5 => self.triggered,
+                _ => self.gate,
+            }
+    }
+
+    fn output(&self) -> bool {
+        if !self.loaded {
+            return self.mode != 0;
+        }
+        let n = self.reload as u64;
+        let e = self.elapsed;
+        match self.mode {
+            0 => e >= n,
+            1 => !self.triggered || e >= n,
+            2 => !self.gate || e % n != n - 1,
+            3 => !self.gate || e % n < n.div_ceil(2),
+            4 => e != n,
+            _ => !self.triggered || e != n,
+        }
+    }
+
+    /// Whether the output rises at some tick in `(from, to]`.
+    fn rises(&self, from: u64, to: u64) -> bool {
+        let n = self.reload as u64;
+        match self.mode {
+            0 | 1 => from < n && n <= to,
+            2 | 3 => to / n > from / n,
+            _ => from <= n && n < to,
+        }
+    }
+
+    /// Advances the counter by `ticks` clock cycles, returning whether the output
+    /// rose on the way.
+    fn advance(&mut self, ticks: u64) -> bool {
+        if !self.is_counting() {
+            return false;
+        }
+        let mut ticks = ticks;
+        let mut rose = false;
+        if let Some((count, at)) = self.next_reload {
+            if self.elapsed + ticks >= at {
+                rose = self.rises(self.elapsed, at);
+                ticks -= at - self.elapsed;
+                self.elapsed = 0;
+                self.reload = count;
+                self.next_reload = None;
+            }
+        }
+        rose |= self.rises(self.elapsed, self.elapsed + ticks);
+        self.elapsed += ticks;
+        rose
+    }
+
+    /// The current value of the counting element, in binary.
+    fn counter(&self) -> u32 {
+        let n = self.reload as u64;
+        if !self.is_counting() && self.elapsed == 0 {
+            return self.count % self.modulus();
+        }
+        let e = self.elapsed;
+        let value = match self.mode {
+            2 => n - e % n,
+            // Mode 3 counts down by two, once through each half of the period.
+            3 => {
+                let high = n.div_ceil(2);
+                let phase = e % n;
+                let half = if phase < high { phase } else { phase - high };
+                (n & !1).saturating_sub(2 * half)
+            }
+            _ => {
+                let modulus = self.modulus() as u64;
+                (n + modulus - e % modulus) % modulus
+            }
+        };
+        value as u32 % self.modulus()
+    }
+
+    fn counter_register(&self) -> u16 {
+        if self.bcd {
+            to_bcd(self.counter())
+        } else {
+            self.counter() as u16
+        }
+    }
+
+    fn status_byte(&self) -> u8 {
+        let mut status = self.access << 4 | self.mode << 1 | self.bcd as u8;
+        if self.output() {
+            status |= STATUS_OUTPUT;
+        }
+        if self.null_count {
+            status |= STATUS_NULL_COUNT;
+        }
+        status
+    }
+
+    fn latch_count(&mut self) {
+        if self.latch.is_none() {
+            self.latch = Some(self.counter_register());
+            self.read_high = false;
+        }
+    }
+
+    fn latch_status(&mut self) {
+        if self.status.is_none() {
+            self.status = Some(self.status_byte());
+        }
+    }
+
+    /// A control word other than a latch command resets the counter to wait for a
+    /// new count.
+    fn set_mode(&mut self, value: u8) {
+        let mode = value >> 1 & 7;
+        *self = Channel {
+            mode: if mode >= 6 { mode - 4 } else { mode },
+            bcd: value & 1 != 0,
+            access: value >> 4 & 3,
+            loaded: false,
+            null_count: true,
+            gate: self.gate,
+            ..Channel::new(0, self.count)
+        };
+    }
+
+    fn load(&mut self, count: u16) {
+        let count = if self.bcd {
+            from_bcd(count)
+        } else {
+            count as u32
+        };
+        self.count = if count == 0 { self.modulus() } else { count };
+        self.null_count = false;
+        match self.mode {
+            2 | 3 if self.loaded && self.is_counting() => {
+                let n = self.reload as u64;
+                self.next_reload = Some((self.count, (self.elapsed / n + 1) * n));
+            }
+            1 | 5 => self.loaded = true,
+            _ => {
+                self.loaded = true;
+                self.reload = self.count;
+                self.elapsed = 0;
+                self.next_reload = None;
+            }
+        }
+    }
+
+    fn set_gate(&mut self, gate: bool) {
+        if gate && !self.gate && self.loaded {
+            match self.mode {
+                1 | 5 => self.triggered = true,
+                2 | 3 => {}
+                _ => {
+                    self.gate = gate;
+                    return;
+                }
+            }
+            self.reload = self.count;
+            self.elapsed = 0;
+            self.next_reload = None;
+        }
+        self.gate = gate;
+    }
+
+    fn read(&mut self) -> u8 {
+        if let Some(status) = self.status.take() {
+            return status;
+        }
+        let value = self.latch.unwrap_or_else(|| self.counter_register());
+        let (byte, done) = match self.access {
+            ACCESS_LOW => (value as u8, true),
+            ACCESS_HIGH => ((value >> 8) as u8, true),
+            _ if self.read_high => ((value >> 8) as u8, true),
+            _ => (value as u8, false),
+        };
+        self.read_high = !done;
+        if done {
+            self.latch = None;
+        }
+        byte
+    }
+
+    fn write(&mut self, value: u8) {
+        match self.access {
+            ACCESS_LOW => self.load(value as u16),
+            ACCESS_HIGH => self.load((value as u16) << 8),
+            _ => match self.low_written.take() {
+                Some(low) => self.load(low as u16 | (value as u16) << 8),
+                None => self.low_written = Some(value),
+            },
+        }
+    }
+}
+
+/// An 8254 programmable interval timer on ports 0x40 to 0x43, with the gate of
+/// counter 2 and the speaker enable on port 0x61. Counter 0 drives `irq`. The
+/// counters run from the instruction count, so timing is reproducible, and start
+/// programmed as the BIOS leaves them: counter 0 as an 18.2 Hz square wave and
+/// counter 1 as the refresh rate generator.
+#[derive(Clone, Debug)]
+pub struct Pit {
+    channels: [Channel; 3],
+    port_b: u8,
+    ticks: u64,
+    irq: IrqLine,
+}
+
+impl Pit {
+    pub fn new(irq: IrqLine) -> Pit {
+        let mut speaker = Channel::new(3, 0x10000);
+        speaker.gate = false;
+        Pit {
+            channels: [Channel::new(3, 0x10000), Channel::new(2, 18), speaker],
+            port_b: 0,
+            ticks: 0,
+            irq,
+        }
+    }
+
+    /// The frequency in Hz the speaker is sounding, if it is on.
+    pub fn speaker_frequency(&self) -> Option<f64> {
+        let channel = &self.channels[2];
+        let enabled = PORT_B_GATE2 | PORT_B_SPEAKER;
+        if self.port_b & enabled != enabled || channel.mode != 3 || !channel.is_counting() {
+            return None;
+        }
+        Some(TICKS_PER_SECOND as f64 / channel.reload as f64)
+    }
+
+    fn write_control(&mut self, value: u8) {
+        let select = value >> 6;
+        if select == 3 {
+            // Read-back: the bits for latching the count and status are active low.
+            for (i, channel) in self.channels.iter_mut().enumerate() {
+                if value & (2 << i) == 0 {
+                    continue;
+                }
+                if value & (1 << 5) == 0 {
+                    channel.latch_count();
+                }
+                if value & (1 << 4) == 0 {
+                    channel.latch_status();
+                }
+            }
+            return;
+        }
+        let channel = &mut self.channels[select as usize];
+        if value >> 4 & 3 == ACCESS_LATCH {
+            channel.latch_count();
+        } else {
+            channel.set_mode(value);
+        }
+    }
+
+    fn update_irq(&mut self, rose: bool) {
+        // A pulse that came and went since the last update still has to register.
+        if rose {
+            self.irq.set(false);
+            self.irq.set(true);
+        }
+        self.irq.set(self.channels[0].output());
+    }
+}
+
+impl IoDevice for Pit {
+    fn in8(&mut self, port: u16) -> u8 {
+        if port == PORT_B {
+            let refresh = (self.ticks / REFRESH_TICKS) & 1 != 0;
+            let mut value = self.port_b;
+            if refresh {
+                value |= PORT_B_REFRESH;
+            }
+            if self.channels[2].output() {
+                value |= PORT_B_OUT2;
+            }
+            return value;
+        }
+        match port & 3 {
+            3 => 0xff,
+            n => self.channels[n as usize].read(),
+        }
+    }
+
+    fn out8(&mut self, port: u16, value: u8) {
+        if port == PORT_B {
+            self.port_b = value & 0x0f;
+            self.channels[2].set_gate(value & PORT_B_GATE2 != 0);
+            return;
+        }
+        match port & 3 {
+            3 => self.write_control(value),
+            n => self.channels[n as usize].write(value),
+        }
+        self.update_irq(false);
+    }
+
+    fn update(&mut self, now: u64) {
+        let ticks = now / INSTRUCTIONS_PER_TICK;
+        let elapsed = ticks.saturating_sub(self.ticks);
+        self.ticks = ticks;
+        let rose = self.channels[0].advance(elapsed);
+        self.channels[1].advance(elapsed);
+        self.channels[2].advance(elapsed);
+        self.update_irq(rose);
+    }
+}
@@ -0,0 +1,94 @@
+use crate::descriptor::*;
+use crate::emulator::*;
+use crate::error::*;
+use crate::function::*;
+use crate::*;
+
+/// Loads the task register from an available TSS descriptor and marks it busy.
+pub fn load_task_register(emu: &mut Emulator, selector: u16) -> EmuResult {
+    if selector & 0xfffc == 0 {
+        return Err(exception_with_code(emu, GENERAL_PROTECTION, 0));
+    }
+    let error_code = (selector & 0xfffc) as u32;
+    let descriptor = read_descriptor(emu, selector)?;
+    let available = match descriptor.system_type() {
+        TSS16_AVAILABLE | TSS32_AVAILABLE => !descriptor.is_segment(),
+        _ => false,
+    };
+    if !available {
+        return Err(exception_with_code(emu, GENERAL_PROTECTION, error_code));
+    }
+    if !descriptor.is_present() {
+        return Err(exception_with_code(emu, SEGMENT_NOT_PRESENT, error_code));
+    }
+
+    // The busy bit is bit 1 of the type.
+    let access = descriptor.access | 0x02;
+    let address = emu.gdtr.base.wrapping_add((selector & 0xfff8) as u32 + 5);
+    set_system_memory8(emu, address, access as u32)?;
+    emu.tr = SegmentRegister {
+        selector,
+        base: descriptor.base,
+        limit: descriptor.limit,
+        big: descriptor.system_type() == TSS32_AVAILABLE,
+        access,
+    };
+    Ok(())
+}
+
+/// Reads SS and ESP for privilege level `dpl` from the current TSS.
+fn inner_stack(emu: &mut Emulator, dpl: u8) -> EmuResult<(u16, u32)> {
+    let error_code = (emu.tr.selector & 0xfffc) as u32;
+    let (offset, size) = if emu.tr.big {
+        (4 + dpl as u32 * 8, 32)
+    } else {
+        (2 + dpl as u32 * 4, 16)
+    };
+    if emu.tr.access & ACCESS_PRESENT == 0 || offset + size / 4 - 1 > emu.tr.limit {
+        return Err(exception_with_code(emu, INVALID_TSS, error_code));
+    }
+    let address = emu.tr.base.wrapping_add(offset);
+    let (esp, ss) = if size == 32 {
+        let esp = get_system_memory32(emu, address)?;
+        (esp, get_system_memory16(emu, address.wrapping_add(4))?)
+    } else {
+        let sp = get_system_memory16(emu, address)?;
+        (sp, get_system_memory16(emu, address.wrapping_add(2))?)
+    };
+    Ok((ss as u16, esp))
+}
+
+/// Switches to the stack for the more privileged level `dpl` and enters that level.
+/// Returns the old SS and ESP for the caller to push. A bad stack segment in the
+/// TSS raises #TS(SS).
+pub fn switch_stack(emu: &mut Emulator, dpl: u8) -> EmuResult<(u32, u32)> {
+    let (ss, esp) = inner_stack(emu, dpl)?;
+    let old_ss = emu.sregs[SS].selector as u32;
+    let old_esp = emu.registers[ESP];
+    match load_segment_with_cpl(emu, SS, ss, dpl) {
+        Err(EmuError::Exception { .. }) => {
+            let error_code = (ss & 0xfffc) as u32;
+            return Err(exception_with_code(emu, INVALID_TSS, error_code));
+        }
+        result => result?,
+    }
+    emu.registers[ESP] = esp;
+    emu.cpl = dpl;
+    Ok((old_ss, old_esp))
+}
+
+/// Nulls the data segment registers that are not accessible at the outer level `cpl`
+/// after a far return or IRET.
+pub fn clear_inaccessible_segments(emu: &mut Emulator, cpl: u8) {
+    for &index in [ES, DS, FS, GS].iter() {
+        let access = emu.sregs[index].access;
+        let dpl = (access >> 5) & 0x03;
+        let conforming_code = access & 0x1c == 0x1c;
+        if !conforming_code && dpl < cpl {
+            emu.sregs[index] = SegmentRegister {
+                selector: 0,
+                ..SegmentRegister::default()
+            };
+        }
+    }
+}
@@ -0,0 +1,393 @@
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+use crate::io::*;
+use crate::irq::*;
+
+/// Base port and IRQ of COM1 to COM4.
+pub const COM_PORTS: [(u16, u8); 4] = [(0x3f8, 4), (0x2f8, 3), (0x3e8, 4), (0x2e8, 3)];
+
+const FIFO_SIZE: usize = 16;
+/// Instructions without receiver activity before a character timeout is signalled.
+const CHARACTER_TIMEOUT: u64 = 4096;
+
+const IER_RECEIVED_DATA: u8 = 1;
+const IER_THR_EMPTY: u8 = 1 << 1;
+const IER_LINE_STATUS: u8 = 1 << 2;
+const IER_MODEM_STATUS: u8 = 1 << 3;
+
+const IIR_NONE: u8 = 0x01;
+const IIR_MODEM_STATUS: u8 = 0x00;
+const IIR_THR_EMPTY: u8 = 0x02;
+const IIR_RECEIVED_DATA: u8 = 0x04;
+const IIR_LINE_STATUS: u8 = 0x06;
+const IIR_CHARACTER_TIMEOUT: u8 = 0x0c;
+const IIR_FIFO_ENABLED: u8 = 0xc0;
+
+const FCR_ENABLE: u8 = 1;
+const FCR_CLEAR_RECEIVER: u8 = 1 << 1;
+
+const LCR_DLAB: u8 = 1 << 7;
+
+const MCR_DTR: u8 = 1;
+const MCR_RTS: u8 = 1 << 1;
+const MCR_OUT1: u8 = 1 << 2;
+/// Gates the interrupt output onto the IRQ line on PC-compatible boards.
+const MCR_OUT2: u8 = 1 << 3;
+const MCR_LOOPBACK: u8 = 1 << 4;
+
+pub const LSR_DATA_READY: u8 = 1;
+pub const LSR_OVERRUN: u8 = 1 << 1;
+pub const LSR_BREAK: u8 = 1 << 4;
+pub const LSR_THR_EMPTY: u8 = 1 << 5;
+pub const LSR_TRANSMITTER_EMPTY: u8 = 1 << 6;
+const LSR_ERRORS: u8 = 0x1e;
+
+const MSR_CTS: u8 = 1 << 4;
+const MSR_DSR: u8 = 1 << 5;
+const MSR_RI: u8 = 1 << 6;
+const MSR_DCD: u8 = 1 << 7;
+
+/// Where the bytes a UART sends and receives go.
+pub trait SerialBackend {
+    /// Returns the next received byte, if one is available, without blocking.
+    fn receive(&mut self) -> Option<u8>;
+
+    fn transmit(&mut self, byte: u8);
+}
+
+/// A backend over byte streams. Input is read on a separate thread so the guest
+/// never blocks; output is written and flushed byte by byte.
+pub struct StreamBackend {
+    input: Option<Receiver<u8>>,
+    output: Box<dyn Write>,
+}
+
+impl StreamBackend {
+    pub fn new(input: Option<Box<dyn Read + Send>>, output: Box<dyn Write>) -> StreamBackend {
+        let input = input.map(|mut reader| {
+            let (sender, receiver) = mpsc::channel();
+            thread::spawn(move || {
+                let mut byte = [0];
+                while let Ok(1) = reader.read(&mut byte) {
+                    if sender.send(byte[0]).is_err() {
+                        break;
+                    }
+                }
+            });
+            receiver
+        });
+        StreamBackend { input, output }
+    }
+
+    /// The host's stdin and stdout.
+    pub fn stdio() -> StreamBackend {
+        StreamBackend::new(Some(Box::new(io::stdin())), Box::new(io::stdout()))
+    }
+
+    /// Appends output to the file at `path`. Nothing is ever received.
+    pub fn file<P: AsRef<Path>>(path: P) -> io::Result<StreamBackend> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(StreamBackend::new(None, Box::new(file)))
+    }
+
+    /// Receives from the named pipe at `input` and sends to the one at `output`.
+    pub fn pipe<P: AsRef<Path>, Q: AsRef<Path>>(input: P, output: Q) -> io::Result<StreamBackend> {
+        let reader = File::open(input)?;
+        let writer = OpenOptions::new().write(true).open(output)?;
+        Ok(StreamBackend::new(Some(Box::new(reader)), Box::new(writer)))
+    }
+
+    /// Connects to the Unix domain socket at `path`.
+    pub fn unix_socket<P: AsRef<Path>>(path: P) -> io::Result<StreamBackend> {
+        let stream = UnixStream::connect(path)?;
+        let reader = stream.try_clone()?;
+        Ok(StreamBackend::new(Some(Box::new(reader)), Box::new(stream)))
+    }
+}
+
+impl SerialBackend for StreamBackend {
+    fn receive(&mut self) -> Option<u8> {
+        let byte = match &self.input {
+            Some(input) => input.try_recv(),
+            None => return None,
+        };
+        match byte {
+            Ok(byte) => Some(byte),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => {
+                self.input = None;
+                None
+            }
+        }
+    }
+
+    fn transmit(&mut self, byte: u8) {
+        // A host that stops listening must not bring the guest down.
+        let _ = self.output.write_all(&[byte]);
+        let _ = self.output.flush();
+    }
+}
+
+/// A 16550A UART. Transmission completes instantly, so THR and the transmitter are
+/// always empty to the guest. The interrupt output drives `irq` while MCR.OUT2 is
+/// set.
+pub struct Uart {
+    base: u16,
+    irq: IrqLine,
+    backend: Box<dyn SerialBackend>,
+    receiver: VecDeque<u8>,
+    divisor: u16,
+    ier: u8,
+    fcr: u8,
+    lcr: u8,
+    mcr: u8,
+    lsr: u8,
+    msr: u8,
+    scratch: u8,
+    /// The THR empty interrupt is pending until IIR reports it or THR is written.
+    thr_empty_pending: bool,
+    now: u64,
+    last_receive: u64,
+}
+
+impl Uart {
+    pub fn new(base: u16, irq: IrqLine, backend: Box<dyn SerialBackend>) -> Uart {
+        let mut uart = Uart {
+            base,
+            irq,
+            backend,
+            receiver: VecDeque::with_capacity(FIFO_SIZE),
+            divisor: 12,
+            ier: 0,
+            fcr: 0,
+            lcr: 0x03,
+            mcr: 0,
+            lsr: LSR_THR_EMPTY | LSR_TRANSMITTER_EMPTY,
+            msr: 0,
+            scratch: 0,
+            thr_empty_pending: false,
+            now: 0,
+            last_receive: 0,
+        };
+        uart.msr = uart.modem_lines();
+        uart
+    }
+
+    /// Baud rate divisor latch.
+    pub fn divisor(&self) -> u16 {
+        self.divisor
+    }
+
+    fn is_fifo_enabled(&self) -> bool {
+        self.fcr & FCR_ENABLE != 0
+    }
+
+    fn receiver_capacity(&self) -> usize {
+        if self.is_fifo_enabled() {
+            FIFO_SIZE
+        } else {
+            1
+        }
+    }
+
+    fn trigger_level(&self) -> usize {
+        if !self.is_fifo_enabled() {
+            return 1;
+        }
+        match self.fcr >> 6 {
+            0 => 1,
+            1 => 4,
+            2 => 8,
+            _ => 14,
+        }
+    }
+
+    fn push_received(&mut self, byte: u8) {
+        if self.receiver.len() >= self.receiver_capacity() {
+            self.lsr |= LSR_OVERRUN;
+        } else {
+            self.receiver.push_back(byte);
+        }
+        self.last_receive = self.now;
+    }
+
+    /// Moves whatever the backend has ready into the receiver, up to its capacity.
+    fn poll(&mut self) {
+        if self.mcr & MCR_LOOPBACK != 0 {
+            return;
+        }
+        while self.receiver.len() < self.receiver_capacity() {
+            match self.backend.receive() {
+                Some(byte) => self.push_received(byte),
+                None => break,
+            }
+        }
+    }
+
+    /// CTS, DSR, RI and DCD. In loopback they follow RTS, DTR, OUT1 and OUT2;
+    /// otherwise the backend is always connected and ready.
+    fn modem_lines(&self) -> u8 {
+        if self.mcr & MCR_LOOPBACK == 0 {
+            return MSR_CTS | MSR_DSR | MSR_DCD;
+        }
+        let mut lines = 0;
+        if self.mcr & MCR_RTS != 0 {
+            lines |= MSR_CTS;
+        }
+        if self.mcr & MCR_DTR != 0 {
+            lines |= MSR_DSR;
+        }
+        if self.mcr & MCR_OUT1 != 0 {
+            lines |= MSR_RI;
+        }
+        if self.mcr & MCR_OUT2 != 0 {
+            lines |= MSR_DCD;
+        }
+        lines
+    }
+
+    /// Latches changes of the modem lines into the MSR delta bits. RI only reports
+    /// its trailing edge.
+    fn update_modem_status(&mut self) {
+        let lines = self.modem_lines();
+        let old = self.msr & 0xf0;
+        let changed = (lines ^ old) >> 4;
+        let mut delta = changed & 0x0b;
+        if changed & 0x04 != 0 && lines & MSR_RI == 0 {
+            delta |= 0x04;
+        }
+        self.msr = lines | (self.msr & 0x0f) | delta;
+    }
+
+    /// The highest-priority pending interrupt, as reported in IIR.
+    fn pending_interrupt(&self) -> u8 {
+        if self.ier & IER_LINE_STATUS != 0 && self.lsr & LSR_ERRORS != 0 {
+            IIR_LINE_STATUS
+        } else if self.ier & IER_RECEIVED_DATA != 0 && self.receiver.len() >= self.trigger_level() {
+            IIR_RECEIVED_DATA
+        } else if self.ier & IER_RECEIVED_DATA != 0
+            && self.is_fifo_enabled()
+            && !self.receiver.is_empty()
+            && self.now.wrapping_sub(self.last_receive) >= CHARACTER_TIMEOUT
+        {
+            IIR_CHARACTER_TIMEOUT
+        } else if self.ier & IER_THR_EMPTY != 0 && self.thr_empty_pending {
+            IIR_THR_EMPTY
+        } else if self.ier & IER_MODEM_STATUS != 0 && self.msr & 0x0f != 0 {
+            IIR_MODEM_STATUS
+        } else {
+            IIR_NONE
+        }
+    }
+
+    fn update_irq(&mut self) {
+        let pending = self.pending_interrupt() != IIR_NONE;
+        self.irq.set(pending && self.mcr & MCR_OUT2 != 0);
+    }
+
+    fn read_register(&mut self, offset: u16) -> u8 {
+        let dlab = self.lcr & LCR_DLAB != 0;
+        match offset {
+            0 if dlab => self.divisor as u8,
+            0 => {
+                self.poll();
+                let byte = self.receiver.pop_front().unwrap_or(0);
+                self.last_receive = self.now;
+                self.poll();
+                byte
+            }
+            1 if dlab => (self.divisor >> 8) as u8,
+            1 => self.ier,
+            2 => {
+                let iir = self.pending_interrupt();
+                if iir == IIR_THR_EMPTY {
+                    self.thr_empty_pending = false;
+                }
+                if self.is_fifo_enabled() {
+                    iir | IIR_FIFO_ENABLED
+                } else {
+                    iir
+                }
+            }
+            3 => self.lcr,
+            4 => self.mcr,
+            5 => {
+                self.poll();
+                let mut lsr = self.lsr;
+                if !self.receiver.is_empty() {
+                    lsr |= LSR_DATA_READY;
+                }
+                self.lsr &= !LSR_ERRORS;
+                lsr
+            }
+            6 => {
+                let msr = self.msr;
+                self.msr &= 0xf0;
+                msr
+            }
+            _ => self.scratch,
+        }
+    }
+
+    fn write_register(&mut self, offset: u16, value: u8) {
+        let dlab = self.lcr & LCR_DLAB != 0;
+        match offset {
+            0 if dlab => self.divisor = (self.divisor & 0xff00) | value as u16,
+            0 => {
+                if self.mcr & MCR_LOOPBACK != 0 {
+                    self.push_received(value);
+                } else {
+                    self.backend.transmit(value);
+                }
+                self.thr_empty_pending = true;
+            }
+            1 if dlab => self.divisor = (self.divisor & 0x00ff) | (value as u16) << 8,
+            1 => {
+                // Enabling the THR empty interrupt while THR is empty raises it.
+                if value & IER_THR_EMPTY != 0 && self.ier & IER_THR_EMPTY == 0 {
+                    self.thr_empty_pending = true;
+                }
+                self.ier = value & 0x0f;
+            }
+            2 => {
+                if (value ^ self.fcr) & FCR_ENABLE != 0 || value & FCR_CLEAR_RECEIVER != 0 {
+                    self.receiver.clear();
+                }
+                self.fcr = value & 0xc1;
+            }
+            3 => self.lcr = value,
+            4 => {
+                self.mcr = value & 0x1f;
+                self.update_modem_status();
+            }
+            // LSR and MSR are read-only.
+            5 | 6 => {}
+            _ => self.scratch = value,
+        }
+    }
+}
+
+impl IoDevice for Uart {
+    fn in8(&mut self, port: u16) -> u8 {
+        let value = self.read_register(port.wrapping_sub(self.base) & 7);
+        self.update_irq();
+        value
+    }
+
+    fn out8(&mut self, port: u16, value: u8) {
+        self.write_register(port.wrapping_sub(self.base) & 7, value);
+        self.update_irq();
+    }
+
+    fn update(&mut self, now: u64) {
+        self.now = now;
+        self.poll();
+        self.update_irq();
+    }
+}
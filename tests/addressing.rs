@@ -0,0 +1,49 @@
+mod common;
+
+use x86emu::function::*;
+use x86emu::*;
+
+use common::*;
+
+#[test]
+fn sib_esp_base_with_disp8() {
+    // mov eax, [esp+8]
+    let mut emu = emulator(&[0x8b, 0x44, 0x24, 0x08]);
+    set_memory32(&mut emu, 0x7c08, 0x11223344).unwrap();
+    emu.step().unwrap();
+    assert_eq!(emu.registers[EAX], 0x11223344);
+    assert_eq!(emu.eip, ORIGIN + 4);
+}
+
+#[test]
+fn sib_scaled_index() {
+    // mov eax, [ebx+ecx*4]
+    let mut emu = emulator(&[0x8b, 0x04, 0x8b]);
+    emu.registers[EBX] = 0x100;
+    emu.registers[ECX] = 3;
+    set_memory32(&mut emu, 0x10c, 0xdeadbeef).unwrap();
+    emu.step().unwrap();
+    assert_eq!(emu.registers[EAX], 0xdeadbeef);
+}
+
+#[test]
+fn sib_no_base_disp32() {
+    // mov [esi*2+0x200], edx
+    let mut emu = emulator(&[0x89, 0x14, 0x75, 0x00, 0x02, 0x00, 0x00]);
+    emu.registers[ESI] = 0x10;
+    emu.registers[EDX] = 0x55aa55aa;
+    emu.step().unwrap();
+    assert_eq!(get_memory32(&mut emu, 0x220).unwrap(), 0x55aa55aa);
+    assert_eq!(emu.eip, ORIGIN + 7);
+}
+
+#[test]
+fn sib_ebp_base_with_disp32() {
+    // mov eax, [ebp+edi*8+0x100]
+    let mut emu = emulator(&[0x8b, 0x84, 0xfd, 0x00, 0x01, 0x00, 0x00]);
+    emu.registers[EBP] = 0x1000;
+    emu.registers[EDI] = 2;
+    set_memory32(&mut emu, 0x1110, 42).unwrap();
+    emu.step().unwrap();
+    assert_eq!(emu.registers[EAX], 42);
+}
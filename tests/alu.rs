@@ -0,0 +1,156 @@
+mod common;
+
+use x86emu::function::*;
+use x86emu::*;
+
+use common::*;
+
+#[test]
+fn rm_r_forms_write_the_rm_operand() {
+    let mut emu = emulator(&[
+        0x28, 0x05, 0x00, 0x80, 0x00, 0x00, // sub [0x8000], al
+        0x29, 0x1d, 0x04, 0x80, 0x00, 0x00, // sub [0x8004], ebx
+    ]);
+    set_memory8(&mut emu, 0x8000, 0x10).unwrap();
+    set_memory32(&mut emu, 0x8004, 5).unwrap();
+    emu.registers[EAX] = 0x20;
+    emu.registers[EBX] = 3;
+
+    step(&mut emu, 1);
+    assert_eq!(get_memory8(&mut emu, 0x8000).unwrap(), 0xf0);
+    assert_eq!(emu.registers[EAX], 0x20);
+    assert_eq!(status_flags(&mut emu), CARRY_FLAG | SIGN_FLAG | PARITY_FLAG);
+
+    step(&mut emu, 1);
+    assert_eq!(get_memory32(&mut emu, 0x8004).unwrap(), 2);
+    assert_eq!(emu.registers[EBX], 3);
+    assert_eq!(status_flags(&mut emu), 0);
+}
+
+#[test]
+fn r_rm_forms_write_the_register() {
+    let mut emu = emulator(&[
+        0x2a, 0x05, 0x00, 0x80, 0x00, 0x00, // sub al, [0x8000]
+        0x2b, 0x1d, 0x04, 0x80, 0x00, 0x00, // sub ebx, [0x8004]
+    ]);
+    set_memory8(&mut emu, 0x8000, 0x10).unwrap();
+    set_memory32(&mut emu, 0x8004, 5).unwrap();
+    emu.registers[EAX] = 0x20;
+    emu.registers[EBX] = 3;
+
+    step(&mut emu, 1);
+    assert_eq!(emu.registers[EAX], 0x10);
+    assert_eq!(get_memory8(&mut emu, 0x8000).unwrap(), 0x10);
+    assert_eq!(status_flags(&mut emu), 0);
+
+    step(&mut emu, 1);
+    assert_eq!(emu.registers[EBX], 0xfffffffe);
+    assert_eq!(get_memory32(&mut emu, 0x8004).unwrap(), 5);
+    assert_eq!(
+        status_flags(&mut emu),
+        CARRY_FLAG | SIGN_FLAG | AUX_CARRY_FLAG
+    );
+}
+
+#[test]
+fn register_forms_of_each_operation() {
+    let mut emu = emulator(&[
+        0x08, 0xd8, // or al, bl
+        0x11, 0xd8, // adc eax, ebx
+        0x1a, 0xc3, // sbb al, bl
+        0x23, 0xc3, // and eax, ebx
+        0x31, 0xd8, // xor eax, ebx
+        0x39, 0xd8, // cmp eax, ebx
+    ]);
+    emu.registers[EAX] = 0x1234560f;
+    emu.registers[EBX] = 0xf0;
+    step(&mut emu, 1);
+    assert_eq!(emu.registers[EAX], 0x123456ff);
+
+    emu.registers[EAX] = 1;
+    emu.registers[EBX] = 2;
+    set_carry(&mut emu, true);
+    step(&mut emu, 1);
+    assert_eq!(emu.registers[EAX], 4);
+    assert!(!is_carry(&mut emu));
+
+    emu.registers[EBX] = 1;
+    step(&mut emu, 1);
+    assert_eq!(emu.registers[EAX], 3);
+
+    emu.registers[EBX] = 2;
+    step(&mut emu, 1);
+    assert_eq!(emu.registers[EAX], 2);
+
+    step(&mut emu, 1);
+    assert_eq!(emu.registers[EAX], 0);
+    assert!(is_zero(&mut emu));
+
+    step(&mut emu, 1);
+    assert_eq!(emu.registers[EAX], 0);
+    assert!(is_carry(&mut emu));
+    assert!(is_sign(&mut emu));
+}
+
+#[test]
+fn accumulator_immediate_forms() {
+    let mut emu = emulator(&[
+        0x04, 0xff, // add al, 0xff
+        0x14, 0x00, // adc al, 0
+        0x05, 0xff, 0xff, 0xff, 0x7f, // add eax, 0x7fffffff
+        0x1d, 0x00, 0x00, 0x00, 0x00, // sbb eax, 0
+        0x24, 0x0f, // and al, 0x0f
+        0x35, 0xff, 0x00, 0x00, 0x00, // xor eax, 0xff
+        0x3c, 0xf0, // cmp al, 0xf0
+        0x3d, 0xf0, 0x00, 0x00, 0x00, // cmp eax, 0xf0
+    ]);
+    emu.registers[EAX] = 0x12345601;
+    step(&mut emu, 1);
+    assert_eq!(emu.registers[EAX], 0x12345600);
+    assert_eq!(
+        status_flags(&mut emu),
+        CARRY_FLAG | ZERO_FLAG | PARITY_FLAG | AUX_CARRY_FLAG
+    );
+    step(&mut emu, 1);
+    assert_eq!(emu.registers[EAX], 0x12345601);
+
+    emu.registers[EAX] = 1;
+    step(&mut emu, 1);
+    assert_eq!(emu.registers[EAX], 0x80000000);
+    assert!(is_overflow(&mut emu));
+    set_carry(&mut emu, true);
+    step(&mut emu, 1);
+    assert_eq!(emu.registers[EAX], 0x7fffffff);
+    assert!(is_overflow(&mut emu));
+
+    step(&mut emu, 1);
+    assert_eq!(emu.registers[EAX], 0x7fffff0f);
+    step(&mut emu, 1);
+    assert_eq!(emu.registers[EAX], 0x7ffffff0);
+
+    step(&mut emu, 1);
+    assert!(is_zero(&mut emu));
+    step(&mut emu, 1);
+    assert!(!is_zero(&mut emu));
+    assert!(!is_carry(&mut emu));
+    assert_eq!(emu.registers[EAX], 0x7ffffff0);
+}
+
+#[test]
+fn operand_size_prefix_selects_16_bit_forms() {
+    let mut emu = emulator(&[
+        0x66, 0x01, 0xd8, // add ax, bx
+        0x66, 0x3d, 0x00, 0x80, // cmp ax, 0x8000
+    ]);
+    emu.registers[EAX] = 0x1234ffff;
+    emu.registers[EBX] = 1;
+    step(&mut emu, 1);
+    assert_eq!(emu.registers[EAX], 0x12340000);
+    assert!(is_carry(&mut emu));
+    assert!(is_zero(&mut emu));
+
+    step(&mut emu, 1);
+    assert!(is_carry(&mut emu));
+    assert!(is_overflow(&mut emu));
+    assert!(is_sign(&mut emu));
+}
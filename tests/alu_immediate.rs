@@ -0,0 +1,106 @@
+mod common;
+
+use x86emu::function::*;
+use x86emu::*;
+
+use common::*;
+
+/// Runs one instruction on EAX with the given carry-in and returns EAX and the
+/// status flags.
+fn alu(code: &[u8], eax: u32, carry: bool) -> (u32, u32) {
+    let mut emu = emulator(code);
+    emu.registers[EAX] = eax;
+    set_carry(&mut emu, carry);
+    emu.step().unwrap();
+    (emu.registers[EAX], status_flags(&mut emu))
+}
+
+#[test]
+fn add_and_sub_set_all_status_flags() {
+    // add eax, 1
+    assert_eq!(
+        alu(&[0x83, 0xc0, 0x01], 0x7fffffff, false),
+        (
+            0x80000000,
+            OVERFLOW_FLAG | SIGN_FLAG | PARITY_FLAG | AUX_CARRY_FLAG
+        )
+    );
+    // sub eax, 1
+    assert_eq!(
+        alu(&[0x81, 0xe8, 0x01, 0x00, 0x00, 0x00], 0x80000000, false),
+        (0x7fffffff, OVERFLOW_FLAG | PARITY_FLAG | AUX_CARRY_FLAG)
+    );
+    // add al, 1
+    assert_eq!(
+        alu(&[0x80, 0xc0, 0x01], 0x123456ff, false),
+        (
+            0x12345600,
+            CARRY_FLAG | ZERO_FLAG | PARITY_FLAG | AUX_CARRY_FLAG
+        )
+    );
+}
+
+#[test]
+fn cmp_only_sets_flags() {
+    // cmp al, 0x80
+    assert_eq!(
+        alu(&[0x80, 0xf8, 0x80], 0x7f, false),
+        (0x7f, CARRY_FLAG | OVERFLOW_FLAG | SIGN_FLAG | PARITY_FLAG)
+    );
+    // cmp eax, -1
+    assert_eq!(
+        alu(&[0x83, 0xf8, 0xff], 0xffffffff, true),
+        (0xffffffff, ZERO_FLAG | PARITY_FLAG)
+    );
+}
+
+#[test]
+fn adc_and_sbb_use_carry_in() {
+    // adc eax, 0
+    assert_eq!(
+        alu(&[0x83, 0xd0, 0x00], 0xffffffff, true),
+        (0, CARRY_FLAG | ZERO_FLAG | PARITY_FLAG | AUX_CARRY_FLAG)
+    );
+    // adc al, 0x7f
+    assert_eq!(
+        alu(&[0x80, 0xd0, 0x7f], 0, true),
+        (0x80, OVERFLOW_FLAG | SIGN_FLAG | AUX_CARRY_FLAG)
+    );
+    // sbb eax, 1
+    assert_eq!(
+        alu(&[0x83, 0xd8, 0x01], 1, true),
+        (
+            0xffffffff,
+            CARRY_FLAG | SIGN_FLAG | PARITY_FLAG | AUX_CARRY_FLAG
+        )
+    );
+    // sbb eax, 7
+    assert_eq!(
+        alu(&[0x81, 0xd8, 0x07, 0x00, 0x00, 0x00], 5, false),
+        (0xfffffffe, CARRY_FLAG | SIGN_FLAG | AUX_CARRY_FLAG)
+    );
+}
+
+#[test]
+fn logic_operations_clear_carry_and_overflow() {
+    // AF is undefined after the logic operations.
+    let logic = |code: &[u8], eax| {
+        let (result, flags) = alu(code, eax, true);
+        (result, flags & !AUX_CARRY_FLAG)
+    };
+    // or eax, -0x80
+    assert_eq!(
+        logic(&[0x83, 0xc8, 0x80], 0x0000000f),
+        (0xffffff8f, SIGN_FLAG)
+    );
+    // and eax, 0xf0f0f0f0
+    assert_eq!(
+        logic(&[0x81, 0xe0, 0xf0, 0xf0, 0xf0, 0xf0], 0x0f0f0f0f),
+        (0, ZERO_FLAG | PARITY_FLAG)
+    );
+    // xor al, 0xff
+    assert_eq!(
+        logic(&[0x80, 0xf0, 0xff], 0x12345655),
+        (0x123456aa, SIGN_FLAG | PARITY_FLAG)
+    );
+}
@@ -0,0 +1,48 @@
+//! Fixtures shared by the integration tests.
+#![allow(dead_code)]
+
+use x86emu::function::*;
+use x86emu::*;
+
+pub const ORIGIN: u32 = 0x7c00;
+
+/// A 32-bit flat emulator with 64KB of memory and `code` loaded at `ORIGIN`.
+pub fn emulator(code: &[u8]) -> Emulator {
+    let mut emu = Emulator::new(0x10000, ORIGIN, 0x7c00);
+    emu.load(ORIGIN as usize, code).unwrap();
+    emu
+}
+
+/// A 32-bit flat emulator with 1MB of memory and `code` loaded at `ORIGIN`.
+pub fn flat(code: &[u8]) -> Emulator {
+    let mut emu = Emulator::new(0x100000, ORIGIN, 0x7c00);
+    emu.load(ORIGIN as usize, code).unwrap();
+    emu
+}
+
+/// A real-mode emulator with 1MB of memory and `code` loaded at 0000:7C00.
+pub fn real(code: &[u8]) -> Emulator {
+    let mut emu = Emulator::new_real_mode(0x100000, ORIGIN as u16, 0x7c00);
+    emu.load(ORIGIN as usize, code).unwrap();
+    emu
+}
+
+pub fn step(emu: &mut Emulator, count: usize) {
+    for _ in 0..count {
+        emu.step().unwrap();
+    }
+}
+
+/// Points the real-mode interrupt vector at `segment:offset`.
+pub fn set_vector(emu: &mut Emulator, vector: u8, segment: u16, offset: u16) {
+    let entry = (segment as u32) << 16 | offset as u32;
+    set_memory32(emu, vector as u32 * 4, entry).unwrap();
+}
+
+/// CF, PF, AF, ZF, SF and OF.
+pub const STATUS_FLAGS: u32 =
+    CARRY_FLAG | PARITY_FLAG | AUX_CARRY_FLAG | ZERO_FLAG | SIGN_FLAG | OVERFLOW_FLAG;
+
+pub fn status_flags(emu: &mut Emulator) -> u32 {
+    get_eflags(emu) & STATUS_FLAGS
+}
@@ -0,0 +1,69 @@
+mod common;
+
+use x86emu::function::*;
+use x86emu::*;
+
+use common::*;
+
+/// Runs `cmp eax, ecx` followed by the short and near forms of condition `cc` and
+/// returns whether each jump was taken.
+fn jumps_taken(cc: u8, eax: u32, ecx: u32) -> (bool, bool) {
+    let mut emu = emulator(&[0x39, 0xc8, 0x70 + cc, 0x10]);
+    emu.registers[EAX] = eax;
+    emu.registers[ECX] = ecx;
+    emu.step().unwrap();
+    emu.step().unwrap();
+    let short = emu.eip == ORIGIN + 0x14;
+
+    let mut emu = emulator(&[0x39, 0xc8, 0x0f, 0x80 + cc, 0x10, 0x00, 0x00, 0x00]);
+    emu.registers[EAX] = eax;
+    emu.registers[ECX] = ecx;
+    emu.step().unwrap();
+    emu.step().unwrap();
+    let near = emu.eip == ORIGIN + 0x18;
+    (short, near)
+}
+
+#[test]
+fn all_conditions_in_both_encodings() {
+    // (cc, eax, ecx, taken)
+    let cases = [
+        (0x0, 0x80000000, 1, true),  // jo
+        (0x1, 0x80000000, 1, false), // jno
+        (0x2, 1, 2, true),           // jb
+        (0x3, 1, 2, false),          // jae
+        (0x4, 5, 5, true),           // je
+        (0x5, 5, 5, false),          // jne
+        (0x6, 5, 5, true),           // jbe
+        (0x7, 0xffffffff, 1, true),  // ja
+        (0x8, 1, 2, true),           // js
+        (0x9, 1, 2, false),          // jns
+        (0xA, 3, 0, true),           // jp
+        (0xB, 3, 0, false),          // jnp
+        (0xC, 0xffffffff, 1, true),  // jl
+        (0xD, 0xffffffff, 1, false), // jge
+        (0xE, 2, 2, true),           // jle
+        (0xF, 2, 0xffffffff, true),  // jg
+    ];
+    for &(cc, eax, ecx, taken) in cases.iter() {
+        assert_eq!(jumps_taken(cc, eax, ecx), (taken, taken), "cc = {:x}", cc);
+        assert_eq!(
+            jumps_taken(cc ^ 1, eax, ecx),
+            (!taken, !taken),
+            "cc = {:x}",
+            cc ^ 1
+        );
+    }
+}
+
+#[test]
+fn condition_matches_flags() {
+    let mut emu = Emulator::new(0x100, 0, 0);
+    set_carry(&mut emu, true);
+    assert!(condition(&mut emu, 0x6));
+    assert!(!condition(&mut emu, 0x7));
+    set_sign(&mut emu, true);
+    assert!(condition(&mut emu, 0xC));
+    set_overflow(&mut emu, true);
+    assert!(condition(&mut emu, 0xD));
+}
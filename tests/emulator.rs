@@ -0,0 +1,20 @@
+mod common;
+
+use x86emu::*;
+
+use common::*;
+
+#[test]
+fn run_hands_unimplemented_opcode_to_caller() {
+    // inc eax; salc
+    let mut emu = emulator(&[0x40, 0xd6]);
+    assert_eq!(
+        emu.run(),
+        Err(EmuError::UnimplementedOpcode {
+            eip: ORIGIN + 1,
+            bytes: vec![0xd6]
+        })
+    );
+    assert_eq!(emu.eip, ORIGIN + 1);
+    assert_eq!(emu.registers[EAX], 1);
+}
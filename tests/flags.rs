@@ -0,0 +1,81 @@
+mod common;
+
+use x86emu::function::*;
+use x86emu::*;
+
+use common::*;
+
+#[test]
+fn reserved_bit_is_always_set() {
+    // push 0; popf; pushf
+    let mut emu = emulator(&[0x6a, 0x00, 0x9d, 0x9c]);
+    step(&mut emu, 3);
+    assert_eq!(get_memory32(&mut emu, 0x7bfc).unwrap(), 0x2);
+}
+
+#[test]
+fn popf_ignores_reserved_bits() {
+    // push -1; popf
+    let mut emu = emulator(&[0x6a, 0xff, 0x9d]);
+    step(&mut emu, 2);
+    assert_eq!(emu.eflags, 0x7fd7);
+    assert!(is_interrupt(&emu));
+    assert!(is_trap(&emu));
+}
+
+#[test]
+fn lahf_and_sahf() {
+    // mov ah, 0xd5; sahf; xor eax, eax; lahf
+    let mut emu = emulator(&[0xb4, 0xd5, 0x9e, 0x31, 0xc0, 0x9f]);
+    step(&mut emu, 2);
+    assert!(is_sign(&mut emu));
+    assert!(is_zero(&mut emu));
+    assert!(is_aux_carry(&mut emu));
+    assert!(is_parity(&mut emu));
+    assert!(is_carry(&mut emu));
+    step(&mut emu, 2);
+    assert_eq!(get_register8(&emu, AH), 0x46);
+}
+
+#[test]
+fn flag_instructions() {
+    // stc; cmc; std; sti; cld; cli; clc
+    let mut emu = emulator(&[0xf9, 0xf5, 0xfd, 0xfb, 0xfc, 0xfa, 0xf8]);
+    step(&mut emu, 1);
+    assert!(is_carry(&mut emu));
+    step(&mut emu, 1);
+    assert!(!is_carry(&mut emu));
+    step(&mut emu, 2);
+    assert!(is_direction(&emu));
+    assert!(is_interrupt(&emu));
+    step(&mut emu, 3);
+    assert_eq!(emu.eflags, 0x2);
+}
+
+#[test]
+fn inc_and_dec_keep_carry() {
+    // stc; inc al; dec ecx
+    let mut emu = emulator(&[0xf9, 0xfe, 0xc0, 0x49]);
+    emu.registers[EAX] = 0x0f;
+    emu.registers[ECX] = 0x80000000;
+    step(&mut emu, 2);
+    assert_eq!(emu.registers[EAX], 0x10);
+    assert!(is_carry(&mut emu));
+    assert!(is_aux_carry(&mut emu));
+    assert!(!is_parity(&mut emu));
+    step(&mut emu, 1);
+    assert_eq!(emu.registers[ECX], 0x7fffffff);
+    assert!(is_carry(&mut emu));
+    assert!(is_overflow(&mut emu));
+}
+
+#[test]
+fn parity_and_aux_carry_from_add() {
+    // add al, 0x09
+    let mut emu = emulator(&[0x04, 0x09]);
+    emu.registers[EAX] = 0x08;
+    step(&mut emu, 1);
+    assert_eq!(emu.registers[EAX], 0x11);
+    assert!(is_aux_carry(&mut emu));
+    assert!(is_parity(&mut emu));
+}
@@ -0,0 +1,153 @@
+mod common;
+
+use x86emu::bios::*;
+use x86emu::function::*;
+use x86emu::*;
+
+use common::*;
+
+#[test]
+fn int_and_iret_through_ivt() {
+    // sti; int 0x21; ...; at 0x0800:0x0010: iret
+    let mut emu = real(&[0xfb, 0xcd, 0x21]);
+    emu.load(0x8010, &[0xcf]).unwrap();
+    set_vector(&mut emu, 0x21, 0x0800, 0x0010);
+    emu.step().unwrap();
+    emu.step().unwrap();
+    assert_eq!(emu.sregs[CS].selector, 0x0800);
+    assert_eq!(emu.eip, 0x0010);
+    assert_eq!(emu.registers[ESP], 0x7bfa);
+    assert_eq!(get_memory16(&mut emu, 0x7bfa).unwrap(), 0x7c03);
+    assert_eq!(get_memory16(&mut emu, 0x7bfc).unwrap(), 0);
+    assert_eq!(get_memory16(&mut emu, 0x7bfe).unwrap(), 0x0202);
+    assert!(!is_interrupt(&emu));
+
+    emu.step().unwrap();
+    assert_eq!(emu.sregs[CS].selector, 0);
+    assert_eq!(emu.eip, 0x7c03);
+    assert_eq!(emu.registers[ESP], 0x7c00);
+    assert!(is_interrupt(&emu));
+}
+
+#[test]
+fn divide_error_returns_to_faulting_instruction() {
+    // div cl
+    let mut emu = real(&[0xf6, 0xf1]);
+    set_vector(&mut emu, 0, 0x0800, 0x0000);
+    emu.step().unwrap();
+    assert_eq!(emu.sregs[CS].selector, 0x0800);
+    assert_eq!(emu.eip, 0);
+    assert_eq!(get_memory16(&mut emu, 0x7bfa).unwrap(), 0x7c00);
+}
+
+#[test]
+fn real_mode_fault_pushes_no_error_code() {
+    // mov ax, [0xffff]; at 0x0800:0x0000: iret
+    let mut emu = real(&[0x8b, 0x06, 0xff, 0xff]);
+    emu.load(0x8000, &[0xcf]).unwrap();
+    set_vector(&mut emu, 13, 0x0800, 0x0000);
+    emu.step().unwrap();
+    assert_eq!(emu.sregs[CS].selector, 0x0800);
+    assert_eq!(emu.registers[ESP], 0x7bfa);
+
+    emu.step().unwrap();
+    assert_eq!(emu.sregs[CS].selector, 0);
+    assert_eq!(emu.eip, 0x7c00);
+    assert_eq!(emu.registers[ESP], 0x7c00);
+}
+
+#[test]
+fn undefined_opcode_raises_ud() {
+    let mut emu = real(&[0x0f, 0xff]);
+    match emu.step() {
+        Err(EmuError::UnimplementedOpcode { .. }) => {}
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+    set_vector(&mut emu, 6, 0x0900, 0x0004);
+    emu.step().unwrap();
+    assert_eq!(emu.sregs[CS].selector, 0x0900);
+    assert_eq!(emu.eip, 0x0004);
+}
+
+#[test]
+fn int3_and_into_are_traps() {
+    // int3; into
+    let mut emu = real(&[0xcc, 0xce]);
+    set_vector(&mut emu, 3, 0, 0x7c01);
+    set_vector(&mut emu, 4, 0x0800, 0);
+    emu.step().unwrap();
+    assert_eq!(emu.eip, 0x7c01);
+    assert_eq!(get_memory16(&mut emu, 0x7bfa).unwrap(), 0x7c01);
+
+    // INTO without OF falls through.
+    emu.step().unwrap();
+    assert_eq!(emu.eip, 0x7c02);
+
+    emu.eip = 0x7c01;
+    set_overflow(&mut emu, true);
+    emu.step().unwrap();
+    assert_eq!(emu.sregs[CS].selector, 0x0800);
+    assert_eq!(get_memory16(&mut emu, 0x7bf4).unwrap(), 0x7c02);
+}
+
+#[test]
+fn unhandled_software_interrupt_is_reported() {
+    let mut emu = real(&[0xcd, 0x80]);
+    assert_eq!(
+        emu.step(),
+        Err(EmuError::UnknownInterrupt {
+            eip: 0x7c00,
+            vector: 0x80
+        })
+    );
+    assert_eq!(emu.eip, 0x7c00);
+}
+
+#[test]
+fn bios_services_are_hooked_stubs() {
+    // int 0x10 with an unimplemented function
+    let mut emu = real(&[0xb4, 0xff, 0xcd, 0x10]);
+    install_bios(&mut emu).unwrap();
+    assert_eq!(get_memory32(&mut emu, 0x40).unwrap(), 0xf0000010);
+    emu.step().unwrap();
+    emu.step().unwrap();
+    assert_eq!(emu.sregs[CS].base, 0xf0000);
+    assert_eq!(
+        emu.step(),
+        Err(EmuError::UnimplementedBiosFunction {
+            eip: 0x0010,
+            vector: 0x10,
+            function: 0xff
+        })
+    );
+}
+
+#[test]
+fn hooks_run_before_the_instruction() {
+    fn hook(emu: &mut Emulator) -> EmuResult {
+        emu.registers[EBX] = 0x1234;
+        Ok(())
+    }
+
+    // int 0x20, with a hooked iret at 0xf0020
+    let mut emu = real(&[0xcd, 0x20]);
+    emu.load(0xf0020, &[0xcf]).unwrap();
+    set_vector(&mut emu, 0x20, 0xf000, 0x0020);
+    emu.hooks.insert(0xf0020, hook);
+    emu.step().unwrap();
+    emu.step().unwrap();
+    assert_eq!(emu.registers[EBX], 0x1234);
+    assert_eq!(emu.eip, 0x7c02);
+    assert_eq!(emu.sregs[CS].selector, 0);
+}
+
+#[test]
+fn fault_while_delivering_double_fault_is_a_triple_fault() {
+    // div cl with an empty IVT limit: #DE and then #DF both raise #GP
+    let mut emu = real(&[0xf6, 0xf1]);
+    set_vector(&mut emu, 0, 0x0800, 0);
+    set_vector(&mut emu, 8, 0x0900, 0);
+    emu.idtr.limit = 0;
+    assert_eq!(emu.step(), Err(EmuError::TripleFault { eip: 0x7c00 }));
+}
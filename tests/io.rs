@@ -0,0 +1,98 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use x86emu::*;
+
+/// Records every write and answers reads with the low byte of the port number.
+#[derive(Default)]
+struct Recorder {
+    writes: Vec<(u16, u32, u32)>,
+}
+
+impl IoDevice for Recorder {
+    fn in8(&mut self, port: u16) -> u8 {
+        port as u8
+    }
+
+    fn out8(&mut self, port: u16, value: u8) {
+        self.writes.push((port, 8, value as u32));
+    }
+
+    fn out32(&mut self, port: u16, value: u32) {
+        self.writes.push((port, 32, value));
+    }
+}
+
+fn emulator(code: &[u8]) -> (Emulator, Rc<RefCell<Recorder>>) {
+    let mut emu = Emulator::new(0x10000, 0x7c00, 0x7c00);
+    emu.load(0x7c00, code).unwrap();
+    let recorder = Rc::new(RefCell::new(Recorder::default()));
+    emu.io.register(0x40, 4, Box::new(recorder.clone()));
+    emu.io.register(0x3f8, 8, Box::new(recorder.clone()));
+    (emu, recorder)
+}
+
+#[test]
+fn in_reads_from_registered_device() {
+    let (mut emu, _) = emulator(&[
+        0xe4, 0x42, // in al, 0x42
+        0xe5, 0x40, // in eax, 0x40
+        0x66, 0xed, // in ax, dx
+        0xec, // in al, dx
+    ]);
+    emu.registers[EAX] = 0xffffffff;
+
+    emu.step().unwrap();
+    assert_eq!(emu.registers[EAX], 0xffffff42);
+    emu.step().unwrap();
+    assert_eq!(emu.registers[EAX], 0x43424140);
+
+    emu.registers[EDX] = 0x103f9;
+    emu.step().unwrap();
+    assert_eq!(emu.registers[EAX], 0x4342faf9);
+
+    // Nothing is attached at 0x80, so the bus floats high.
+    emu.registers[EDX] = 0x80;
+    emu.step().unwrap();
+    assert_eq!(emu.registers[EAX] & 0xff, 0xff);
+}
+
+#[test]
+fn out_writes_to_registered_device() {
+    let (mut emu, recorder) = emulator(&[
+        0xe6, 0x43, // out 0x43, al
+        0xe7, 0x40, // out 0x40, eax
+        0xee, // out dx, al
+        0x66, 0xef, // out dx, ax
+        0xe6, 0x80, // out 0x80, al
+    ]);
+    emu.registers[EAX] = 0x12345678;
+    emu.registers[EDX] = 0x3fc;
+    for _ in 0..5 {
+        emu.step().unwrap();
+    }
+    assert_eq!(emu.eip, 0x7c09);
+    assert_eq!(
+        recorder.borrow().writes,
+        vec![
+            (0x43, 8, 0x78),
+            (0x40, 32, 0x12345678),
+            (0x3fc, 8, 0x78),
+            (0x3fc, 8, 0x78),
+            (0x3fd, 8, 0x56),
+        ]
+    );
+}
+
+#[test]
+fn later_registration_takes_over_ports() {
+    let (mut emu, recorder) = emulator(&[]);
+    let other = Rc::new(RefCell::new(Recorder::default()));
+    emu.io.register(0x42, 1, Box::new(other.clone()));
+
+    emu.io.write(0x41, 8, 1);
+    emu.io.write(0x42, 8, 2);
+    assert_eq!(recorder.borrow().writes, vec![(0x41, 8, 1)]);
+    assert_eq!(other.borrow().writes, vec![(0x42, 8, 2)]);
+    assert_eq!(emu.io.read(0x44, 16), 0xffff);
+}
@@ -0,0 +1,42 @@
+mod common;
+
+use x86emu::function::*;
+
+use common::*;
+
+#[test]
+fn alu_results_are_pending_until_read() {
+    // sub eax, 1
+    let mut emu = emulator(&[0x83, 0xe8, 0x01]);
+    emu.step().unwrap();
+    assert!(emu.lazy_flags.is_pending());
+    assert_eq!(emu.eflags, 0x2);
+    assert_eq!(
+        get_eflags(&mut emu),
+        0x2 | CARRY_FLAG | PARITY_FLAG | AUX_CARRY_FLAG | SIGN_FLAG
+    );
+    assert!(!emu.lazy_flags.is_pending());
+}
+
+#[test]
+fn pushf_materializes_pending_flags() {
+    // cmp eax, eax; pushf
+    let mut emu = emulator(&[0x39, 0xc0, 0x9c]);
+    emu.step().unwrap();
+    emu.step().unwrap();
+    assert_eq!(
+        get_memory32(&mut emu, 0x7bfc).unwrap(),
+        0x2 | PARITY_FLAG | ZERO_FLAG
+    );
+}
+
+#[test]
+fn setting_one_flag_keeps_the_pending_ones() {
+    // xor eax, eax; stc
+    let mut emu = emulator(&[0x31, 0xc0, 0xf9]);
+    emu.step().unwrap();
+    emu.step().unwrap();
+    assert!(is_carry(&mut emu));
+    assert!(is_zero(&mut emu));
+    assert!(is_parity(&mut emu));
+}
@@ -0,0 +1,88 @@
+mod common;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use x86emu::bios::*;
+use x86emu::function::*;
+use x86emu::*;
+
+use common::*;
+
+/// A text-mode frame buffer that records the characters written to it.
+#[derive(Default)]
+struct TextScreen {
+    cells: Vec<u8>,
+    text: String,
+}
+
+impl MmioDevice for TextScreen {
+    fn read8(&mut self, offset: u32) -> u8 {
+        self.cells.get(offset as usize).copied().unwrap_or(0)
+    }
+
+    fn write8(&mut self, offset: u32, value: u8) {
+        if self.cells.len() <= offset as usize {
+            self.cells.resize(offset as usize + 1, 0);
+        }
+        self.cells[offset as usize] = value;
+        if offset & 1 == 0 {
+            self.text.push(value as char);
+        }
+    }
+}
+
+#[test]
+fn mmio_region_receives_guest_accesses() {
+    let mut emu = flat(&[
+        0x66, 0xc7, 0x05, 0x00, 0x80, 0x0b, 0x00, 0x48, 0x07, // mov word [0xb8000], 0x0748
+        0xb0, 0x69, // mov al, 'i'
+        0x88, 0x05, 0x02, 0x80, 0x0b, 0x00, // mov [0xb8002], al
+        0x8b, 0x1d, 0x00, 0x80, 0x0b, 0x00, // mov ebx, [0xb8000]
+    ]);
+    let screen = Rc::new(RefCell::new(TextScreen::default()));
+    emu.memory.add_mmio(0xb8000, 4000, Box::new(screen.clone()));
+
+    for _ in 0..4 {
+        emu.step().unwrap();
+    }
+    assert_eq!(screen.borrow().text, "Hi");
+    assert_eq!(emu.registers[EBX], 0x00690748);
+    // The RAM underneath is untouched.
+    assert_eq!(emu.memory.ram()[0xb8000], 0);
+}
+
+#[test]
+fn rom_ignores_writes() {
+    let mut emu = flat(&[]);
+    emu.memory.add_rom(0xf0000, vec![0x12, 0x34, 0x56, 0x78]);
+    set_memory32(&mut emu, 0xf0000, 0xffffffff).unwrap();
+    assert_eq!(get_memory32(&mut emu, 0xf0000).unwrap(), 0x78563412);
+    // Past the image the base RAM shows through again.
+    set_memory8(&mut emu, 0xf0004, 0xaa).unwrap();
+    assert_eq!(get_memory8(&mut emu, 0xf0004).unwrap(), 0xaa);
+}
+
+#[test]
+fn ram_regions_extend_the_address_space() {
+    let mut emu = emulator(&[]);
+    emu.memory.add_ram(0x200000, 0x1000);
+    set_memory32(&mut emu, 0x200ffc, 0xcafebabe).unwrap();
+    assert_eq!(get_memory32(&mut emu, 0x200ffc).unwrap(), 0xcafebabe);
+    assert_eq!(
+        get_memory8(&mut emu, 0x201000),
+        Err(EmuError::MemoryFault {
+            eip: 0x7c00,
+            address: 0x201000
+        })
+    );
+}
+
+#[test]
+fn bios_rom_is_mapped_without_ram_behind_it() {
+    let mut emu = real(&[]);
+    install_bios(&mut emu).unwrap();
+    assert_eq!(get_memory8(&mut emu, 0xf0010).unwrap(), 0xcf);
+    set_memory8(&mut emu, 0xf0010, 0x90).unwrap();
+    assert_eq!(get_memory8(&mut emu, 0xf0010).unwrap(), 0xcf);
+}
@@ -0,0 +1,120 @@
+mod common;
+
+use x86emu::function::*;
+use x86emu::*;
+
+use common::*;
+
+#[test]
+fn mul_r32_writes_edx_eax() {
+    // mul ecx
+    let mut emu = emulator(&[0xf7, 0xe1]);
+    emu.registers[EAX] = 0x80000000;
+    emu.registers[ECX] = 4;
+    emu.step().unwrap();
+    assert_eq!(emu.registers[EAX], 0);
+    assert_eq!(emu.registers[EDX], 2);
+    assert!(is_carry(&mut emu));
+    assert!(is_overflow(&mut emu));
+}
+
+#[test]
+fn mul_r8_writes_ax() {
+    // mul cl
+    let mut emu = emulator(&[0xf6, 0xe1]);
+    emu.registers[EAX] = 0x12345610;
+    emu.registers[ECX] = 0x08;
+    emu.step().unwrap();
+    assert_eq!(emu.registers[EAX], 0x12340080);
+    assert!(!is_carry(&mut emu));
+    assert!(!is_overflow(&mut emu));
+}
+
+#[test]
+fn imul_r32_sign_extends_into_edx() {
+    // imul ecx
+    let mut emu = emulator(&[0xf7, 0xe9]);
+    emu.registers[EAX] = 0xffffffff;
+    emu.registers[ECX] = 3;
+    emu.step().unwrap();
+    assert_eq!(emu.registers[EAX], 0xfffffffd);
+    assert_eq!(emu.registers[EDX], 0xffffffff);
+    assert!(!is_carry(&mut emu));
+    assert!(!is_overflow(&mut emu));
+}
+
+#[test]
+fn imul_two_and_three_operand_forms() {
+    // imul eax, ecx, -2; imul eax, ecx, 0x40000000; imul eax, ecx
+    let mut emu = emulator(&[
+        0x6b, 0xc1, 0xfe, 0x69, 0xc1, 0x00, 0x00, 0x00, 0x40, 0x0f, 0xaf, 0xc1,
+    ]);
+    emu.registers[ECX] = 5;
+    emu.step().unwrap();
+    assert_eq!(emu.registers[EAX], -10i32 as u32);
+    assert!(!is_overflow(&mut emu));
+    emu.step().unwrap();
+    assert_eq!(emu.registers[EAX], 0x40000000);
+    assert!(is_carry(&mut emu));
+    assert!(is_overflow(&mut emu));
+    emu.step().unwrap();
+    assert_eq!(emu.registers[EAX], 0x40000000);
+    assert_eq!(emu.eip, ORIGIN + 12);
+}
+
+#[test]
+fn div_and_idiv() {
+    // div ecx; idiv cl
+    let mut emu = emulator(&[0xf7, 0xf1, 0xf6, 0xf9]);
+    emu.registers[EDX] = 1;
+    emu.registers[EAX] = 5;
+    emu.registers[ECX] = 0x10;
+    emu.step().unwrap();
+    assert_eq!(emu.registers[EAX], 0x10000000);
+    assert_eq!(emu.registers[EDX], 5);
+
+    emu.registers[EAX] = -7i16 as u16 as u32;
+    emu.registers[ECX] = 2;
+    emu.step().unwrap();
+    assert_eq!(get_register8(&emu, AL), -3i8 as u8);
+    assert_eq!(get_register8(&emu, AH), -1i8 as u8);
+}
+
+#[test]
+fn divide_errors_raise_de() {
+    // div ecx with ecx = 0
+    let mut emu = emulator(&[0xf7, 0xf1]);
+    assert_eq!(
+        emu.step(),
+        Err(EmuError::Exception {
+            eip: ORIGIN,
+            vector: 0,
+            error_code: None
+        })
+    );
+    assert_eq!(emu.eip, ORIGIN);
+
+    // div cl with a quotient that does not fit in AL
+    let mut emu = emulator(&[0xf6, 0xf1]);
+    emu.registers[EAX] = 0x100;
+    emu.registers[ECX] = 1;
+    assert!(emu.step().is_err());
+
+    // idiv ecx with EDX:EAX = INT64_MIN, ECX = -1
+    let mut emu = emulator(&[0xf7, 0xf9]);
+    emu.registers[EDX] = 0x80000000;
+    emu.registers[ECX] = 0xffffffff;
+    assert!(emu.step().is_err());
+}
+
+#[test]
+fn neg_and_not() {
+    // neg eax; not ecx
+    let mut emu = emulator(&[0xf7, 0xd8, 0xf7, 0xd1]);
+    emu.registers[EAX] = 1;
+    emu.step().unwrap();
+    assert_eq!(emu.registers[EAX], 0xffffffff);
+    assert!(is_carry(&mut emu));
+    emu.step().unwrap();
+    assert_eq!(emu.registers[ECX], 0xffffffff);
+}
@@ -0,0 +1,156 @@
+mod common;
+
+use x86emu::function::*;
+use x86emu::paging::*;
+use x86emu::*;
+
+use common::*;
+
+const PAGE_DIRECTORY: u32 = 0x10000;
+const LOW_TABLE: u32 = 0x11000;
+const HIGH_TABLE: u32 = 0x12000;
+const PAGE: u32 = 0x400000;
+const FRAME: u32 = 0x30000;
+
+const PRESENT_RW: u32 = PAGE_PRESENT | PAGE_WRITABLE;
+const PRESENT_RW_USER: u32 = PAGE_PRESENT | PAGE_WRITABLE | PAGE_USER;
+
+/// Identity-maps the first megabyte and maps the page at 0x400000 to 0x30000 with
+/// `flags`, then turns paging on.
+fn paged(code: &[u8], flags: u32) -> Emulator {
+    let mut emu = flat(code);
+    set_physical32(&mut emu, PAGE_DIRECTORY, LOW_TABLE | PRESENT_RW_USER).unwrap();
+    set_physical32(&mut emu, PAGE_DIRECTORY + 4, HIGH_TABLE | PRESENT_RW_USER).unwrap();
+    for i in 0..0x100 {
+        set_physical32(&mut emu, LOW_TABLE + i * 4, (i << 12) | PRESENT_RW_USER).unwrap();
+    }
+    set_physical32(&mut emu, HIGH_TABLE, FRAME | flags).unwrap();
+    emu.cr[3] = PAGE_DIRECTORY;
+    emu.cr[0] = CR0_PE | CR0_ET | CR0_PG;
+    emu
+}
+
+fn page_fault<T>(eip: u32, error_code: u32) -> EmuResult<T> {
+    Err(EmuError::Exception {
+        eip,
+        vector: 14,
+        error_code: Some(error_code),
+    })
+}
+
+#[test]
+fn accesses_set_accessed_and_dirty_bits() {
+    let mut emu = paged(
+        &[
+            0x8b, 0x1d, 0x00, 0x00, 0x40, 0x00, // mov ebx, [0x400000]
+            0x89, 0x05, 0x04, 0x00, 0x40, 0x00, // mov [0x400004], eax
+        ],
+        PRESENT_RW,
+    );
+    set_physical32(&mut emu, FRAME, 0xcafe).unwrap();
+    emu.registers[EAX] = 0x12345678;
+
+    emu.step().unwrap();
+    assert_eq!(emu.registers[EBX], 0xcafe);
+    assert_eq!(
+        get_physical32(&mut emu, HIGH_TABLE).unwrap(),
+        FRAME | PRESENT_RW | PAGE_ACCESSED
+    );
+    assert_eq!(
+        get_physical32(&mut emu, PAGE_DIRECTORY + 4).unwrap() & PAGE_ACCESSED,
+        PAGE_ACCESSED
+    );
+
+    emu.step().unwrap();
+    assert_eq!(get_physical32(&mut emu, FRAME + 4).unwrap(), 0x12345678);
+    assert_eq!(
+        get_physical32(&mut emu, HIGH_TABLE).unwrap(),
+        FRAME | PRESENT_RW | PAGE_ACCESSED | PAGE_DIRTY
+    );
+}
+
+#[test]
+fn not_present_page_faults_with_cr2() {
+    let mut emu = paged(&[], PRESENT_RW);
+    assert_eq!(get_memory32(&mut emu, 0x401010), page_fault(0x7c00, 0));
+    assert_eq!(emu.cr[2], 0x401010);
+    assert_eq!(set_memory8(&mut emu, 0x800000, 0), page_fault(0x7c00, 2));
+    assert_eq!(emu.cr[2], 0x800000);
+
+    // A write straddling into a missing page stores nothing.
+    assert_eq!(
+        set_memory32(&mut emu, 0x400ffe, 0xffffffff),
+        page_fault(0x7c00, 2)
+    );
+    assert_eq!(emu.cr[2], 0x401000);
+    assert_eq!(get_physical8(&mut emu, FRAME + 0xffe).unwrap(), 0);
+}
+
+#[test]
+fn user_accesses_are_checked() {
+    let mut emu = paged(&[], PAGE_PRESENT | PAGE_WRITABLE);
+    emu.cpl = 3;
+    assert_eq!(get_memory8(&mut emu, PAGE), page_fault(0x7c00, 5));
+
+    let mut emu = paged(&[], PAGE_PRESENT | PAGE_USER);
+    emu.cpl = 3;
+    assert_eq!(get_memory8(&mut emu, PAGE), Ok(0));
+    assert_eq!(set_memory8(&mut emu, PAGE, 1), page_fault(0x7c00, 7));
+}
+
+#[test]
+fn supervisor_writes_honour_cr0_wp() {
+    let mut emu = paged(&[], PAGE_PRESENT);
+    set_memory8(&mut emu, PAGE, 0x55).unwrap();
+    assert_eq!(get_physical8(&mut emu, FRAME).unwrap(), 0x55);
+
+    emu.cr[0] |= CR0_WP;
+    assert_eq!(set_memory8(&mut emu, PAGE + 1, 0x55), page_fault(0x7c00, 3));
+}
+
+#[test]
+fn large_pages_map_4mb() {
+    let mut emu = paged(&[], PRESENT_RW);
+    set_physical32(&mut emu, PAGE_DIRECTORY + 8, PRESENT_RW | PAGE_SIZE).unwrap();
+    emu.cr[4] = CR4_PSE;
+    set_memory32(&mut emu, 0x812340, 0xdeadbeef).unwrap();
+    assert_eq!(get_physical32(&mut emu, 0x12340).unwrap(), 0xdeadbeef);
+    assert_eq!(
+        get_physical32(&mut emu, PAGE_DIRECTORY + 8).unwrap(),
+        PRESENT_RW | PAGE_SIZE | PAGE_ACCESSED | PAGE_DIRTY
+    );
+
+    // Without CR4.PSE the entry points to a page table at 0.
+    emu.cr[4] = 0;
+    emu.tlb.flush();
+    assert_eq!(get_memory32(&mut emu, 0x812340), page_fault(0x7c00, 0));
+}
+
+#[test]
+fn invlpg_drops_stale_translation() {
+    let mut emu = paged(
+        &[
+            0x0f, 0x01, 0x3d, 0x00, 0x00, 0x40, 0x00, // invlpg [0x400000]
+            0x0f, 0x20, 0xd8, // mov eax, cr3
+            0x0f, 0x22, 0xd8, // mov cr3, eax
+        ],
+        PRESENT_RW,
+    );
+    set_physical32(&mut emu, FRAME, 1).unwrap();
+    set_physical32(&mut emu, FRAME + 0x1000, 2).unwrap();
+    set_physical32(&mut emu, FRAME + 0x2000, 3).unwrap();
+    assert_eq!(get_memory32(&mut emu, PAGE), Ok(1));
+
+    // The TLB keeps the old mapping until the page is invalidated.
+    set_physical32(&mut emu, HIGH_TABLE, (FRAME + 0x1000) | PRESENT_RW).unwrap();
+    assert_eq!(get_memory32(&mut emu, PAGE), Ok(1));
+    emu.step().unwrap();
+    assert_eq!(get_memory32(&mut emu, PAGE), Ok(2));
+
+    // Reloading CR3 flushes every entry.
+    set_physical32(&mut emu, HIGH_TABLE, (FRAME + 0x2000) | PRESENT_RW).unwrap();
+    assert_eq!(get_memory32(&mut emu, PAGE), Ok(2));
+    emu.step().unwrap();
+    emu.step().unwrap();
+    assert_eq!(get_memory32(&mut emu, PAGE), Ok(3));
+}
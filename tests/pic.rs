@@ -0,0 +1,173 @@
+mod common;
+
+use x86emu::bios::*;
+use x86emu::function::*;
+use x86emu::*;
+
+use common::*;
+
+fn in_service(emu: &mut Emulator, port: u16) -> u8 {
+    emu.io.write(port, 8, 0x0b);
+    let isr = emu.io.read(port, 8) as u8;
+    emu.io.write(port, 8, 0x0a);
+    isr
+}
+
+/// Reprograms both controllers with the vectors at `master` and `slave`.
+fn initialize(emu: &mut Emulator, master: u8, slave: u8, icw4: u8) {
+    for &(port, base, icw3) in [(0x20, master, 0x04), (0xa0, slave, 0x02)].iter() {
+        emu.io.write(port, 8, 0x11);
+        emu.io.write(port + 1, 8, base as u32);
+        emu.io.write(port + 1, 8, icw3);
+        emu.io.write(port + 1, 8, icw4 as u32);
+    }
+}
+
+#[test]
+fn irq_is_taken_after_sti_and_ended_by_eoi() {
+    // inc ax; sti; inc ax; inc ax
+    let mut emu = real(&[0x40, 0xfb, 0x40, 0x40]);
+    // mov al, 0x20; out 0x20, al; iret
+    emu.load(0x1000, &[0xb0, 0x20, 0xe6, 0x20, 0xcf]).unwrap();
+    set_vector(&mut emu, 0x0c, 0, 0x1000);
+    let irq4 = emu.irq_lines.line(4);
+    irq4.set(true);
+
+    emu.step().unwrap();
+    emu.step().unwrap();
+    // STI holds interrupts off for one more instruction.
+    emu.step().unwrap();
+    assert_eq!(emu.eip, 0x7c03);
+
+    emu.step().unwrap();
+    assert_eq!(emu.eip, 0x1000);
+    assert!(!is_interrupt(&emu));
+    assert_eq!(get_memory16(&mut emu, 0x7bfa).unwrap(), 0x7c03);
+    assert_eq!(in_service(&mut emu, 0x20), 0x10);
+
+    irq4.set(false);
+    for _ in 0..3 {
+        emu.step().unwrap();
+    }
+    assert_eq!(emu.eip, 0x7c03);
+    assert!(is_interrupt(&emu));
+    assert_eq!(in_service(&mut emu, 0x20), 0);
+}
+
+#[test]
+fn irq_with_null_vector_does_not_block_lower_priorities() {
+    // sti; inc ax; inc ax; inc ax
+    let mut emu = real(&[0xfb, 0x40, 0x40, 0x40]);
+    // inc bx; mov al, 0x20; out 0x20, al; iret
+    emu.load(0x1000, &[0x43, 0xb0, 0x20, 0xe6, 0x20, 0xcf])
+        .unwrap();
+    set_vector(&mut emu, 0x0c, 0, 0x1000);
+    let irq0 = emu.irq_lines.line(0);
+    let irq4 = emu.irq_lines.line(4);
+    irq0.set(true);
+    irq4.set(true);
+
+    for _ in 0..8 {
+        emu.step().unwrap();
+    }
+    assert_eq!(emu.registers[EBX], 1);
+    assert_eq!(in_service(&mut emu, 0x20), 0);
+}
+
+#[test]
+fn masking_and_priority() {
+    let mut emu = real(&[]);
+    emu.io.write(0x21, 8, 0x08);
+    assert_eq!(emu.io.read(0x21, 8), 0x08);
+    emu.irq_lines.line(3).set(true);
+    emu.irq_lines.line(5).set(true);
+    emu.irq_lines.line(1).set(true);
+
+    let mut pic = emu.pic.borrow_mut();
+    assert_eq!(pic.acknowledge(), 0x09);
+    // IRQ5 waits until IRQ1 is ended; IRQ3 is masked.
+    assert!(!pic.has_interrupt());
+    pic.out8(0x20, 0x20);
+    assert_eq!(pic.acknowledge(), 0x0d);
+    pic.out8(0x20, 0x20);
+    assert!(!pic.has_interrupt());
+    pic.out8(0x21, 0x00);
+    assert_eq!(pic.acknowledge(), 0x0b);
+}
+
+#[test]
+fn slave_interrupts_cascade_through_irq2() {
+    let mut emu = real(&[]);
+    let irq9 = emu.irq_lines.line(9);
+    irq9.set(true);
+    assert_eq!(emu.pic.borrow_mut().acknowledge(), 0x71);
+    assert_eq!(in_service(&mut emu, 0x20), 0x04);
+    assert_eq!(in_service(&mut emu, 0xa0), 0x02);
+
+    // A second slave request waits for both controllers to be ended.
+    irq9.set(false);
+    emu.irq_lines.line(12).set(true);
+    assert!(!emu.pic.borrow_mut().has_interrupt());
+    emu.io.write(0xa0, 8, 0x20);
+    assert!(!emu.pic.borrow_mut().has_interrupt());
+    emu.io.write(0x20, 8, 0x20);
+    assert_eq!(emu.pic.borrow_mut().acknowledge(), 0x74);
+}
+
+#[test]
+fn hlt_waits_for_an_interrupt() {
+    // sti; hlt; cli; hlt
+    let mut emu = real(&[0xfb, 0xf4, 0xfa, 0xf4]);
+    install_bios(&mut emu).unwrap();
+    emu.step().unwrap();
+    assert!(emu.step().unwrap());
+    assert!(emu.halted);
+    assert!(emu.step().unwrap());
+    assert_eq!(emu.eip, 0x7c02);
+
+    let irq0 = emu.irq_lines.line(0);
+    irq0.set(true);
+    assert!(emu.step().unwrap());
+    assert!(!emu.halted);
+    assert_eq!(emu.sregs[CS].selector, BIOS_SEGMENT);
+    irq0.set(false);
+
+    // The BIOS handler ends the interrupt; with IF clear HLT stops the emulator.
+    while emu.sregs[CS].selector != 0 {
+        emu.step().unwrap();
+    }
+    assert_eq!(in_service(&mut emu, 0x20), 0);
+    emu.step().unwrap();
+    assert!(!emu.step().unwrap());
+}
+
+#[test]
+fn reprogrammed_vectors_with_auto_eoi_and_poll() {
+    let mut emu = real(&[]);
+    initialize(&mut emu, 0x20, 0x28, 0x03);
+    emu.irq_lines.line(14).set(true);
+    assert_eq!(emu.pic.borrow_mut().acknowledge(), 0x2e);
+    assert_eq!(in_service(&mut emu, 0x20), 0);
+    assert_eq!(in_service(&mut emu, 0xa0), 0);
+
+    // Polling reports and acknowledges the request without an INTA cycle.
+    initialize(&mut emu, 0x20, 0x28, 0x01);
+    emu.irq_lines.line(6).set(true);
+    emu.io.write(0x20, 8, 0x0c);
+    assert_eq!(emu.io.read(0x20, 8), 0x86);
+    assert_eq!(in_service(&mut emu, 0x20), 0x40);
+}
+
+#[test]
+fn specific_rotation_changes_priority() {
+    let mut emu = real(&[]);
+    // Set IRQ4 as the lowest priority, making IRQ5 the highest.
+    emu.io.write(0x20, 8, 0xc4);
+    emu.irq_lines.line(3).set(true);
+    emu.irq_lines.line(5).set(true);
+    let mut pic = emu.pic.borrow_mut();
+    assert_eq!(pic.acknowledge(), 0x0d);
+    // Rotate on specific EOI for IRQ5: IRQ6 is now the highest, IRQ3 next.
+    pic.out8(0x20, 0xe5);
+    assert_eq!(pic.acknowledge(), 0x0b);
+}
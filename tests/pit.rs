@@ -0,0 +1,155 @@
+mod common;
+
+use x86emu::pit::*;
+use x86emu::*;
+
+use common::*;
+
+fn pit() -> (Pit, IrqLines) {
+    let lines = IrqLines::new();
+    (Pit::new(lines.line(0)), lines)
+}
+
+/// Writes `control` to the mode register and a 16-bit `count` to the counter.
+fn program(pit: &mut Pit, control: u8, count: u16) {
+    pit.out8(0x43, control);
+    pit.out8(0x40 + (control >> 6) as u16, count as u8);
+    pit.out8(0x40 + (control >> 6) as u16, (count >> 8) as u8);
+}
+
+fn read_count(pit: &mut Pit, channel: u16) -> u16 {
+    pit.in8(0x40 + channel) as u16 | (pit.in8(0x40 + channel) as u16) << 8
+}
+
+fn tick(pit: &mut Pit, ticks: u64) {
+    pit.update(ticks * INSTRUCTIONS_PER_TICK);
+}
+
+/// Programs counter 0 as a rate generator and counts timer interrupts while the
+/// guest halts in a loop. Returns the count after `instructions` steps.
+fn count_ticks(instructions: usize) -> u32 {
+    let mut emu = real(&[
+        0xb0, 0x34, 0xe6, 0x43, // mov al, 0x34; out 0x43, al
+        0xb0, 0x64, 0xe6, 0x40, // mov al, 100; out 0x40, al
+        0xb0, 0x00, 0xe6, 0x40, // mov al, 0; out 0x40, al
+        0xfb, // sti
+        0xf4, 0xeb, 0xfd, // wait: hlt; jmp wait
+    ]);
+    // inc bx; mov al, 0x20; out 0x20, al; iret
+    emu.load(0x1000, &[0x43, 0xb0, 0x20, 0xe6, 0x20, 0xcf])
+        .unwrap();
+    set_vector(&mut emu, 0x08, 0, 0x1000);
+
+    for _ in 0..instructions {
+        assert!(emu.step().unwrap());
+    }
+    emu.registers[EBX]
+}
+
+#[test]
+fn timer_interrupts_follow_the_instruction_count() {
+    let ticks = count_ticks(2000);
+    assert!(ticks > 0);
+    assert_eq!(count_ticks(2000), ticks);
+    assert!(count_ticks(4000) > ticks);
+}
+
+#[test]
+fn fast_square_wave_interrupts_every_update() {
+    // A 10-tick square wave rises several times between device updates, and is
+    // often low again by the time the PIC samples IRQ0.
+    let mut emu = real(&[
+        0xb0, 0x36, 0xe6, 0x43, // mov al, 0x36; out 0x43, al
+        0xb0, 0x0a, 0xe6, 0x40, // mov al, 10; out 0x40, al
+        0xb0, 0x00, 0xe6, 0x40, // mov al, 0; out 0x40, al
+        0xfb, // sti
+        0xf4, 0xeb, 0xfd, // wait: hlt; jmp wait
+    ]);
+    // inc bx; mov al, 0x20; out 0x20, al; iret
+    emu.load(0x1000, &[0x43, 0xb0, 0x20, 0xe6, 0x20, 0xcf])
+        .unwrap();
+    set_vector(&mut emu, 0x08, 0, 0x1000);
+
+    while emu.tsc < 100 * 256 {
+        assert!(emu.step().unwrap());
+    }
+    assert_eq!(emu.registers[EBX], 100);
+}
+
+#[test]
+fn latched_count_is_held_until_read() {
+    let (mut pit, _) = pit();
+    program(&mut pit, 0x34, 1000);
+    tick(&mut pit, 300);
+    pit.out8(0x43, 0x00);
+    tick(&mut pit, 350);
+    assert_eq!(read_count(&mut pit, 0), 700);
+    assert_eq!(read_count(&mut pit, 0), 650);
+
+    // Counter 2 at its default count of 0x10000 reads as zero.
+    pit.out8(0x43, 0x80);
+    assert_eq!(read_count(&mut pit, 2), 0);
+}
+
+#[test]
+fn mode_0_raises_irq0_on_terminal_count() {
+    let (mut pit, lines) = pit();
+    program(&mut pit, 0x30, 10);
+    lines.take_edges();
+    tick(&mut pit, 9);
+    assert_eq!(lines.levels() & 1, 0);
+    tick(&mut pit, 10);
+    assert_eq!(lines.levels() & 1, 1);
+    assert_eq!(lines.take_edges(), 1);
+    tick(&mut pit, 20);
+    assert_eq!(lines.take_edges(), 0);
+}
+
+#[test]
+fn short_strobe_is_not_lost_between_updates() {
+    let (mut pit, lines) = pit();
+    program(&mut pit, 0x38, 10);
+    lines.take_edges();
+    tick(&mut pit, 64);
+    assert_eq!(lines.levels() & 1, 1);
+    assert_eq!(lines.take_edges(), 1);
+}
+
+#[test]
+fn read_back_status_and_bcd_counting() {
+    let (mut pit, _) = pit();
+    pit.out8(0x43, 0x71);
+    pit.out8(0x43, 0xe4);
+    assert_eq!(pit.in8(0x41), 0x71);
+
+    pit.out8(0x41, 0x00);
+    pit.out8(0x41, 0x01);
+    tick(&mut pit, 30);
+    pit.out8(0x43, 0xc4);
+    assert_eq!(pit.in8(0x41), 0x31);
+    assert_eq!(read_count(&mut pit, 1), 0x0070);
+}
+
+#[test]
+fn port_61_gates_counter_2_and_the_speaker() {
+    let (mut pit, _) = pit();
+    program(&mut pit, 0xb6, 1193);
+    assert_eq!(pit.speaker_frequency(), None);
+    assert_eq!(pit.in8(0x61) & 0x23, 0x20);
+
+    pit.out8(0x61, 0x03);
+    let frequency = pit.speaker_frequency().unwrap();
+    assert!((frequency - 1000.15).abs() < 0.01);
+    tick(&mut pit, 600);
+    assert_eq!(pit.in8(0x61) & 0x23, 0x03);
+    tick(&mut pit, 1193);
+    assert_eq!(pit.in8(0x61) & 0x23, 0x23);
+
+    // In mode 1 the gate's rising edge fires a one-shot.
+    program(&mut pit, 0xb2, 5);
+    pit.out8(0x61, 0x00);
+    pit.out8(0x61, 0x01);
+    assert_eq!(pit.in8(0x61) & 0x20, 0);
+    tick(&mut pit, 1198);
+    assert_eq!(pit.in8(0x61) & 0x20, 0x20);
+}
@@ -0,0 +1,88 @@
+mod common;
+
+use x86emu::function::*;
+use x86emu::*;
+
+use common::*;
+
+#[test]
+fn operand_size_prefix_in_32bit_code() {
+    // mov ax, 1; add ax, -1
+    let mut emu = flat(&[0x66, 0xb8, 0x01, 0x00, 0x66, 0x83, 0xc0, 0xff]);
+    emu.registers[EAX] = 0xabcd0000;
+    emu.step().unwrap();
+    assert_eq!(emu.registers[EAX], 0xabcd0001);
+    assert_eq!(emu.eip, 0x7c04);
+    emu.step().unwrap();
+    assert_eq!(emu.registers[EAX], 0xabcd0000);
+    assert!(is_zero(&mut emu));
+    assert!(is_carry(&mut emu));
+    assert_eq!(emu.eip, 0x7c08);
+}
+
+#[test]
+fn operand_size_prefix_in_real_mode() {
+    // mov eax, 0x12345678; push eax
+    let mut emu = real(&[0x66, 0xb8, 0x78, 0x56, 0x34, 0x12, 0x66, 0x50]);
+    emu.step().unwrap();
+    assert_eq!(emu.registers[EAX], 0x12345678);
+    emu.step().unwrap();
+    assert_eq!(emu.registers[ESP], 0x7bfc);
+    assert_eq!(get_memory32(&mut emu, 0x7bfc).unwrap(), 0x12345678);
+}
+
+#[test]
+fn address_size_prefix_in_real_mode() {
+    // mov ax, [ebx+ecx*4]
+    let mut emu = real(&[0x67, 0x8b, 0x04, 0x8b]);
+    load_segment(&mut emu, DS, 0x1000).unwrap();
+    emu.registers[EBX] = 0x100;
+    emu.registers[ECX] = 2;
+    set_memory16(&mut emu, 0x10108, 0x4242).unwrap();
+    emu.step().unwrap();
+    assert_eq!(emu.registers[EAX], 0x4242);
+    assert_eq!(emu.eip, 0x7c04);
+}
+
+#[test]
+fn address_size_prefix_in_32bit_code() {
+    // mov eax, [bx+di]
+    let mut emu = flat(&[0x67, 0x8b, 0x01]);
+    emu.registers[EBX] = 0xffff0100;
+    emu.registers[EDI] = 0x10;
+    set_memory32(&mut emu, 0x110, 0x600dcafe).unwrap();
+    emu.step().unwrap();
+    assert_eq!(emu.registers[EAX], 0x600dcafe);
+}
+
+#[test]
+fn segment_override_prefix() {
+    // mov ax, fs:[bx]; mov cx, ds:[bp]
+    let mut emu = real(&[0x64, 0x8b, 0x07, 0x3e, 0x8b, 0x4e, 0x00]);
+    load_segment(&mut emu, FS, 0x2000).unwrap();
+    load_segment(&mut emu, DS, 0x3000).unwrap();
+    load_segment(&mut emu, SS, 0x4000).unwrap();
+    emu.registers[EBX] = 0x10;
+    emu.registers[EBP] = 0x20;
+    set_memory16(&mut emu, 0x20010, 0x1111).unwrap();
+    set_memory16(&mut emu, 0x30020, 0x2222).unwrap();
+    set_memory16(&mut emu, 0x40020, 0x3333).unwrap();
+    emu.step().unwrap();
+    assert_eq!(emu.decode.segment, Some(FS));
+    assert_eq!(emu.registers[EAX], 0x1111);
+    emu.step().unwrap();
+    assert_eq!(emu.registers[ECX], 0x2222);
+}
+
+#[test]
+fn prefixes_are_recorded_per_instruction() {
+    // lock rep add [eax], ecx; add eax, ecx
+    let mut emu = flat(&[0xf0, 0xf3, 0x01, 0x08, 0x01, 0xc8]);
+    emu.step().unwrap();
+    assert!(emu.decode.lock);
+    assert_eq!(emu.decode.rep, Some(RepPrefix::Rep));
+    assert_eq!(emu.eip, 0x7c04);
+    emu.step().unwrap();
+    assert!(!emu.decode.lock);
+    assert_eq!(emu.decode.rep, None);
+}
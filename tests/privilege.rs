@@ -0,0 +1,230 @@
+mod common;
+
+use x86emu::function::*;
+use x86emu::*;
+
+use common::*;
+
+const GDT: u32 = 0x1000;
+const IDT: u32 = 0x2000;
+const TSS: u32 = 0x3000;
+const USER_CODE: u32 = 0x7d00;
+
+fn set_entry(emu: &mut Emulator, address: u32, low: u32, high: u32) {
+    set_memory32(emu, address, low).unwrap();
+    set_memory32(emu, address + 4, high).unwrap();
+}
+
+/// Ring 0 and ring 3 flat segments, a TSS at 0x28 and a call gate at 0x30. The
+/// IDT has a DPL 3 trap gate for INT 0x80, a DPL 0 gate for INT 0x81 and a #GP
+/// handler at 0x9100. The kernel loads TR and IRETs to 0x1b:0x7d00.
+fn ring3(user_code: &[u8]) -> Emulator {
+    let mut emu = Emulator::new(0x100000, 0x7c00, 0x7000);
+    emu.load(
+        0x7c00,
+        &[
+            0xb8, 0x28, 0x00, 0x00, 0x00, // mov eax, 0x28
+            0x0f, 0x00, 0xd8, // ltr ax
+            0x6a, 0x23, // push 0x23
+            0x68, 0x00, 0x60, 0x00, 0x00, // push 0x6000
+            0x68, 0x02, 0x02, 0x00, 0x00, // push 0x202
+            0x6a, 0x1b, // push 0x1b
+            0x68, 0x00, 0x7d, 0x00, 0x00, // push 0x7d00
+            0xcf, // iret
+        ],
+    )
+    .unwrap();
+    emu.load(USER_CODE as usize, user_code).unwrap();
+
+    set_entry(&mut emu, GDT + 0x08, 0x0000ffff, 0x00cf9a00);
+    set_entry(&mut emu, GDT + 0x10, 0x0000ffff, 0x00cf9200);
+    set_entry(&mut emu, GDT + 0x18, 0x0000ffff, 0x00cffa00);
+    set_entry(&mut emu, GDT + 0x20, 0x0000ffff, 0x00cff200);
+    set_entry(&mut emu, GDT + 0x28, (TSS << 16) | 0x67, 0x00008900);
+    set_entry(&mut emu, GDT + 0x30, 0x00089200, 0x0000ec01);
+    set_entry(&mut emu, IDT + 0x80 * 8, 0x00089000, 0x0000ef00);
+    set_entry(&mut emu, IDT + 0x81 * 8, 0x00089000, 0x00008e00);
+    set_entry(&mut emu, IDT + 13 * 8, 0x00089100, 0x00008e00);
+    set_memory32(&mut emu, TSS + 4, 0x8000).unwrap();
+    set_memory32(&mut emu, TSS + 8, 0x10).unwrap();
+
+    emu.cr[0] = CR0_PE | CR0_ET;
+    emu.gdtr = DescriptorTable {
+        base: GDT,
+        limit: 0x37,
+    };
+    emu.idtr = DescriptorTable {
+        base: IDT,
+        limit: 0x7ff,
+    };
+    load_segment(&mut emu, CS, 0x08).unwrap();
+    load_segment(&mut emu, SS, 0x10).unwrap();
+    load_segment(&mut emu, DS, 0x23).unwrap();
+    load_segment(&mut emu, ES, 0x10).unwrap();
+    step(&mut emu, 8);
+    emu
+}
+
+/// Asserts that the instruction at 0x7d00 raised #GP with `error_code` from ring 3.
+fn assert_gp(emu: &mut Emulator, error_code: u32) {
+    assert_eq!(emu.eip, 0x9100);
+    assert_eq!(emu.cpl, 0);
+    assert_eq!(emu.registers[ESP], 0x7fe8);
+    assert_eq!(get_memory32(emu, 0x7fe8).unwrap(), error_code);
+    assert_eq!(get_memory32(emu, 0x7fec).unwrap(), USER_CODE);
+}
+
+#[test]
+fn ltr_and_iret_enter_ring3() {
+    let mut emu = ring3(&[]);
+    assert_eq!(emu.tr.selector, 0x28);
+    assert_eq!(emu.tr.base, TSS);
+    // The TSS descriptor is now busy.
+    assert_eq!(get_memory8(&mut emu, GDT + 0x28 + 5).unwrap(), 0x8b);
+
+    assert_eq!(emu.eip, USER_CODE);
+    assert_eq!(emu.cpl, 3);
+    assert_eq!(emu.sregs[CS].selector, 0x1b);
+    assert_eq!(emu.sregs[SS].selector, 0x23);
+    assert_eq!(emu.registers[ESP], 0x6000);
+    assert_eq!(emu.sregs[DS].selector, 0x23);
+    // ES held a ring 0 data segment and is nulled on the way out.
+    assert_eq!(emu.sregs[ES].selector, 0);
+    assert!(segment_address(&emu, ES, 0, 8).is_err());
+}
+
+#[test]
+fn interrupt_from_ring3_uses_tss_stack() {
+    let mut emu = ring3(&[0xcd, 0x80]); // int 0x80
+    emu.load(0x9000, &[0xcf]).unwrap(); // iret
+
+    step(&mut emu, 1);
+    assert_eq!(emu.eip, 0x9000);
+    assert_eq!(emu.cpl, 0);
+    assert_eq!(emu.sregs[CS].selector, 0x08);
+    assert_eq!(emu.sregs[SS].selector, 0x10);
+    assert_eq!(emu.registers[ESP], 0x7fec);
+    assert_eq!(get_memory32(&mut emu, 0x7fec).unwrap(), 0x7d02);
+    assert_eq!(get_memory32(&mut emu, 0x7ff0).unwrap(), 0x1b);
+    assert_eq!(get_memory32(&mut emu, 0x7ff4).unwrap(), 0x202);
+    assert_eq!(get_memory32(&mut emu, 0x7ff8).unwrap(), 0x6000);
+    assert_eq!(get_memory32(&mut emu, 0x7ffc).unwrap(), 0x23);
+    // A trap gate leaves IF set.
+    assert!(is_interrupt(&emu));
+
+    step(&mut emu, 1);
+    assert_eq!(emu.eip, 0x7d02);
+    assert_eq!(emu.cpl, 3);
+    assert_eq!(emu.sregs[CS].selector, 0x1b);
+    assert_eq!(emu.sregs[SS].selector, 0x23);
+    assert_eq!(emu.registers[ESP], 0x6000);
+}
+
+#[test]
+fn fault_on_inner_stack_keeps_ring3_state() {
+    let mut emu = ring3(&[0xcd, 0x80]); // int 0x80
+    set_memory32(&mut emu, TSS + 4, 0x200000).unwrap();
+    match emu.step() {
+        Err(EmuError::MemoryFault { .. }) => {}
+        other => panic!("unexpected result: {:?}", other),
+    }
+    assert_eq!(emu.eip, USER_CODE);
+    assert_eq!(emu.cpl, 3);
+    assert_eq!(emu.sregs[SS].selector, 0x23);
+    assert_eq!(emu.registers[ESP], 0x6000);
+
+    // The same for an IRQ taken in ring 3.
+    set_entry(&mut emu, IDT + 0x08 * 8, 0x00089000, 0x00008e00);
+    emu.irq_lines.line(0).set(true);
+    match emu.step() {
+        Err(EmuError::MemoryFault { .. }) => {}
+        other => panic!("unexpected result: {:?}", other),
+    }
+    assert_eq!(emu.eip, USER_CODE);
+    assert_eq!(emu.cpl, 3);
+    assert_eq!(emu.sregs[SS].selector, 0x23);
+    assert_eq!(emu.registers[ESP], 0x6000);
+}
+
+#[test]
+fn privileged_instructions_fault_in_ring3() {
+    let codes: [&[u8]; 6] = [
+        &[0xfa],                                     // cli
+        &[0xf4],                                     // hlt
+        &[0x0f, 0x20, 0xc0],                         // mov eax, cr0
+        &[0x0f, 0x22, 0xd8],                         // mov cr3, eax
+        &[0xec],                                     // in al, dx
+        &[0x0f, 0x01, 0x15, 0x00, 0x00, 0x00, 0x00], // lgdt [0]
+    ];
+    for code in codes.iter() {
+        let mut emu = ring3(code);
+        step(&mut emu, 1);
+        assert_gp(&mut emu, 0);
+    }
+
+    // INT through a DPL 0 gate faults with the IDT entry as error code.
+    let mut emu = ring3(&[0xcd, 0x81]); // int 0x81
+    step(&mut emu, 1);
+    assert_gp(&mut emu, 0x81 * 8 + 2);
+}
+
+#[test]
+fn iopl_allows_cli_and_popf_cannot_raise_it() {
+    let mut emu = ring3(&[
+        0x68, 0x00, 0x30, 0x00, 0x00, // push 0x3000
+        0x9d, // popf
+        0xfa, // cli
+    ]);
+    step(&mut emu, 2);
+    // IOPL is unchanged and IF stays set.
+    assert_eq!(get_eflags(&mut emu) & IOPL_MASK, 0);
+    assert!(is_interrupt(&emu));
+
+    emu.eflags |= IOPL_MASK;
+    step(&mut emu, 1);
+    assert!(!is_interrupt(&emu));
+}
+
+#[test]
+fn call_gate_switches_stack_and_retf_returns() {
+    let mut emu = ring3(&[
+        0x68, 0x34, 0x12, 0x00, 0x00, // push 0x1234
+        0x9a, 0x00, 0x00, 0x00, 0x00, 0x33, 0x00, // call 0x33:0
+    ]);
+    emu.load(0x9200, &[0xca, 0x04, 0x00]).unwrap(); // retf 4
+
+    step(&mut emu, 2);
+    assert_eq!(emu.eip, 0x9200);
+    assert_eq!(emu.cpl, 0);
+    assert_eq!(emu.sregs[CS].selector, 0x08);
+    assert_eq!(emu.registers[ESP], 0x7fec);
+    assert_eq!(get_memory32(&mut emu, 0x7fec).unwrap(), 0x7d0c);
+    assert_eq!(get_memory32(&mut emu, 0x7ff0).unwrap(), 0x1b);
+    assert_eq!(get_memory32(&mut emu, 0x7ff4).unwrap(), 0x1234);
+    assert_eq!(get_memory32(&mut emu, 0x7ff8).unwrap(), 0x5ffc);
+    assert_eq!(get_memory32(&mut emu, 0x7ffc).unwrap(), 0x23);
+
+    step(&mut emu, 1);
+    assert_eq!(emu.eip, 0x7d0c);
+    assert_eq!(emu.cpl, 3);
+    assert_eq!(emu.sregs[SS].selector, 0x23);
+    assert_eq!(emu.registers[ESP], 0x6000);
+}
+
+#[test]
+fn ring3_cannot_reach_ring0_segments_directly() {
+    let mut emu = ring3(&[0xea, 0x00, 0x90, 0x00, 0x00, 0x08, 0x00]); // jmp 0x08:0x9000
+    assert_eq!(
+        load_segment(&mut emu, DS, 0x10),
+        Err(EmuError::Exception {
+            eip: emu.start_eip,
+            vector: 13,
+            error_code: Some(0x10)
+        })
+    );
+    assert!(load_segment(&mut emu, SS, 0x10).is_err());
+    load_segment(&mut emu, FS, 0x23).unwrap();
+
+    step(&mut emu, 1);
+    assert_gp(&mut emu, 0x08);
+}
@@ -0,0 +1,153 @@
+mod common;
+
+use x86emu::function::*;
+use x86emu::*;
+
+use common::*;
+
+const GDT: u32 = 0x1000;
+const IDT: u32 = 0x2000;
+const DATA_BASE: u32 = 0x20000;
+
+fn set_descriptor(emu: &mut Emulator, selector: u16, low: u32, high: u32) {
+    let address = GDT + selector as u32;
+    set_memory32(emu, address, low).unwrap();
+    set_memory32(emu, address + 4, high).unwrap();
+}
+
+/// A flat 32-bit code segment at 0x08 and a 4KB data segment at 0x10, with the
+/// GDT pseudo-descriptor at 0x0f00.
+fn setup_gdt(emu: &mut Emulator) {
+    set_descriptor(emu, 0x08, 0x0000ffff, 0x00cf9a00);
+    set_descriptor(emu, 0x10, 0x00000fff, 0x00409202);
+    set_descriptor(emu, 0x18, 0x0000ffff, 0x00cf1200);
+    set_memory16(emu, 0x0f00, 0x1f).unwrap();
+    set_memory32(emu, 0x0f02, GDT).unwrap();
+}
+
+/// Switches to protected mode and loads DS from the 4KB data segment.
+fn enter_protected_mode() -> Emulator {
+    let mut emu = real(&[
+        0x0f, 0x01, 0x16, 0x00, 0x0f, // lgdt [0x0f00]
+        0x0f, 0x20, 0xc0, // mov eax, cr0
+        0x66, 0x83, 0xc8, 0x01, // or eax, 1
+        0x0f, 0x22, 0xc0, // mov cr0, eax
+        0x66, 0xea, 0x17, 0x7c, 0x00, 0x00, 0x08, 0x00, // jmp dword 0x08:0x7c17
+        0xb8, 0x10, 0x00, 0x00, 0x00, // mov eax, 0x10
+        0x8e, 0xd8, // mov ds, eax
+        0x89, 0x05, 0x00, 0x10, 0x00, 0x00, // mov [0x1000], eax
+    ]);
+    setup_gdt(&mut emu);
+    step(&mut emu, 4);
+    assert!(is_protected_mode(&emu));
+    assert!(!emu.sregs[CS].big);
+    step(&mut emu, 3);
+    emu
+}
+
+#[test]
+fn far_jump_switches_to_32bit_code() {
+    let mut emu = enter_protected_mode();
+    assert_eq!(emu.gdtr.base, GDT);
+    assert_eq!(emu.gdtr.limit, 0x1f);
+    assert_eq!(emu.cr[0] & 0x11, 0x11);
+    assert_eq!(emu.sregs[CS].selector, 0x08);
+    assert!(emu.sregs[CS].big);
+    assert_eq!(emu.sregs[CS].limit, 0xffffffff);
+    assert_eq!(emu.eip, 0x7c1e);
+
+    assert_eq!(emu.sregs[DS].base, DATA_BASE);
+    assert_eq!(emu.sregs[DS].limit, 0xfff);
+    // The accessed bit is set in the GDT.
+    assert_eq!(get_memory8(&mut emu, GDT + 0x10 + 5).unwrap(), 0x93);
+}
+
+#[test]
+fn limit_violation_raises_gp_through_idt() {
+    let mut emu = enter_protected_mode();
+    // 32-bit interrupt gate for #GP at 0x08:0x9000
+    emu.idtr = DescriptorTable {
+        base: IDT,
+        limit: 0x7ff,
+    };
+    set_memory32(&mut emu, IDT + 13 * 8, 0x00089000).unwrap();
+    set_memory32(&mut emu, IDT + 13 * 8 + 4, 0x00008e00).unwrap();
+
+    step(&mut emu, 1);
+    assert_eq!(emu.eip, 0x9000);
+    assert_eq!(emu.registers[ESP], 0x7bf0);
+    assert_eq!(get_memory32(&mut emu, 0x7bf0).unwrap(), 0);
+    assert_eq!(get_memory32(&mut emu, 0x7bf4).unwrap(), 0x7c1e);
+    assert_eq!(get_memory32(&mut emu, 0x7bf8).unwrap(), 0x08);
+}
+
+#[test]
+fn limit_violation_without_idt_is_reported() {
+    let mut emu = enter_protected_mode();
+    emu.idtr.limit = 0;
+    assert_eq!(emu.step(), Err(EmuError::TripleFault { eip: 0x7c1e }));
+}
+
+#[test]
+fn bad_selectors_raise_gp() {
+    let mut emu = enter_protected_mode();
+    // Code segment into SS, null selector into SS, and a selector past the GDT limit.
+    assert_eq!(
+        load_segment(&mut emu, SS, 0x08),
+        Err(EmuError::Exception {
+            eip: emu.start_eip,
+            vector: 13,
+            error_code: Some(0x08)
+        })
+    );
+    assert!(load_segment(&mut emu, SS, 0x00).is_err());
+    assert!(load_segment(&mut emu, ES, 0x20).is_err());
+
+    // A null selector may be loaded into ES, but using it faults.
+    load_segment(&mut emu, ES, 0x00).unwrap();
+    assert!(segment_address(&emu, ES, 0, 8).is_err());
+
+    // Not-present data segment.
+    assert_eq!(
+        load_segment(&mut emu, ES, 0x18),
+        Err(EmuError::Exception {
+            eip: emu.start_eip,
+            vector: 11,
+            error_code: Some(0x18)
+        })
+    );
+}
+
+#[test]
+fn sgdt_sidt_and_mov_from_cr() {
+    // sgdt [0x500]; sidt [0x508]; mov ebx, cr0
+    let mut emu = emulator(&[
+        0x0f, 0x01, 0x05, 0x00, 0x05, 0x00, 0x00, 0x0f, 0x01, 0x0d, 0x08, 0x05, 0x00, 0x00, 0x0f,
+        0x20, 0xc3,
+    ]);
+    emu.gdtr = DescriptorTable {
+        base: 0x12345678,
+        limit: 0x27,
+    };
+    emu.cr[0] = 0x10;
+    step(&mut emu, 3);
+    assert_eq!(get_memory16(&mut emu, 0x500).unwrap(), 0x27);
+    assert_eq!(get_memory32(&mut emu, 0x502).unwrap(), 0x12345678);
+    assert_eq!(get_memory16(&mut emu, 0x508).unwrap(), 0x3ff);
+    assert_eq!(get_memory32(&mut emu, 0x50a).unwrap(), 0);
+    assert_eq!(emu.registers[EBX], 0x10);
+}
+
+#[test]
+fn real_mode_segment_limit_faults() {
+    // mov ax, [0xffff]
+    let mut emu = real(&[0x8b, 0x06, 0xff, 0xff]);
+    assert_eq!(
+        emu.step(),
+        Err(EmuError::Exception {
+            eip: 0x7c00,
+            vector: 13,
+            error_code: Some(0)
+        })
+    );
+}
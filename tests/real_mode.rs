@@ -0,0 +1,77 @@
+mod common;
+
+use x86emu::function::*;
+use x86emu::*;
+
+use common::*;
+
+#[test]
+fn mov_imm16_and_add_r16() {
+    // mov ax, 0xffff; mov cx, 1; add ax, cx
+    let mut emu = real(&[0xb8, 0xff, 0xff, 0xb9, 0x01, 0x00, 0x01, 0xc8]);
+    emu.registers[EAX] = 0x12340000;
+    step(&mut emu, 3);
+    assert_eq!(emu.registers[EAX], 0x12340000);
+    assert!(is_carry(&mut emu));
+    assert!(is_zero(&mut emu));
+    assert_eq!(emu.eip, 0x7c08);
+}
+
+#[test]
+fn segment_relative_modrm16() {
+    // mov ax, 0x1000; mov ds, ax; mov [bx+si+2], ax
+    let mut emu = real(&[0xb8, 0x00, 0x10, 0x8e, 0xd8, 0x89, 0x40, 0x02]);
+    emu.registers[EBX] = 0x10;
+    emu.registers[ESI] = 0x20;
+    step(&mut emu, 3);
+    assert_eq!(emu.sregs[DS].selector, 0x1000);
+    assert_eq!(get_memory16(&mut emu, 0x10032).unwrap(), 0x1000);
+}
+
+#[test]
+fn bp_addressing_uses_stack_segment() {
+    // mov ax, [bp+4]
+    let mut emu = real(&[0x8b, 0x46, 0x04]);
+    load_segment(&mut emu, SS, 0x2000).unwrap();
+    emu.registers[EBP] = 0x100;
+    set_memory16(&mut emu, 0x20104, 0xbeef).unwrap();
+    step(&mut emu, 1);
+    assert_eq!(emu.registers[EAX], 0xbeef);
+}
+
+#[test]
+fn push_pop_wrap_sp() {
+    // push ax; pop ds
+    let mut emu = real(&[0x50, 0x1f]);
+    emu.registers[EAX] = 0x07c0;
+    emu.registers[ESP] = 0;
+    step(&mut emu, 1);
+    assert_eq!(emu.registers[ESP], 0xfffe);
+    assert_eq!(get_memory16(&mut emu, 0xfffe).unwrap(), 0x07c0);
+    step(&mut emu, 1);
+    assert_eq!(emu.registers[ESP], 0);
+    assert_eq!(emu.sregs[DS].base, 0x7c00);
+}
+
+#[test]
+fn far_call_and_retf() {
+    // call 0x0800:0x0010; ...; at 0x8010: retf
+    let mut emu = real(&[0x9a, 0x10, 0x00, 0x00, 0x08]);
+    emu.load(0x8010, &[0xcb]).unwrap();
+    step(&mut emu, 1);
+    assert_eq!(emu.sregs[CS].selector, 0x0800);
+    assert_eq!(emu.eip, 0x10);
+    assert_eq!(emu.registers[ESP], 0x7bfc);
+    step(&mut emu, 1);
+    assert_eq!(emu.sregs[CS].selector, 0);
+    assert_eq!(emu.eip, 0x7c05);
+    assert_eq!(emu.registers[ESP], 0x7c00);
+}
+
+#[test]
+fn near_jump_wraps_ip() {
+    // jmp near +0x83fd, which wraps IP past 0xffff back to 0
+    let mut emu = real(&[0xe9, 0xfd, 0x83]);
+    step(&mut emu, 1);
+    assert_eq!(emu.eip, 0x0000);
+}
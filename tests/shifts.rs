@@ -0,0 +1,89 @@
+mod common;
+
+use x86emu::function::*;
+use x86emu::*;
+
+use common::*;
+
+#[test]
+fn shl_by_one_sets_carry_and_overflow() {
+    // shl eax, 1
+    let mut emu = emulator(&[0xd1, 0xe0]);
+    emu.registers[EAX] = 0x80000001;
+    emu.step().unwrap();
+    assert_eq!(emu.registers[EAX], 2);
+    assert!(is_carry(&mut emu));
+    assert!(is_overflow(&mut emu));
+    assert_eq!(emu.eip, ORIGIN + 2);
+}
+
+#[test]
+fn shr_and_sar_by_imm8() {
+    // shr al, 4; sar ecx, 4
+    let mut emu = emulator(&[0xc0, 0xe8, 0x04, 0xc1, 0xf9, 0x04]);
+    emu.registers[EAX] = 0x98;
+    emu.registers[ECX] = 0x80000018;
+    emu.step().unwrap();
+    assert_eq!(emu.registers[EAX], 0x09);
+    assert!(is_carry(&mut emu));
+    emu.step().unwrap();
+    assert_eq!(emu.registers[ECX], 0xf8000001);
+    assert!(is_carry(&mut emu));
+    assert!(is_sign(&mut emu));
+}
+
+#[test]
+fn count_is_masked_to_five_bits() {
+    // shl eax, cl with cl = 0x20 leaves eax and the flags alone
+    let mut emu = emulator(&[0xd3, 0xe0]);
+    emu.registers[EAX] = 0x1234;
+    emu.registers[ECX] = 0x20;
+    set_carry(&mut emu, true);
+    emu.step().unwrap();
+    assert_eq!(emu.registers[EAX], 0x1234);
+    assert!(is_carry(&mut emu));
+}
+
+#[test]
+fn rol_and_ror() {
+    // rol al, cl; ror eax, 1
+    let mut emu = emulator(&[0xd2, 0xc0, 0xd1, 0xc8]);
+    emu.registers[EAX] = 0x81;
+    emu.registers[ECX] = 9;
+    emu.step().unwrap();
+    assert_eq!(emu.registers[EAX], 0x03);
+    assert!(is_carry(&mut emu));
+    emu.step().unwrap();
+    assert_eq!(emu.registers[EAX], 0x80000001);
+    assert!(is_carry(&mut emu));
+}
+
+#[test]
+fn rcl_and_rcr_through_carry() {
+    // rcl al, 1; rcr al, 1
+    let mut emu = emulator(&[0xd0, 0xd0, 0xd0, 0xd8]);
+    emu.registers[EAX] = 0x80;
+    set_carry(&mut emu, true);
+    emu.step().unwrap();
+    assert_eq!(emu.registers[EAX], 0x01);
+    assert!(is_carry(&mut emu));
+    emu.step().unwrap();
+    assert_eq!(emu.registers[EAX], 0x80);
+    assert!(is_carry(&mut emu));
+}
+
+#[test]
+fn shld_and_shrd() {
+    // shld eax, edx, 8; shrd eax, edx, cl
+    let mut emu = emulator(&[0x0f, 0xa4, 0xd0, 0x08, 0x0f, 0xad, 0xd0]);
+    emu.registers[EAX] = 0x11223344;
+    emu.registers[EDX] = 0xaabbccdd;
+    emu.registers[ECX] = 4;
+    emu.step().unwrap();
+    assert_eq!(emu.registers[EAX], 0x223344aa);
+    assert!(is_carry(&mut emu));
+    emu.step().unwrap();
+    assert_eq!(emu.registers[EAX], 0xd223344a);
+    assert!(is_carry(&mut emu));
+    assert_eq!(emu.eip, ORIGIN + 7);
+}
@@ -0,0 +1,107 @@
+mod common;
+
+use x86emu::function::*;
+use x86emu::*;
+
+use common::*;
+
+#[test]
+fn rep_movsd_copies_and_counts() {
+    // rep movsd
+    let mut emu = flat(&[0xf3, 0xa5]);
+    emu.load(0x1000, &[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12])
+        .unwrap();
+    emu.registers[ESI] = 0x1000;
+    emu.registers[EDI] = 0x2000;
+    emu.registers[ECX] = 3;
+    emu.step().unwrap();
+    assert_eq!(
+        &emu.memory.ram()[0x2000..0x200c],
+        &emu.memory.ram()[0x1000..0x100c]
+    );
+    assert_eq!(emu.registers[ECX], 0);
+    assert_eq!(emu.registers[ESI], 0x100c);
+    assert_eq!(emu.registers[EDI], 0x200c);
+    assert_eq!(emu.eip, 0x7c02);
+}
+
+#[test]
+fn rep_stosb_with_zero_count_does_nothing() {
+    // rep stosb
+    let mut emu = flat(&[0xf3, 0xaa]);
+    emu.registers[EAX] = 0xff;
+    emu.registers[EDI] = 0x2000;
+    emu.step().unwrap();
+    assert_eq!(get_memory8(&mut emu, 0x2000).unwrap(), 0);
+    assert_eq!(emu.registers[EDI], 0x2000);
+}
+
+#[test]
+fn rep_stosb_backwards_with_direction_flag() {
+    // rep stosb
+    let mut emu = flat(&[0xf3, 0xaa]);
+    set_direction(&mut emu, true);
+    emu.registers[EAX] = 0xab;
+    emu.registers[EDI] = 0x2003;
+    emu.registers[ECX] = 4;
+    emu.step().unwrap();
+    assert_eq!(get_memory32(&mut emu, 0x2000).unwrap(), 0xabababab);
+    assert_eq!(emu.registers[EDI], 0x1fff);
+}
+
+#[test]
+fn repne_scasb_finds_terminator() {
+    // repne scasb
+    let mut emu = flat(&[0xf2, 0xae]);
+    emu.load(0x3000, b"hello\0").unwrap();
+    emu.registers[EAX] = 0;
+    emu.registers[EDI] = 0x3000;
+    emu.registers[ECX] = 0xffffffff;
+    emu.step().unwrap();
+    assert!(is_zero(&mut emu));
+    assert_eq!(emu.registers[EDI], 0x3006);
+    assert_eq!(!emu.registers[ECX] - 1, 5);
+}
+
+#[test]
+fn repe_cmpsb_stops_at_mismatch() {
+    // repe cmpsb
+    let mut emu = flat(&[0xf3, 0xa6]);
+    emu.load(0x1000, b"abcx").unwrap();
+    emu.load(0x2000, b"abcd").unwrap();
+    emu.registers[ESI] = 0x1000;
+    emu.registers[EDI] = 0x2000;
+    emu.registers[ECX] = 4;
+    emu.step().unwrap();
+    assert!(!is_zero(&mut emu));
+    assert!(!is_carry(&mut emu));
+    assert_eq!(emu.registers[ECX], 0);
+    assert_eq!(emu.registers[ESI], 0x1004);
+}
+
+#[test]
+fn lodsd_loads_accumulator() {
+    // lodsd
+    let mut emu = flat(&[0xad]);
+    set_memory32(&mut emu, 0x1000, 0x89abcdef).unwrap();
+    emu.registers[ESI] = 0x1000;
+    emu.step().unwrap();
+    assert_eq!(emu.registers[EAX], 0x89abcdef);
+    assert_eq!(emu.registers[ESI], 0x1004);
+}
+
+#[test]
+fn real_mode_movsb_uses_segments_and_cx() {
+    // rep movsb with an FS source override
+    let mut emu = real(&[0x64, 0xf3, 0xa4]);
+    emu.load(0x20010, b"xyz").unwrap();
+    load_segment(&mut emu, FS, 0x2000).unwrap();
+    load_segment(&mut emu, ES, 0x3000).unwrap();
+    emu.registers[ESI] = 0xffff0010;
+    emu.registers[EDI] = 0x20;
+    emu.registers[ECX] = 0xabcd0003;
+    emu.step().unwrap();
+    assert_eq!(&emu.memory.ram()[0x30020..0x30023], b"xyz");
+    assert_eq!(emu.registers[ECX], 0xabcd0000);
+    assert_eq!(emu.registers[ESI], 0xffff0013);
+}
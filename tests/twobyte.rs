@@ -0,0 +1,108 @@
+mod common;
+
+use x86emu::function::*;
+use x86emu::*;
+
+use common::*;
+
+#[test]
+fn near_jcc_taken_and_not_taken() {
+    // cmp eax, 1; jne near +0x100
+    let mut emu = emulator(&[0x83, 0xf8, 0x01, 0x0f, 0x85, 0x00, 0x01, 0x00, 0x00]);
+    emu.registers[EAX] = 1;
+    step(&mut emu, 2);
+    assert_eq!(emu.eip, ORIGIN + 9);
+
+    emu.registers[EAX] = 2;
+    emu.eip = ORIGIN;
+    step(&mut emu, 2);
+    assert_eq!(emu.eip, ORIGIN + 9 + 0x100);
+}
+
+#[test]
+fn setcc_and_cmovcc() {
+    // cmp eax, ecx; setb dl; cmova ebx, ecx; cmovb esi, ecx
+    let mut emu = emulator(&[
+        0x39, 0xc8, 0x0f, 0x92, 0xc2, 0x0f, 0x47, 0xd9, 0x0f, 0x42, 0xf1,
+    ]);
+    emu.registers[EAX] = 1;
+    emu.registers[ECX] = 2;
+    emu.registers[EDX] = 0xffffffff;
+    step(&mut emu, 4);
+    assert_eq!(emu.registers[EDX], 0xffffff01);
+    assert_eq!(emu.registers[EBX], 0);
+    assert_eq!(emu.registers[ESI], 2);
+}
+
+#[test]
+fn movzx_and_movsx() {
+    // movzx eax, cl; movsx ebx, cx
+    let mut emu = emulator(&[0x0f, 0xb6, 0xc1, 0x0f, 0xbf, 0xd9]);
+    emu.registers[ECX] = 0x1234f080;
+    step(&mut emu, 2);
+    assert_eq!(emu.registers[EAX], 0x80);
+    assert_eq!(emu.registers[EBX], 0xfffff080);
+}
+
+#[test]
+fn bit_test_instructions() {
+    // bts eax, 4; btr [0x100], ecx; bt eax, ecx
+    let mut emu = emulator(&[
+        0x0f, 0xba, 0xe8, 0x04, 0x0f, 0xb3, 0x0d, 0x00, 0x01, 0x00, 0x00, 0x0f, 0xa3, 0xc8,
+    ]);
+    emu.registers[ECX] = 33;
+    set_memory32(&mut emu, 0x104, 0x02).unwrap();
+    step(&mut emu, 1);
+    assert_eq!(emu.registers[EAX], 0x10);
+    assert!(!is_carry(&mut emu));
+    step(&mut emu, 1);
+    assert_eq!(get_memory32(&mut emu, 0x104).unwrap(), 0);
+    assert!(is_carry(&mut emu));
+    step(&mut emu, 1);
+    assert!(!is_carry(&mut emu));
+}
+
+#[test]
+fn bsf_bsr_and_bswap() {
+    // bsf eax, ecx; bsr ebx, ecx; bswap ecx
+    let mut emu = emulator(&[0x0f, 0xbc, 0xc1, 0x0f, 0xbd, 0xd9, 0x0f, 0xc9]);
+    emu.registers[ECX] = 0x00f0_0100;
+    step(&mut emu, 3);
+    assert_eq!(emu.registers[EAX], 8);
+    assert_eq!(emu.registers[EBX], 23);
+    assert_eq!(emu.registers[ECX], 0x0001_f000);
+}
+
+#[test]
+fn cpuid_and_rdtsc() {
+    // cpuid; rdtsc
+    let mut emu = emulator(&[0x0f, 0xa2, 0x0f, 0x31]);
+    step(&mut emu, 1);
+    assert_eq!(emu.registers[EAX], 1);
+    assert_eq!(emu.registers[EBX], 0x756e6547);
+    step(&mut emu, 1);
+    assert_eq!(emu.registers[EAX], 1);
+    assert_eq!(emu.registers[EDX], 0);
+}
+
+#[test]
+fn push_pop_fs_gs() {
+    // mov ax, 0x10; mov fs, ax; push fs; pop gs
+    let mut emu = emulator(&[0x66, 0xb8, 0x10, 0x00, 0x8e, 0xe0, 0x0f, 0xa0, 0x0f, 0xa9]);
+    step(&mut emu, 4);
+    assert_eq!(emu.sregs[GS].selector, 0x10);
+    assert_eq!(emu.registers[ESP], 0x7c00);
+    assert_eq!(emu.eip, ORIGIN + 10);
+}
+
+#[test]
+fn undefined_two_byte_opcode_reports_both_bytes() {
+    let mut emu = emulator(&[0x0f, 0xff]);
+    assert_eq!(
+        emu.step(),
+        Err(EmuError::UnimplementedOpcode {
+            eip: ORIGIN,
+            bytes: vec![0x0f, 0xff]
+        })
+    );
+}
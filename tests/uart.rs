@@ -0,0 +1,207 @@
+mod common;
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::Read;
+use std::os::unix::net::UnixListener;
+use std::rc::Rc;
+
+use x86emu::uart::*;
+use x86emu::*;
+
+use common::*;
+
+const COM1: u16 = 0x3f8;
+
+/// Hands out queued bytes and keeps everything transmitted.
+#[derive(Clone, Default)]
+struct Host {
+    input: Rc<RefCell<VecDeque<u8>>>,
+    output: Rc<RefCell<Vec<u8>>>,
+}
+
+impl SerialBackend for Host {
+    fn receive(&mut self) -> Option<u8> {
+        self.input.borrow_mut().pop_front()
+    }
+
+    fn transmit(&mut self, byte: u8) {
+        self.output.borrow_mut().push(byte);
+    }
+}
+
+fn uart() -> (Uart, Host, IrqLine) {
+    let lines = IrqLines::new();
+    let host = Host::default();
+    let uart = Uart::new(COM1, lines.line(4), Box::new(host.clone()));
+    (uart, host, lines.line(4))
+}
+
+#[test]
+fn divisor_latch_and_scratch() {
+    let (mut uart, _, _) = uart();
+    uart.out8(COM1 + 3, 0x83);
+    uart.out8(COM1, 0x01);
+    uart.out8(COM1 + 1, 0x00);
+    uart.out8(COM1 + 3, 0x03);
+    assert_eq!(uart.divisor(), 1);
+    assert_eq!(uart.in8(COM1 + 3), 0x03);
+
+    uart.out8(COM1 + 7, 0x5a);
+    assert_eq!(uart.in8(COM1 + 7), 0x5a);
+}
+
+#[test]
+fn guest_sends_through_backend() {
+    let (uart, host, _) = uart();
+    let mut emu = emulator(&[
+        0xba, 0xfd, 0x03, 0x00, 0x00, // mov edx, 0x3fd
+        0xec, // wait: in al, dx
+        0xa8, 0x20, // test al, 0x20
+        0x74, 0xfb, // jz wait
+        0xb0, 0x41, // mov al, 'A'
+        0x4a, 0x4a, 0x4a, 0x4a, 0x4a, // dec edx (x5)
+        0xee, // out dx, al
+    ]);
+    emu.io.register(COM1, 8, Box::new(uart));
+    for _ in 0..12 {
+        emu.step().unwrap();
+    }
+    assert_eq!(*host.output.borrow(), b"A");
+    assert_eq!(
+        emu.io.read(COM1 + 5, 8) as u8 & (LSR_THR_EMPTY | LSR_TRANSMITTER_EMPTY),
+        LSR_THR_EMPTY | LSR_TRANSMITTER_EMPTY
+    );
+}
+
+#[test]
+fn irq4_arrives_alongside_the_default_timer() {
+    // sti; wait: jmp wait
+    let mut emu = real(&[0xfb, 0xeb, 0xfe]);
+    // inc bx; mov al, 0x20; out 0x20, al; iret
+    emu.load(0x1000, &[0x43, 0xb0, 0x20, 0xe6, 0x20, 0xcf])
+        .unwrap();
+    set_vector(&mut emu, 0x0c, 0, 0x1000);
+    let uart = Uart::new(COM1, emu.irq_lines.line(4), Box::new(Host::default()));
+    emu.io.register(COM1, 8, Box::new(uart));
+
+    // The timer's IRQ0 fires with nothing at vector 8.
+    for _ in 0..1000 {
+        emu.step().unwrap();
+    }
+    emu.io.write(COM1 + 4, 8, 0x08);
+    emu.io.write(COM1 + 1, 8, 0x02);
+    for _ in 0..4 {
+        emu.step().unwrap();
+    }
+    assert_eq!(emu.registers[EBX], 1);
+}
+
+#[test]
+fn received_data_interrupt_follows_fifo_trigger_and_out2() {
+    let (mut uart, host, irq) = uart();
+    uart.out8(COM1 + 2, 0x41); // FIFO on, trigger at 4 bytes
+    uart.out8(COM1 + 1, 0x01);
+    host.input.borrow_mut().extend(b"abc");
+    uart.update(1);
+    assert_eq!(uart.in8(COM1 + 2), 0xc1);
+    assert_eq!(uart.in8(COM1 + 5) & LSR_DATA_READY, LSR_DATA_READY);
+
+    host.input.borrow_mut().push_back(b'd');
+    uart.update(2);
+    assert_eq!(uart.in8(COM1 + 2), 0xc4);
+    assert!(!irq.is_raised());
+    uart.out8(COM1 + 4, 0x08);
+    assert!(irq.is_raised());
+
+    let received: Vec<u8> = (0..4).map(|_| uart.in8(COM1)).collect();
+    assert_eq!(received, b"abcd");
+    assert!(!irq.is_raised());
+    assert_eq!(uart.in8(COM1 + 5) & LSR_DATA_READY, 0);
+}
+
+#[test]
+fn character_timeout_reports_stragglers() {
+    let (mut uart, host, irq) = uart();
+    uart.out8(COM1 + 2, 0xc1); // trigger at 14 bytes
+    uart.out8(COM1 + 1, 0x01);
+    uart.out8(COM1 + 4, 0x08);
+    host.input.borrow_mut().push_back(b'x');
+    uart.update(100);
+    assert!(!irq.is_raised());
+    uart.update(100 + 4096);
+    assert!(irq.is_raised());
+    assert_eq!(uart.in8(COM1 + 2), 0xcc);
+    assert_eq!(uart.in8(COM1), b'x');
+    assert!(!irq.is_raised());
+}
+
+#[test]
+fn thr_empty_interrupt_is_cleared_by_reading_iir() {
+    let (mut uart, host, irq) = uart();
+    uart.out8(COM1 + 4, 0x08);
+    uart.out8(COM1 + 1, 0x02);
+    assert!(irq.is_raised());
+    assert_eq!(uart.in8(COM1 + 2), 0x02);
+    assert!(!irq.is_raised());
+    assert_eq!(uart.in8(COM1 + 2), 0x01);
+
+    uart.out8(COM1, b'z');
+    assert!(irq.is_raised());
+    assert_eq!(*host.output.borrow(), b"z");
+}
+
+#[test]
+fn com1_and_com3_share_irq4() {
+    const COM3: u16 = 0x3e8;
+    let lines = IrqLines::new();
+    let mut com1 = Uart::new(COM1, lines.line(4), Box::new(Host::default()));
+    let mut com3 = Uart::new(COM3, lines.line(4), Box::new(Host::default()));
+    let irq = lines.line(4);
+
+    com1.out8(COM1 + 4, 0x08);
+    com1.out8(COM1 + 1, 0x02);
+    assert!(irq.is_raised());
+    // An idle UART on the same line leaves it raised.
+    com3.out8(COM3 + 7, 0x00);
+    com3.update(1);
+    assert!(irq.is_raised());
+
+    com3.out8(COM3 + 4, 0x08);
+    com3.out8(COM3 + 1, 0x02);
+    assert_eq!(com1.in8(COM1 + 2), 0x02);
+    assert!(irq.is_raised());
+    assert_eq!(com3.in8(COM3 + 2), 0x02);
+    assert!(!irq.is_raised());
+}
+
+#[test]
+fn loopback_echoes_data_and_modem_lines() {
+    let (mut uart, host, _) = uart();
+    uart.in8(COM1 + 6);
+    uart.out8(COM1 + 4, 0x10);
+    uart.out8(COM1, 0x55);
+    assert_eq!(uart.in8(COM1), 0x55);
+    assert!(host.output.borrow().is_empty());
+
+    // RTS and DTR come back as CTS and DSR.
+    uart.in8(COM1 + 6);
+    uart.out8(COM1 + 4, 0x13);
+    assert_eq!(uart.in8(COM1 + 6), 0x33);
+    assert_eq!(uart.in8(COM1 + 6), 0x30);
+}
+
+#[test]
+fn unix_socket_backend() {
+    let path = std::env::temp_dir().join(format!("x86emu-uart-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path).unwrap();
+    let mut backend = StreamBackend::unix_socket(&path).unwrap();
+    let (mut peer, _) = listener.accept().unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    backend.transmit(b'!');
+    let mut byte = [0];
+    peer.read_exact(&mut byte).unwrap();
+    assert_eq!(byte[0], b'!');
+}
@@ -0,0 +1,184 @@
+mod common;
+
+use x86emu::function::*;
+use x86emu::*;
+
+use common::*;
+
+#[test]
+fn sub_imm8_from_zero_wraps() {
+    // sub eax, 1
+    let mut emu = emulator(&[0x83, 0xe8, 0x01]);
+    step(&mut emu, 1);
+    assert_eq!(emu.registers[EAX], 0xffffffff);
+    assert!(is_carry(&mut emu));
+    assert!(is_sign(&mut emu));
+    assert!(!is_overflow(&mut emu));
+}
+
+#[test]
+fn add_imm8_wraps_to_zero() {
+    // add eax, 1
+    let mut emu = emulator(&[0x83, 0xc0, 0x01]);
+    emu.registers[EAX] = 0xffffffff;
+    step(&mut emu, 1);
+    assert_eq!(emu.registers[EAX], 0);
+    assert!(is_carry(&mut emu));
+    assert!(is_zero(&mut emu));
+}
+
+#[test]
+fn add_rm32_r32_signed_overflow() {
+    // add eax, ecx
+    let mut emu = emulator(&[0x01, 0xc8]);
+    emu.registers[EAX] = 0x7fffffff;
+    emu.registers[ECX] = 1;
+    step(&mut emu, 1);
+    assert_eq!(emu.registers[EAX], 0x80000000);
+    assert!(is_overflow(&mut emu));
+    assert!(!is_carry(&mut emu));
+}
+
+#[test]
+fn inc_r32_wraps() {
+    // inc edx
+    let mut emu = emulator(&[0x42]);
+    emu.registers[EDX] = 0xffffffff;
+    step(&mut emu, 1);
+    assert_eq!(emu.registers[EDX], 0);
+}
+
+#[test]
+fn inc_rm32_wraps() {
+    // inc dword [0x100]
+    let mut emu = emulator(&[0xff, 0x05, 0x00, 0x01, 0x00, 0x00]);
+    set_memory32(&mut emu, 0x100, 0xffffffff).unwrap();
+    step(&mut emu, 1);
+    assert_eq!(get_memory32(&mut emu, 0x100).unwrap(), 0);
+}
+
+#[test]
+fn cmp_eax_imm32_below() {
+    // cmp eax, 2
+    let mut emu = emulator(&[0x3d, 0x02, 0x00, 0x00, 0x00]);
+    emu.registers[EAX] = 1;
+    step(&mut emu, 1);
+    assert_eq!(emu.registers[EAX], 1);
+    assert!(is_carry(&mut emu));
+    assert!(is_sign(&mut emu));
+    assert!(!is_zero(&mut emu));
+}
+
+#[test]
+fn cmp_al_imm8_below() {
+    // cmp al, 0x10
+    let mut emu = emulator(&[0x3c, 0x10]);
+    emu.registers[EAX] = 0x0f;
+    step(&mut emu, 1);
+    assert!(is_carry(&mut emu));
+    assert!(is_sign(&mut emu));
+}
+
+#[test]
+fn call_rel32_backwards() {
+    // nop slot; call $-3
+    let mut emu = emulator(&[0x90, 0xe8, 0xfa, 0xff, 0xff, 0xff]);
+    emu.eip = ORIGIN + 1;
+    step(&mut emu, 1);
+    assert_eq!(emu.eip, ORIGIN);
+    assert_eq!(emu.registers[ESP], 0x7bfc);
+    assert_eq!(get_memory32(&mut emu, 0x7bfc).unwrap(), ORIGIN + 6);
+}
+
+#[test]
+fn short_and_near_jumps_backwards() {
+    // jmp short $-2 (to itself)
+    let mut emu = emulator(&[0xeb, 0xfe]);
+    step(&mut emu, 2);
+    assert_eq!(emu.eip, ORIGIN);
+
+    // jmp near $-5 (to itself)
+    let mut emu = emulator(&[0xe9, 0xfb, 0xff, 0xff, 0xff]);
+    step(&mut emu, 2);
+    assert_eq!(emu.eip, ORIGIN);
+}
+
+#[test]
+fn conditional_jump_backwards() {
+    // xor eax, eax; jz $-2
+    let mut emu = emulator(&[0x31, 0xc0, 0x74, 0xfc]);
+    step(&mut emu, 2);
+    assert_eq!(emu.eip, ORIGIN);
+}
+
+#[test]
+fn ret_to_zero_finishes() {
+    // push 0; ret
+    let mut emu = emulator(&[0x6a, 0x00, 0xc3]);
+    assert!(emu.step().unwrap());
+    assert!(!emu.step().unwrap());
+}
+
+#[test]
+fn eip_wraps_at_the_top_of_the_code_segment() {
+    // inc eax at the last byte of the address space
+    let mut emu = emulator(&[]);
+    emu.memory.add_ram(0xffff0000, 0x10000);
+    set_memory8(&mut emu, 0xffffffff, 0x40).unwrap();
+    emu.eip = 0xffffffff;
+    emu.step().unwrap();
+    assert_eq!(emu.eip, 0);
+    assert_eq!(emu.registers[EAX], 1);
+
+    // inc ax at IP 0xffff in a 16-bit code segment
+    let mut emu = real(&[]);
+    set_memory8(&mut emu, 0xffff, 0x40).unwrap();
+    emu.eip = 0xffff;
+    emu.step().unwrap();
+    assert_eq!(emu.eip, 0);
+    assert_eq!(emu.registers[EAX], 1);
+}
+
+#[test]
+fn push_imm8_sign_extends() {
+    // push -1
+    let mut emu = emulator(&[0x6a, 0xff]);
+    step(&mut emu, 1);
+    assert_eq!(get_memory32(&mut emu, 0x7bfc).unwrap(), 0xffffffff);
+}
+
+#[test]
+fn push_with_esp_zero_faults() {
+    // push eax
+    let mut emu = emulator(&[0x50]);
+    emu.registers[ESP] = 0;
+    match emu.step() {
+        Err(EmuError::MemoryFault { eip, address }) => {
+            assert_eq!(eip, ORIGIN);
+            assert_eq!(address, 0xfffffffc);
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+    assert_eq!(emu.eip, ORIGIN);
+    assert_eq!(emu.registers[ESP], 0);
+}
+
+#[test]
+fn negative_disp8_addressing() {
+    // mov eax, [ebx-4]
+    let mut emu = emulator(&[0x8b, 0x43, 0xfc]);
+    emu.registers[EBX] = 0x104;
+    set_memory32(&mut emu, 0x100, 0x12345678).unwrap();
+    step(&mut emu, 1);
+    assert_eq!(emu.registers[EAX], 0x12345678);
+}
+
+#[test]
+fn disp32_addressing_wraps() {
+    // mov eax, [ebx+0xfffffffc]
+    let mut emu = emulator(&[0x8b, 0x83, 0xfc, 0xff, 0xff, 0xff]);
+    emu.registers[EBX] = 0x104;
+    set_memory32(&mut emu, 0x100, 0xcafebabe).unwrap();
+    step(&mut emu, 1);
+    assert_eq!(emu.registers[EAX], 0xcafebabe);
+}